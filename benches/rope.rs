@@ -0,0 +1,140 @@
+// Rope benchmarks across document sizes. The editor is a binary crate, so
+// the modules under test are compiled in directly.
+//
+//     cargo bench --bench rope
+
+#[allow(dead_code)]
+#[path = "../src/encoding.rs"]
+mod encoding;
+#[allow(dead_code)]
+#[path = "../src/loader.rs"]
+mod loader;
+// Its unit tests come along too, without the test harness to run them
+#[allow(dead_code, unused_imports)]
+#[path = "../src/rope.rs"]
+mod rope;
+
+use std::hint::black_box;
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use loader::FileLoader;
+use rope::{Rope, RopeBuilder};
+
+const SIZES: &[(usize, &str)] = &[
+    (1024, "1KB"),
+    (100 * 1024, "100KB"),
+    (1024 * 1024, "1MB"),
+    (10 * 1024 * 1024, "10MB"),
+];
+
+// Source-like text: ASCII lines of varying length
+fn sample_text(len: usize) -> String {
+    let line = "    let value = compute(index, &mut state); // keep going\n";
+    let mut text = line.repeat(len / line.len() + 1);
+    text.truncate(len);
+    text
+}
+
+// Built the way a loaded file is, rather than as one giant leaf
+fn sample_rope(len: usize) -> Rope {
+    let text = sample_text(len);
+    let mut builder = RopeBuilder::new();
+    for chunk in text.as_bytes().chunks(64 * 1024) {
+        builder.push(String::from_utf8(chunk.to_vec()).unwrap());
+    }
+    builder.finish()
+}
+
+// Repeatable positions without pulling in a random number crate
+fn positions(len: usize, count: usize) -> Vec<usize> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    (0..count)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state % len as u64) as usize
+        })
+        .collect()
+}
+
+fn insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert_random");
+    for &(size, name) in SIZES {
+        let rope = sample_rope(size);
+        let positions = positions(size, 64);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            let mut i = 0;
+            b.iter(|| {
+                i = (i + 1) % positions.len();
+                black_box(rope.insert(positions[i], "inserted text"))
+            })
+        });
+    }
+    group.finish();
+}
+
+fn delete(c: &mut Criterion) {
+    let mut group = c.benchmark_group("delete_random");
+    for &(size, name) in SIZES {
+        let rope = sample_rope(size);
+        let positions = positions(size - 16, 64);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            let mut i = 0;
+            b.iter(|| {
+                i = (i + 1) % positions.len();
+                black_box(rope.delete(positions[i], 16))
+            })
+        });
+    }
+    group.finish();
+}
+
+fn char_at(c: &mut Criterion) {
+    let mut group = c.benchmark_group("char_at");
+    for &(size, name) in SIZES {
+        let rope = sample_rope(size);
+        let positions = positions(size, 64);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            let mut i = 0;
+            b.iter(|| {
+                i = (i + 1) % positions.len();
+                black_box(rope.char_at(positions[i]))
+            })
+        });
+    }
+    group.finish();
+}
+
+fn len(c: &mut Criterion) {
+    let mut group = c.benchmark_group("len");
+    for &(size, name) in SIZES {
+        let rope = sample_rope(size);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| b.iter(|| black_box(rope.len())));
+    }
+    group.finish();
+}
+
+fn load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load_file");
+    group.sample_size(10);
+    for &(size, name) in SIZES {
+        let path: PathBuf = std::env::temp_dir().join(format!("rope-bench-{}-{}.txt", std::process::id(), name));
+        std::fs::write(&path, sample_text(size)).unwrap();
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| {
+                let mut loader = FileLoader::open(&path, None).unwrap();
+                while !loader.step().unwrap() {}
+                black_box(loader.finish())
+            })
+        });
+        std::fs::remove_file(&path).unwrap();
+    }
+    group.finish();
+}
+
+criterion_group!(benches, insert, delete, char_at, len, load);
+criterion_main!(benches);
@@ -0,0 +1,82 @@
+// Bracket matching over plain text. Brackets inside strings or comments
+// aren't special-cased; this only counts nesting depth.
+
+// Byte index of the unmatched `open` before `index`, i.e. the one a `close`
+// inserted there would pair with
+pub fn find_opener(content: &str, index: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in content[..index].char_indices().rev() {
+        if c == close {
+            depth += 1;
+        } else if c == open {
+            if depth == 0 {
+                return Some(i);
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+// Byte index of the unmatched `close` at or after `index`
+pub fn find_closer(content: &str, index: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    for (i, c) in content[index..].char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            if depth == 0 {
+                return Some(index + i);
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+// The bracket at `index` and its match, as (opener, closer)
+pub fn pair_at(content: &str, index: usize) -> Option<(usize, usize)> {
+    let c = content[index..].chars().next()?;
+    PAIRS.iter().find_map(|&(open, close)| {
+        if c == open {
+            find_closer(content, index + 1, open, close).map(|closer| (index, closer))
+        } else if c == close {
+            find_opener(content, index, open, close).map(|opener| (opener, index))
+        } else {
+            None
+        }
+    })
+}
+
+// The innermost pair of any kind with its opener before `start` and its
+// closer at or after `end`
+pub fn enclosing(content: &str, start: usize, end: usize) -> Option<(usize, usize)> {
+    PAIRS
+        .iter()
+        .filter_map(|&(open, close)| {
+            let opener = find_opener(content, start, open, close)?;
+            let closer = find_closer(content, end, open, close)?;
+            // Only a pair if what's between them is balanced
+            (find_closer(content, opener + 1, open, close) == Some(closer)).then_some((opener, closer))
+        })
+        .max_by_key(|&(opener, _)| opener)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_are_found_from_either_bracket_or_inside() {
+        let text = "f(a[1], {b: (c)})";
+        assert_eq!(pair_at(text, 1), Some((1, 16)));
+        assert_eq!(pair_at(text, 16), Some((1, 16)));
+        assert_eq!(pair_at(text, 0), None);
+        assert_eq!(enclosing(text, 13, 13), Some((12, 14)));
+        assert_eq!(enclosing(text, 12, 15), Some((8, 15)));
+        assert_eq!(enclosing(text, 6, 6), Some((1, 16)));
+        assert_eq!(enclosing("a ( b", 4, 4), None);
+    }
+}
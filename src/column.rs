@@ -0,0 +1,113 @@
+// Screen columns of text. Most characters take one column; tabs run to the
+// next multiple of the tab width, East Asian wide characters take two and
+// combining marks none, since they're drawn over the character before.
+
+// Zero-width characters: combining marks, joiners and variation selectors.
+// Not the whole of Unicode's tables, but the ranges text actually uses.
+const ZERO_WIDTH: &[(char, char)] = &[
+    ('\u{0300}', '\u{036F}'),
+    ('\u{0483}', '\u{0489}'),
+    ('\u{0591}', '\u{05BD}'),
+    ('\u{0610}', '\u{061A}'),
+    ('\u{064B}', '\u{065F}'),
+    ('\u{0E31}', '\u{0E31}'),
+    ('\u{0E34}', '\u{0E3A}'),
+    ('\u{0E47}', '\u{0E4E}'),
+    ('\u{1AB0}', '\u{1AFF}'),
+    ('\u{1DC0}', '\u{1DFF}'),
+    ('\u{200B}', '\u{200F}'),
+    ('\u{20D0}', '\u{20FF}'),
+    ('\u{302A}', '\u{302F}'),
+    ('\u{3099}', '\u{309A}'),
+    ('\u{FE00}', '\u{FE0F}'),
+    ('\u{FE20}', '\u{FE2F}'),
+];
+
+// Characters that take two columns
+const WIDE: &[(char, char)] = &[
+    ('\u{1100}', '\u{115F}'), // Hangul Jamo
+    ('\u{2E80}', '\u{3029}'), // CJK radicals and punctuation
+    ('\u{3030}', '\u{3098}'),
+    ('\u{309B}', '\u{33FF}'), // Kana and CJK compatibility
+    ('\u{3400}', '\u{4DBF}'),
+    ('\u{4E00}', '\u{9FFF}'), // CJK ideographs
+    ('\u{A000}', '\u{A4CF}'), // Yi
+    ('\u{AC00}', '\u{D7A3}'), // Hangul syllables
+    ('\u{F900}', '\u{FAFF}'),
+    ('\u{FE30}', '\u{FE4F}'),
+    ('\u{FF00}', '\u{FF60}'), // Fullwidth forms
+    ('\u{FFE0}', '\u{FFE6}'),
+    ('\u{1F300}', '\u{1F64F}'), // Emoji
+    ('\u{1F900}', '\u{1F9FF}'),
+    ('\u{20000}', '\u{3FFFD}'),
+];
+
+fn in_ranges(ranges: &[(char, char)], c: char) -> bool {
+    ranges.iter().any(|&(first, last)| (first..=last).contains(&c))
+}
+
+pub fn is_zero_width(c: char) -> bool {
+    in_ranges(ZERO_WIDTH, c)
+}
+
+// Columns `c` takes, other than a tab
+pub fn char_width(c: char) -> usize {
+    if c.is_ascii() {
+        1
+    } else if is_zero_width(c) {
+        0
+    } else if in_ranges(WIDE, c) {
+        2
+    } else {
+        1
+    }
+}
+
+// Column reached after drawing `c` at `col`
+pub fn advance(col: usize, c: char, tab_width: usize) -> usize {
+    if c == '\t' {
+        col + tab_width - col % tab_width
+    } else {
+        col + char_width(c)
+    }
+}
+
+pub fn display_width(text: &str, tab_width: usize) -> usize {
+    text.chars().fold(0, |col, c| advance(col, c, tab_width))
+}
+
+// Screen column of the character `char_col` characters into `line`
+pub fn display_col(line: &str, char_col: usize, tab_width: usize) -> usize {
+    line.chars().take(char_col).fold(0, |col, c| advance(col, c, tab_width))
+}
+
+// Character `display_col` is the inverse of: the one drawn over screen
+// column `x`, or the end of the line when `x` is past it
+pub fn char_col_at(line: &str, x: usize, tab_width: usize) -> usize {
+    let mut col = 0;
+    for (i, c) in line.chars().enumerate() {
+        col = advance(col, c, tab_width);
+        if col > x {
+            return i;
+        }
+    }
+    line.chars().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wide_characters_take_two_columns_and_marks_none() {
+        assert_eq!(display_width("한글", 4), 4);
+        assert_eq!(display_width("e\u{301}t\u{E9}", 4), 3);
+        assert_eq!(display_width("a\t漢", 4), 6);
+        // Clicks on either half of a wide character land on it, and a
+        // combining mark goes with the character it's drawn over
+        assert_eq!(char_col_at("한글x", 1, 4), 0);
+        assert_eq!(char_col_at("한글x", 3, 4), 1);
+        assert_eq!(char_col_at("e\u{301}x", 1, 4), 2);
+        assert_eq!(display_col("한글x", 2, 4), 4);
+    }
+}
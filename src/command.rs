@@ -0,0 +1,414 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+
+// Everything the editor can do from a key binding or the command palette
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditorCommand {
+    Quit,
+    Save,
+    OpenMenu,
+    SetFilename,
+    NewBuffer,
+    ReopenClosed,
+    SaveSession,
+    RestoreSession,
+    RevertToSaved,
+    RevertToCheckpoint,
+    PreviousBranch,
+    NextBranch,
+    UndoTree,
+    OpenFileUnderCursor,
+    OpenCompanion,
+    InsertFile,
+    WriteSelection,
+    Undo,
+    Redo,
+    DeleteBackward,
+    DeleteWordBackward,
+    DeleteToLineStart,
+    DeleteToLineEnd,
+    MoveLeft,
+    MoveRight,
+    MoveUp,
+    MoveDown,
+    LineStart,
+    LineEnd,
+    WordLeft,
+    WordRight,
+    ParagraphUp,
+    ParagraphDown,
+    SelectParagraph,
+    SelectToBracket,
+    SelectInsideBrackets,
+    ExpandSelection,
+    ShrinkSelection,
+    ScrollUp,
+    ScrollDown,
+    CenterView,
+    CursorToTop,
+    CursorToBottom,
+    BufferStart,
+    BufferEnd,
+    JumpBack,
+    JumpForward,
+    SwapPosition,
+    SelectLeft,
+    SelectRight,
+    TransposeChars,
+    ReverseSelection,
+    SwapSelectionEnds,
+    SelectionLength,
+    AlignOn,
+    ToggleBlockComment,
+    DuplicateAndComment,
+    DeleteTrailingBlankLines,
+    InsertNewline,
+    InsertTab,
+    Dedent,
+    CommandPalette,
+    FindFile,
+    Search,
+    SearchNext,
+    ToggleSearchHighlight,
+    ClearSearchHighlight,
+    ToggleMinimap,
+    ToggleCentered,
+    ToggleDebugHud,
+    ToggleKeyLog,
+    WrapLine,
+    ToggleLineNumbers,
+    Settings,
+    Cancel,
+    Redraw,
+    ToggleOverwrite,
+    RepeatLastEdit,
+    RecordMacro,
+    ReplayMacro,
+    LineEndingsToLf,
+    LineEndingsToCrlf,
+}
+
+impl EditorCommand {
+    // Human-readable name, shown and searched in the command palette
+    pub fn name(self) -> &'static str {
+        match self {
+            EditorCommand::Quit => "Quit the editor",
+            EditorCommand::Save => "Save the file",
+            EditorCommand::OpenMenu => "Open the help menu",
+            EditorCommand::SetFilename => "Set filename",
+            EditorCommand::NewBuffer => "New buffer",
+            EditorCommand::ReopenClosed => "Reopen last closed file",
+            EditorCommand::SaveSession => "Save session to file",
+            EditorCommand::RestoreSession => "Restore session from file",
+            EditorCommand::RevertToSaved => "Revert to saved",
+            EditorCommand::RevertToCheckpoint => "Revert to last undo checkpoint",
+            EditorCommand::PreviousBranch => "Undo history: previous branch",
+            EditorCommand::NextBranch => "Undo history: next branch",
+            EditorCommand::UndoTree => "Undo history: show tree",
+            EditorCommand::OpenFileUnderCursor => "Open file under cursor",
+            EditorCommand::OpenCompanion => "Open companion file",
+            EditorCommand::InsertFile => "Insert file at cursor",
+            EditorCommand::WriteSelection => "Write selection (or buffer) to file",
+            EditorCommand::Undo => "Undo",
+            EditorCommand::Redo => "Redo",
+            EditorCommand::DeleteBackward => "Delete character",
+            EditorCommand::DeleteWordBackward => "Delete previous word",
+            EditorCommand::DeleteToLineStart => "Delete to start of line",
+            EditorCommand::DeleteToLineEnd => "Delete to end of line",
+            EditorCommand::MoveLeft => "Move cursor left",
+            EditorCommand::MoveRight => "Move cursor right",
+            EditorCommand::MoveUp => "Move cursor up",
+            EditorCommand::MoveDown => "Move cursor down",
+            EditorCommand::LineStart => "Go to start of line",
+            EditorCommand::LineEnd => "Go to end of line",
+            EditorCommand::WordLeft => "Move to previous word",
+            EditorCommand::WordRight => "Move to next word",
+            EditorCommand::ParagraphUp => "Move to previous blank line",
+            EditorCommand::ParagraphDown => "Move to next blank line",
+            EditorCommand::SelectParagraph => "Select paragraph",
+            EditorCommand::SelectToBracket => "Select to matching bracket",
+            EditorCommand::SelectInsideBrackets => "Select inside brackets",
+            EditorCommand::ExpandSelection => "Expand selection (word, line, brackets, buffer)",
+            EditorCommand::ShrinkSelection => "Shrink selection back",
+            EditorCommand::ScrollUp => "Scroll view up a line",
+            EditorCommand::ScrollDown => "Scroll view down a line",
+            EditorCommand::CenterView => "Center view on cursor line",
+            EditorCommand::CursorToTop => "Scroll cursor line to top",
+            EditorCommand::CursorToBottom => "Scroll cursor line to bottom",
+            EditorCommand::BufferStart => "Go to start of buffer",
+            EditorCommand::BufferEnd => "Go to end of buffer",
+            EditorCommand::JumpBack => "Jump back",
+            EditorCommand::JumpForward => "Jump forward",
+            EditorCommand::SwapPosition => "Go to previous position",
+            EditorCommand::SelectLeft => "Extend selection left",
+            EditorCommand::SelectRight => "Extend selection right",
+            EditorCommand::TransposeChars => "Transpose characters",
+            EditorCommand::ReverseSelection => "Reverse selected text",
+            EditorCommand::SwapSelectionEnds => "Move cursor to the other end of the selection",
+            EditorCommand::SelectionLength => "Selection length (characters, lines, bytes)",
+            EditorCommand::AlignOn => "Align selected lines on a character",
+            EditorCommand::ToggleBlockComment => "Toggle block comment around selection",
+            EditorCommand::DuplicateAndComment => "Duplicate line and comment out the original",
+            EditorCommand::DeleteTrailingBlankLines => "Delete trailing blank lines",
+            EditorCommand::InsertNewline => "Insert new line",
+            EditorCommand::InsertTab => "Indent",
+            EditorCommand::Dedent => "Dedent line",
+            EditorCommand::CommandPalette => "Command palette",
+            EditorCommand::FindFile => "Find and open file",
+            EditorCommand::Search => "Search",
+            EditorCommand::SearchNext => "Find next match",
+            EditorCommand::ToggleSearchHighlight => "Toggle search highlighting",
+            EditorCommand::ClearSearchHighlight => "Clear search highlighting",
+            EditorCommand::ToggleMinimap => "Toggle minimap",
+            EditorCommand::ToggleCentered => "Toggle centered layout",
+            EditorCommand::ToggleDebugHud => "Toggle debug HUD (render time, rope stats)",
+            EditorCommand::ToggleKeyLog => "Toggle keystroke display",
+            EditorCommand::WrapLine => "Wrap the current line",
+            EditorCommand::ToggleLineNumbers => "Cycle line numbers (off/absolute/relative/hybrid)",
+            EditorCommand::Settings => "Settings",
+            EditorCommand::Cancel => "Cancel",
+            EditorCommand::Redraw => "Redraw the screen",
+            EditorCommand::ToggleOverwrite => "Toggle insert/overwrite",
+            EditorCommand::RepeatLastEdit => "Repeat last edit",
+            EditorCommand::RecordMacro => "Start/stop recording a macro",
+            EditorCommand::ReplayMacro => "Replay a macro",
+            EditorCommand::LineEndingsToLf => "Convert line endings to LF",
+            EditorCommand::LineEndingsToCrlf => "Convert line endings to CRLF",
+        }
+    }
+
+    // Commands that change the text, and so are part of what
+    // RepeatLastEdit repeats
+    pub fn is_edit(self) -> bool {
+        matches!(
+            self,
+            EditorCommand::DeleteBackward
+                | EditorCommand::DeleteWordBackward
+                | EditorCommand::DeleteToLineStart
+                | EditorCommand::DeleteToLineEnd
+                | EditorCommand::InsertNewline
+                | EditorCommand::InsertTab
+                | EditorCommand::Dedent
+                | EditorCommand::TransposeChars
+                | EditorCommand::DuplicateAndComment
+                | EditorCommand::DeleteTrailingBlankLines
+        )
+    }
+
+    // Every other command drops the selection before it runs
+    pub fn keeps_selection(self) -> bool {
+        matches!(
+            self,
+            EditorCommand::SelectLeft
+                | EditorCommand::SelectRight
+                | EditorCommand::ReverseSelection
+                | EditorCommand::SwapSelectionEnds
+                | EditorCommand::AlignOn
+                | EditorCommand::SelectionLength
+                | EditorCommand::ToggleBlockComment
+                | EditorCommand::SelectParagraph
+                | EditorCommand::SelectToBracket
+                | EditorCommand::SelectInsideBrackets
+                | EditorCommand::ExpandSelection
+                | EditorCommand::ShrinkSelection
+                | EditorCommand::ScrollUp
+                | EditorCommand::ScrollDown
+                | EditorCommand::CenterView
+                | EditorCommand::CursorToTop
+                | EditorCommand::CursorToBottom
+                | EditorCommand::CommandPalette
+                | EditorCommand::WriteSelection
+                | EditorCommand::Cancel
+        )
+    }
+}
+
+// One step of an edit, with what it needs to run again. Typing, pasting and
+// editing commands in a row make up the last edit.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Edit {
+    Type(String),
+    Paste(String),
+    Command(EditorCommand),
+}
+
+const CTRL_SHIFT: KeyModifiers = KeyModifiers::CONTROL.union(KeyModifiers::SHIFT);
+const ALT_SHIFT: KeyModifiers = KeyModifiers::ALT.union(KeyModifiers::SHIFT);
+
+// Key binding table. Plain and Shift+character input isn't listed here; the
+// main loop inserts those directly.
+pub const KEYBINDINGS: &[(KeyCode, KeyModifiers, EditorCommand)] = &[
+    (KeyCode::Char('a'), KeyModifiers::CONTROL, EditorCommand::Quit),
+    (KeyCode::Char('s'), KeyModifiers::CONTROL, EditorCommand::Save),
+    (KeyCode::Char('m'), KeyModifiers::CONTROL, EditorCommand::OpenMenu),
+    (KeyCode::Char('x'), KeyModifiers::CONTROL, EditorCommand::SetFilename),
+    (KeyCode::Char('n'), CTRL_SHIFT, EditorCommand::NewBuffer),
+    (KeyCode::Char('N'), CTRL_SHIFT, EditorCommand::NewBuffer),
+    (KeyCode::Char('t'), CTRL_SHIFT, EditorCommand::ReopenClosed),
+    (KeyCode::Char('T'), CTRL_SHIFT, EditorCommand::ReopenClosed),
+    (KeyCode::Char('z'), KeyModifiers::CONTROL, EditorCommand::Undo),
+    (KeyCode::Char('y'), KeyModifiers::CONTROL, EditorCommand::Redo),
+    (KeyCode::Char('p'), KeyModifiers::CONTROL, EditorCommand::CommandPalette),
+    (KeyCode::Char('t'), KeyModifiers::CONTROL, EditorCommand::FindFile),
+    (KeyCode::Char('f'), KeyModifiers::CONTROL, EditorCommand::Search),
+    (KeyCode::F(3), KeyModifiers::NONE, EditorCommand::SearchNext),
+    (KeyCode::Esc, KeyModifiers::NONE, EditorCommand::Cancel),
+    (KeyCode::Char('l'), KeyModifiers::CONTROL, EditorCommand::Redraw),
+    (KeyCode::F(12), KeyModifiers::NONE, EditorCommand::ToggleDebugHud),
+    (KeyCode::Char('w'), KeyModifiers::ALT, EditorCommand::WrapLine),
+    (KeyCode::Insert, KeyModifiers::NONE, EditorCommand::ToggleOverwrite),
+    (KeyCode::Char('.'), KeyModifiers::ALT, EditorCommand::RepeatLastEdit),
+    (KeyCode::Char('r'), KeyModifiers::CONTROL, EditorCommand::RecordMacro),
+    (KeyCode::Char('e'), KeyModifiers::CONTROL, EditorCommand::ReplayMacro),
+    (KeyCode::Backspace, KeyModifiers::NONE, EditorCommand::DeleteBackward),
+    (KeyCode::Backspace, KeyModifiers::ALT, EditorCommand::DeleteWordBackward),
+    (KeyCode::Left, KeyModifiers::NONE, EditorCommand::MoveLeft),
+    (KeyCode::Right, KeyModifiers::NONE, EditorCommand::MoveRight),
+    (KeyCode::Up, KeyModifiers::NONE, EditorCommand::MoveUp),
+    (KeyCode::Down, KeyModifiers::NONE, EditorCommand::MoveDown),
+    (KeyCode::Left, KeyModifiers::CONTROL, EditorCommand::WordLeft),
+    (KeyCode::Right, KeyModifiers::CONTROL, EditorCommand::WordRight),
+    (KeyCode::Up, KeyModifiers::CONTROL, EditorCommand::ParagraphUp),
+    (KeyCode::Down, KeyModifiers::CONTROL, EditorCommand::ParagraphDown),
+    (KeyCode::Up, CTRL_SHIFT, EditorCommand::ScrollUp),
+    (KeyCode::Down, CTRL_SHIFT, EditorCommand::ScrollDown),
+    (KeyCode::Char('p'), KeyModifiers::ALT, EditorCommand::SelectParagraph),
+    (KeyCode::Char('m'), KeyModifiers::ALT, EditorCommand::SelectToBracket),
+    (KeyCode::Char('m'), ALT_SHIFT, EditorCommand::SelectInsideBrackets),
+    (KeyCode::Char('M'), ALT_SHIFT, EditorCommand::SelectInsideBrackets),
+    (KeyCode::Up, KeyModifiers::ALT, EditorCommand::ExpandSelection),
+    (KeyCode::Down, KeyModifiers::ALT, EditorCommand::ShrinkSelection),
+    (KeyCode::Home, KeyModifiers::CONTROL, EditorCommand::BufferStart),
+    (KeyCode::End, KeyModifiers::CONTROL, EditorCommand::BufferEnd),
+    (KeyCode::Left, KeyModifiers::ALT, EditorCommand::JumpBack),
+    (KeyCode::Right, KeyModifiers::ALT, EditorCommand::JumpForward),
+    // Terminals send Ctrl+^ as Ctrl+6
+    (KeyCode::Char('6'), KeyModifiers::CONTROL, EditorCommand::SwapPosition),
+    (KeyCode::Char('^'), KeyModifiers::CONTROL, EditorCommand::SwapPosition),
+    (KeyCode::Left, KeyModifiers::SHIFT, EditorCommand::SelectLeft),
+    (KeyCode::Right, KeyModifiers::SHIFT, EditorCommand::SelectRight),
+    (KeyCode::Char('t'), KeyModifiers::ALT, EditorCommand::TransposeChars),
+    (KeyCode::Char('r'), KeyModifiers::ALT, EditorCommand::ReverseSelection),
+    (KeyCode::Char(';'), KeyModifiers::ALT, EditorCommand::SwapSelectionEnds),
+    (KeyCode::Enter, KeyModifiers::NONE, EditorCommand::InsertNewline),
+    (KeyCode::Enter, KeyModifiers::CONTROL, EditorCommand::OpenFileUnderCursor),
+    (KeyCode::Char('o'), KeyModifiers::ALT, EditorCommand::OpenCompanion),
+    (KeyCode::Tab, KeyModifiers::NONE, EditorCommand::InsertTab),
+    (KeyCode::BackTab, KeyModifiers::NONE, EditorCommand::Dedent),
+];
+
+// Commands with no key of their own, only run from the palette
+const UNBOUND: &[EditorCommand] = &[
+    EditorCommand::InsertFile,
+    EditorCommand::WriteSelection,
+    EditorCommand::RevertToSaved,
+    EditorCommand::SaveSession,
+    EditorCommand::RestoreSession,
+    EditorCommand::RevertToCheckpoint,
+    EditorCommand::PreviousBranch,
+    EditorCommand::NextBranch,
+    EditorCommand::UndoTree,
+    EditorCommand::AlignOn,
+    EditorCommand::SelectionLength,
+    EditorCommand::CenterView,
+    EditorCommand::CursorToTop,
+    EditorCommand::CursorToBottom,
+    EditorCommand::ToggleBlockComment,
+    EditorCommand::DuplicateAndComment,
+    EditorCommand::DeleteTrailingBlankLines,
+    EditorCommand::ToggleKeyLog,
+    EditorCommand::LineEndingsToLf,
+    EditorCommand::LineEndingsToCrlf,
+    EditorCommand::ToggleSearchHighlight,
+    EditorCommand::ClearSearchHighlight,
+    EditorCommand::ToggleMinimap,
+    EditorCommand::ToggleCentered,
+    EditorCommand::ToggleLineNumbers,
+    EditorCommand::Settings,
+];
+
+// Which key binding table is laid over KEYBINDINGS
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Keymap {
+    Default,
+    Readline,
+}
+
+impl Keymap {
+    pub fn parse(value: &str) -> Option<Keymap> {
+        match value {
+            "default" => Some(Keymap::Default),
+            "readline" => Some(Keymap::Readline),
+            _ => None,
+        }
+    }
+}
+
+// Bash and Emacs style editing keys, for `keymap = readline`. Ctrl+A and
+// Ctrl+E take over from Quit and ReplayMacro, which move to Ctrl+Q and
+// Alt+E.
+pub const READLINE_BINDINGS: &[(KeyCode, KeyModifiers, EditorCommand)] = &[
+    (KeyCode::Char('a'), KeyModifiers::CONTROL, EditorCommand::LineStart),
+    (KeyCode::Char('e'), KeyModifiers::CONTROL, EditorCommand::LineEnd),
+    (KeyCode::Char('w'), KeyModifiers::CONTROL, EditorCommand::DeleteWordBackward),
+    (KeyCode::Char('u'), KeyModifiers::CONTROL, EditorCommand::DeleteToLineStart),
+    (KeyCode::Char('k'), KeyModifiers::CONTROL, EditorCommand::DeleteToLineEnd),
+    (KeyCode::Char('b'), KeyModifiers::ALT, EditorCommand::WordLeft),
+    (KeyCode::Char('f'), KeyModifiers::ALT, EditorCommand::WordRight),
+    (KeyCode::Char('q'), KeyModifiers::CONTROL, EditorCommand::Quit),
+    (KeyCode::Char('e'), KeyModifiers::ALT, EditorCommand::ReplayMacro),
+];
+
+pub fn lookup(code: KeyCode, modifiers: KeyModifiers, keymap: Keymap) -> Option<EditorCommand> {
+    let overlay = match keymap {
+        Keymap::Default => &[][..],
+        Keymap::Readline => READLINE_BINDINGS,
+    };
+    let find = |modifiers: KeyModifiers| {
+        overlay
+            .iter()
+            .chain(KEYBINDINGS)
+            .find(|(c, m, _)| *c == code && *m == modifiers)
+            .map(|&(_, _, cmd)| cmd)
+    };
+    // Non-character keys fall back to their unmodified binding, so e.g.
+    // Shift+Backspace still deletes.
+    match code {
+        KeyCode::Char(_) => find(modifiers),
+        _ => find(modifiers).or_else(|| find(KeyModifiers::NONE)),
+    }
+}
+
+// Every command once, bound ones in table order, for the palette
+pub fn all_commands() -> Vec<EditorCommand> {
+    let mut commands = Vec::new();
+    let bound = KEYBINDINGS.iter().chain(READLINE_BINDINGS).map(|(_, _, cmd)| cmd);
+    for &cmd in bound.chain(UNBOUND) {
+        if !commands.contains(&cmd) {
+            commands.push(cmd);
+        }
+    }
+    commands
+}
+
+// A key as the README writes it, e.g. Ctrl+Shift+Z, Alt+Up or F3. Shift
+// on its own with a character is just the character it typed, and
+// Shift+Tab arrives as a key of its own.
+pub fn key_name(code: KeyCode, modifiers: KeyModifiers) -> String {
+    let mut name = String::new();
+    let typed = matches!(code, KeyCode::Char(_)) && (modifiers - KeyModifiers::SHIFT).is_empty();
+    for (flag, label) in [(KeyModifiers::CONTROL, "Ctrl+"), (KeyModifiers::ALT, "Alt+"), (KeyModifiers::SHIFT, "Shift+")] {
+        let implied = typed || code == KeyCode::BackTab;
+        if modifiers.contains(flag) && !(implied && flag == KeyModifiers::SHIFT) {
+            name.push_str(label);
+        }
+    }
+    match code {
+        KeyCode::Char(' ') => name.push_str("Space"),
+        KeyCode::Char(c) if typed => name.push(c),
+        KeyCode::Char(c) => name.extend(c.to_uppercase()),
+        KeyCode::BackTab => name.push_str("Shift+Tab"),
+        KeyCode::F(n) => name.push_str(&format!("F{}", n)),
+        code => name.push_str(&format!("{:?}", code)),
+    }
+    name
+}
@@ -0,0 +1,122 @@
+// Comments: which delimiters a file type uses, and wrapping text in them or
+// taking them off again
+
+use std::ops::Range;
+use std::path::Path;
+
+// Opening and closing delimiters, by file extension
+const BLOCK_COMMENTS: &[(&[&str], &str, &str)] = &[
+    (
+        &[
+            "c", "h", "cc", "cpp", "hpp", "cs", "css", "go", "java", "js", "jsx", "kt", "less", "php", "rs",
+            "scss", "swift", "ts", "tsx",
+        ],
+        "/*",
+        "*/",
+    ),
+    (&["htm", "html", "md", "svg", "vue", "xml"], "<!--", "-->"),
+    (&["hs"], "{-", "-}"),
+    (&["ml", "mli"], "(*", "*)"),
+    (&["lua"], "--[[", "]]"),
+];
+
+// Prefixes of comments that run to the end of the line, by file extension
+const LINE_COMMENTS: &[(&[&str], &str)] = &[
+    (
+        &[
+            "c", "h", "cc", "cpp", "hpp", "cs", "go", "java", "js", "jsx", "kt", "less", "php", "rs", "scss",
+            "swift", "ts", "tsx",
+        ],
+        "//",
+    ),
+    (&["py", "sh", "bash", "rb", "pl", "toml", "yaml", "yml", "conf", "r"], "#"),
+    (&["hs", "lua", "sql"], "--"),
+    (&["el", "lisp", "clj", "scm"], ";"),
+    (&["tex", "erl"], "%"),
+    (&["vim"], "\""),
+];
+
+fn line_prefix(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    LINE_COMMENTS.iter().find(|(exts, _)| exts.contains(&ext.as_str())).map(|&(_, prefix)| prefix)
+}
+
+// `line` commented out after its indentation, with a line comment where the
+// file type has them and a block comment otherwise
+pub fn comment_line(line: &str, path: &Path) -> Option<String> {
+    let text = line.trim_start_matches([' ', '\t']);
+    let indent = &line[..line.len() - text.len()];
+    match line_prefix(path) {
+        Some(prefix) => Some(format!("{}{} {}", indent, prefix, text)),
+        None => delimiters(path).map(|(open, close)| format!("{}{} {} {}", indent, open, text, close)),
+    }
+}
+
+pub fn delimiters(path: &Path) -> Option<(&'static str, &'static str)> {
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    BLOCK_COMMENTS
+        .iter()
+        .find(|(exts, _, _)| exts.contains(&ext.as_str()))
+        .map(|&(_, open, close)| (open, close))
+}
+
+// The edit that toggles a comment around `range` of `content`: the range
+// to replace, what replaces it, and where the commented (or uncommented)
+// text ends up. Already wrapped means the range itself starts and ends
+// with the delimiters, or they sit just outside it.
+pub fn toggle(content: &str, range: Range<usize>, open: &str, close: &str) -> (Range<usize>, String, Range<usize>) {
+    let text = &content[range.clone()];
+    if let Some(inner) = text.strip_prefix(open).and_then(|t| t.strip_suffix(close)) {
+        let inner = unpad(inner);
+        return (range.clone(), inner.to_string(), range.start..range.start + inner.len());
+    }
+    let before = &content[..range.start];
+    let after = &content[range.end..];
+    let open_at = before.strip_suffix(' ').unwrap_or(before).strip_suffix(open).map(str::len);
+    let close_len = after.strip_prefix(' ').unwrap_or(after).strip_prefix(close).map(|rest| after.len() - rest.len());
+    if let (Some(start), Some(close_len)) = (open_at, close_len) {
+        return (start..range.end + close_len, text.to_string(), start..start + text.len());
+    }
+    let wrapped = format!("{} {} {}", open, text, close);
+    let inner = range.start + open.len() + 1;
+    (range.clone(), wrapped, inner..inner + text.len())
+}
+
+// One space just inside each delimiter is part of the comment's dressing
+fn unpad(inner: &str) -> &str {
+    match inner.strip_prefix(' ').and_then(|t| t.strip_suffix(' ')) {
+        Some(unpadded) => unpadded,
+        None => inner,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn comments_wrap_and_unwrap() {
+        assert_eq!(delimiters(Path::new("page.HTML")), Some(("<!--", "-->")));
+        assert_eq!(delimiters(Path::new("notes.txt")), None);
+
+        let content = "a = b + c;";
+        let (range, text, inner) = toggle(content, 4..9, "/*", "*/");
+        assert_eq!((range, text.as_str(), inner), (4..9, "/* b + c */", 7..12));
+
+        // With the comment's text selected, the delimiters around it go
+        let content = "a = /* b + c */;";
+        let (range, text, inner) = toggle(content, 7..12, "/*", "*/");
+        assert_eq!((range, text.as_str(), inner), (4..15, "b + c", 4..9));
+        // ...and the same with the whole comment selected
+        let (range, text, inner) = toggle(content, 4..15, "/*", "*/");
+        assert_eq!((range, text.as_str(), inner), (4..15, "b + c", 4..9));
+    }
+
+    #[test]
+    fn lines_are_commented_after_their_indentation() {
+        assert_eq!(comment_line("    let x = 1;", Path::new("a.rs")).as_deref(), Some("    // let x = 1;"));
+        assert_eq!(comment_line("\tx = 1", Path::new("a.py")).as_deref(), Some("\t# x = 1"));
+        assert_eq!(comment_line("  <p>", Path::new("a.html")).as_deref(), Some("  <!-- <p> -->"));
+        assert_eq!(comment_line("x", Path::new("notes.txt")), None);
+    }
+}
@@ -0,0 +1,91 @@
+// Companion files: the header for a source file, the tests for a module.
+// A rule is two path patterns with one `*` each, like `*.h *.c` or
+// `src/*.rs tests/*.rs`, and works both ways. A pattern matches the end of
+// a path, starting at a directory boundary, and `*` stands for any run of
+// characters other than `/`.
+
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Rule {
+    pub from: String,
+    pub to: String,
+}
+
+impl Rule {
+    // `from to`, both with exactly one `*`
+    pub fn parse(value: &str) -> Option<Rule> {
+        let mut patterns = value.split_whitespace();
+        let (from, to) = (patterns.next()?, patterns.next()?);
+        let valid = |pattern: &str| pattern.matches('*').count() == 1;
+        (patterns.next().is_none() && valid(from) && valid(to)).then(|| Rule {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+    }
+}
+
+// Tried after the user's own rules
+pub fn default_rules() -> Vec<Rule> {
+    [("*.h", "*.c"), ("*.h", "*.cpp"), ("*.hpp", "*.cpp"), ("*.hh", "*.cc"), ("src/*.rs", "tests/*.rs")]
+        .iter()
+        .map(|&(from, to)| Rule {
+            from: from.to_string(),
+            to: to.to_string(),
+        })
+        .collect()
+}
+
+// Every companion the rules give for `path`, in rule order, without repeats
+pub fn candidates(path: &Path, rules: &[Rule]) -> Vec<PathBuf> {
+    let path = path.to_string_lossy();
+    let mut found: Vec<PathBuf> = Vec::new();
+    for rule in rules {
+        let forward = rewrite(&path, &rule.from, &rule.to);
+        let backward = rewrite(&path, &rule.to, &rule.from);
+        for companion in forward.into_iter().chain(backward) {
+            if !found.contains(&companion) {
+                found.push(companion);
+            }
+        }
+    }
+    found
+}
+
+// `path` with the part matching `from` swapped for `to`, the `*` carried
+// across
+fn rewrite(path: &str, from: &str, to: &str) -> Option<PathBuf> {
+    let (prefix, suffix) = from.split_once('*')?;
+    let boundaries = std::iter::once(0).chain(path.match_indices('/').map(|(i, _)| i + 1));
+    for start in boundaries {
+        let Some(stem) = path[start..].strip_prefix(prefix).and_then(|rest| rest.strip_suffix(suffix)) else {
+            continue;
+        };
+        if stem.is_empty() || stem.contains('/') {
+            continue;
+        }
+        return Some(PathBuf::from(format!("{}{}", &path[..start], to.replacen('*', stem, 1))));
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rules_rewrite_paths_both_ways() {
+        assert_eq!(Rule::parse("*.h *.c").unwrap().to, "*.c");
+        assert_eq!(Rule::parse("*.h"), None);
+        assert_eq!(Rule::parse("*.h c"), None);
+
+        let rules = default_rules();
+        assert_eq!(candidates(Path::new("lib/list.h"), &rules), [PathBuf::from("lib/list.c"), PathBuf::from("lib/list.cpp")]);
+        assert_eq!(candidates(Path::new("lib/list.cpp"), &rules), [PathBuf::from("lib/list.h"), PathBuf::from("lib/list.hpp")]);
+        assert_eq!(candidates(Path::new("/work/app/src/rope.rs"), &rules), [PathBuf::from("/work/app/tests/rope.rs")]);
+        assert_eq!(candidates(Path::new("tests/rope.rs"), &rules), [PathBuf::from("src/rope.rs")]);
+        // `*` stops at directories, and the match starts at one
+        assert!(candidates(Path::new("src/deep/rope.rs"), &rules).is_empty());
+        assert!(candidates(Path::new("mysrc/rope.rs"), &rules).is_empty());
+    }
+}
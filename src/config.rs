@@ -0,0 +1,610 @@
+// User configuration, read from `~/.config/rope-editor/config`.
+//
+// The format is `key = value` lines with optional per-extension sections:
+//
+//     tab_width = 4
+//     indent_style = spaces
+//     detect_indent = true
+//     editorconfig = true
+//     expand_brackets = false
+//     fallback_encoding = windows-1252
+//     large_file_size = 100
+//     undo_checkpoints = 50
+//     undo_share_size = 4096
+//     cursor_style = bar
+//     cursor_blink = false
+//     hide_cursor_after = 5
+//     normalize_paste = true
+//     trim_paste = false
+//     show_line_endings = false
+//     scrolloff = 3
+//     show_eof_markers = true
+//     scroll_past_end = true
+//     indent_guides = true
+//     wrap_motion = false
+//     smart_backspace = true
+//     trim_on_enter = true
+//     open_line_at_eof = false
+//     soft_tab_motion = true
+//     remember_position = false
+//     follow_symlinks = false
+//     highlight_search = false
+//     minimap = true
+//     centered = true
+//     center_width = 72
+//     line_numbers = relative
+//     visual_bell = false
+//     audible_bell = true
+//     mouse = true
+//     keymap = readline
+//     line_length_limit = 100
+//     line_length_color = dark_red
+//     companion = lib/*.js spec/*.test.js
+//
+//     [py]
+//     tab_width = 4
+//     check_mixed_indent = true
+//     trim_trailing_whitespace = true
+//
+//     [go]
+//     indent_style = tabs
+//
+//     [rs]
+//     dedent_closing_brace = true
+//
+// Blank lines and lines starting with `#` are ignored, as are unknown keys
+// and values that don't parse, so a bad line never stops the editor opening.
+//
+// With `editorconfig` on, a project's `.editorconfig` files (see
+// editorconfig.rs) override these for the files under them.
+
+use crossterm::style::Color;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::command::Keymap;
+use crate::companion::{self, Rule};
+use crate::encoding::Encoding;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndentStyle {
+    Spaces,
+    Tabs,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndentSettings {
+    pub tab_width: usize,
+    pub style: IndentStyle,
+}
+
+impl Default for IndentSettings {
+    fn default() -> Self {
+        IndentSettings {
+            tab_width: 4,
+            style: IndentStyle::Spaces,
+        }
+    }
+}
+
+// Shape of the terminal cursor
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CursorShape {
+    Block,
+    Bar,
+    Underline,
+}
+
+// What the line number column shows, as in vim: `number`, `relativenumber`
+// or both (the cursor's own line absolute, the others as distances)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineNumbers {
+    Off,
+    Absolute,
+    Relative,
+    Hybrid,
+}
+
+impl LineNumbers {
+    pub fn name(self) -> &'static str {
+        match self {
+            LineNumbers::Off => "off",
+            LineNumbers::Absolute => "absolute",
+            LineNumbers::Relative => "relative",
+            LineNumbers::Hybrid => "hybrid",
+        }
+    }
+
+    // The order the toggle command steps through
+    pub fn next(self) -> LineNumbers {
+        match self {
+            LineNumbers::Off => LineNumbers::Absolute,
+            LineNumbers::Absolute => LineNumbers::Relative,
+            LineNumbers::Relative => LineNumbers::Hybrid,
+            LineNumbers::Hybrid => LineNumbers::Off,
+        }
+    }
+
+    // The number shown beside `line` with the cursor on `cursor_line`
+    pub fn label(self, line: usize, cursor_line: usize) -> Option<usize> {
+        match self {
+            LineNumbers::Off => None,
+            LineNumbers::Absolute => Some(line + 1),
+            LineNumbers::Hybrid if line == cursor_line => Some(line + 1),
+            LineNumbers::Relative | LineNumbers::Hybrid => Some(line.abs_diff(cursor_line)),
+        }
+    }
+}
+
+// Per-extension settings; unset fields fall back to the global ones
+#[derive(Clone, Debug, Default)]
+struct FileTypeConfig {
+    tab_width: Option<usize>,
+    style: Option<IndentStyle>,
+    dedent_brace: Option<bool>,
+    check_mixed_indent: Option<bool>,
+    trim_trailing_whitespace: Option<bool>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub indent: IndentSettings,
+    pub auto_indent: bool,
+    pub expand_brackets: bool, // With auto_indent, Enter between `{}` opens an indented block
+    pub detect_indent: bool, // Prefer the style found in the file itself
+    dedent_brace: bool,      // Typing `}` re-indents it to match its `{`
+    check_mixed_indent: bool, // Saving a file that mixes tabs and spaces asks to fix it
+    trim_trailing_whitespace: bool, // Saving strips whitespace from line ends
+    pub editorconfig: bool,   // Read `.editorconfig` files above the opened file
+    // Used for files that aren't UTF-8; guessed from the bytes when unset
+    pub fallback_encoding: Option<Encoding>,
+    // Opening a file bigger than this many bytes asks first
+    pub large_file_size: Option<u64>,
+    // Snapshots of the text kept in the undo history, for reverting far
+    // back in one step; 0 keeps none
+    pub undo_checkpoints: usize,
+    // Undo keeps text longer than this many bytes as part of the buffer's
+    // rope, sharing it, rather than as a copy
+    pub undo_share_size: usize,
+    // None underlines the character under the cursor instead of using the
+    // terminal's own cursor
+    pub cursor_shape: Option<CursorShape>,
+    pub cursor_blink: bool,
+    pub hide_cursor_after: Option<Duration>, // Without input for this long, the cursor is hidden
+    pub normalize_paste: bool, // Pasted line endings become `\n`
+    pub trim_paste: bool,      // Pasted lines lose their trailing whitespace
+    pub show_line_endings: bool, // Draw `\r` as a visible marker
+    pub scrolloff: usize,        // Lines of context kept above and below the cursor
+    pub show_eof_markers: bool,  // `~` on rows past the end of the buffer
+    pub scroll_past_end: bool,   // The view can scroll on until the last line is at the top
+    pub indent_guides: bool,     // Dim bars at each indent level in leading whitespace
+    pub wrap_motion: bool,       // Left/right and word motion cross line breaks
+    pub smart_backspace: bool,   // Backspace in leading spaces removes an indent level
+    pub trim_on_enter: bool,     // Enter strips the trailing whitespace of the line it leaves
+    // Enter at the end of a file with no line break at the end opens a new
+    // line; off, it only adds the missing line break
+    pub open_line_at_eof: bool,
+    pub soft_tab_motion: bool,   // Left/right in leading spaces move an indent level
+    pub remember_position: bool, // Reopened files start where the cursor was left
+    pub follow_symlinks: bool,   // Saving a symlink writes its target, not a file in its place
+    pub highlight_search: bool,  // Matches of the last search stay highlighted
+    pub minimap: bool,           // Overview of the whole file down the right edge
+    pub centered: bool,          // Text in a column of `center_width` mid-screen
+    pub center_width: usize,
+    pub line_numbers: LineNumbers,
+    pub visual_bell: bool,  // Invalid actions flash the status line
+    pub audible_bell: bool, // Invalid actions ring the terminal's bell
+    pub mouse: bool,        // Clicks move the cursor and select, instead of the terminal's own selection
+    pub keymap: Keymap,
+    // Text past this many screen columns gets `line_length_color` behind it
+    pub line_length_limit: Option<usize>,
+    pub line_length_color: Color,
+    pub companions: Vec<Rule>, // For opening a file's header, tests and the like
+    filetypes: HashMap<String, FileTypeConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            indent: IndentSettings::default(),
+            auto_indent: true,
+            expand_brackets: true,
+            detect_indent: true,
+            dedent_brace: false,
+            check_mixed_indent: false,
+            trim_trailing_whitespace: false,
+            editorconfig: true,
+            fallback_encoding: None,
+            large_file_size: Some(DEFAULT_LARGE_FILE_MB * 1024 * 1024),
+            undo_checkpoints: 20,
+            undo_share_size: 64 * 1024,
+            cursor_shape: Some(CursorShape::Block),
+            cursor_blink: true,
+            hide_cursor_after: None,
+            normalize_paste: true,
+            trim_paste: false,
+            show_line_endings: false,
+            scrolloff: 0,
+            show_eof_markers: false,
+            scroll_past_end: false,
+            indent_guides: false,
+            wrap_motion: true,
+            smart_backspace: false,
+            trim_on_enter: false,
+            open_line_at_eof: true,
+            soft_tab_motion: false,
+            remember_position: true,
+            follow_symlinks: true,
+            highlight_search: true,
+            minimap: false,
+            centered: false,
+            center_width: 80,
+            line_numbers: LineNumbers::Off,
+            visual_bell: true,
+            audible_bell: false,
+            mouse: false,
+            keymap: Keymap::Default,
+            line_length_limit: None,
+            line_length_color: Color::DarkRed,
+            companions: companion::default_rules(),
+            filetypes: HashMap::new(),
+        }
+    }
+}
+
+// Directory holding the config file and any other editor state
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("rope-editor"))
+}
+
+// Files up to this size open without asking
+const DEFAULT_LARGE_FILE_MB: u64 = 256;
+
+// An on/off setting: its name in the config file, whether it's live, and
+// the field it's kept in, to read and to set. Live ones, read each time they
+// apply (detect_indent and remember_position at the next file opened), can
+// be changed in the settings view while the editor runs; the rest can differ
+// per file type or need the terminal set up again, so they stay in the file.
+type Flag = (&'static str, bool, fn(&Config) -> bool, fn(&mut Config) -> &mut bool);
+
+const FLAGS: &[Flag] = &[
+    ("auto_indent", true, |c| c.auto_indent, |c| &mut c.auto_indent),
+    ("expand_brackets", true, |c| c.expand_brackets, |c| &mut c.expand_brackets),
+    ("wrap_motion", true, |c| c.wrap_motion, |c| &mut c.wrap_motion),
+    ("smart_backspace", true, |c| c.smart_backspace, |c| &mut c.smart_backspace),
+    ("trim_on_enter", true, |c| c.trim_on_enter, |c| &mut c.trim_on_enter),
+    ("open_line_at_eof", true, |c| c.open_line_at_eof, |c| &mut c.open_line_at_eof),
+    ("soft_tab_motion", true, |c| c.soft_tab_motion, |c| &mut c.soft_tab_motion),
+    ("show_line_endings", true, |c| c.show_line_endings, |c| &mut c.show_line_endings),
+    ("show_eof_markers", true, |c| c.show_eof_markers, |c| &mut c.show_eof_markers),
+    ("scroll_past_end", true, |c| c.scroll_past_end, |c| &mut c.scroll_past_end),
+    ("indent_guides", true, |c| c.indent_guides, |c| &mut c.indent_guides),
+    ("highlight_search", true, |c| c.highlight_search, |c| &mut c.highlight_search),
+    ("minimap", true, |c| c.minimap, |c| &mut c.minimap),
+    ("centered", true, |c| c.centered, |c| &mut c.centered),
+    ("normalize_paste", true, |c| c.normalize_paste, |c| &mut c.normalize_paste),
+    ("trim_paste", true, |c| c.trim_paste, |c| &mut c.trim_paste),
+    ("cursor_blink", true, |c| c.cursor_blink, |c| &mut c.cursor_blink),
+    ("detect_indent", true, |c| c.detect_indent, |c| &mut c.detect_indent),
+    ("remember_position", true, |c| c.remember_position, |c| &mut c.remember_position),
+    ("editorconfig", true, |c| c.editorconfig, |c| &mut c.editorconfig),
+    ("follow_symlinks", true, |c| c.follow_symlinks, |c| &mut c.follow_symlinks),
+    ("visual_bell", true, |c| c.visual_bell, |c| &mut c.visual_bell),
+    ("audible_bell", true, |c| c.audible_bell, |c| &mut c.audible_bell),
+    ("dedent_closing_brace", false, |c| c.dedent_brace, |c| &mut c.dedent_brace),
+    ("check_mixed_indent", false, |c| c.check_mixed_indent, |c| &mut c.check_mixed_indent),
+    ("trim_trailing_whitespace", false, |c| c.trim_trailing_whitespace, |c| &mut c.trim_trailing_whitespace),
+    ("mouse", false, |c| c.mouse, |c| &mut c.mouse),
+];
+
+// Settings the settings view offers: line_numbers and the live on/off ones.
+// The others take a typed value, so they stay in the file.
+pub fn live_settings() -> Vec<&'static str> {
+    let flags = FLAGS.iter().filter(|(_, live, ..)| *live).map(|(name, ..)| *name);
+    std::iter::once("line_numbers").chain(flags).collect()
+}
+
+// `text` with the global `key` set to `value`: the existing line is
+// replaced, or a new one goes at the end of the global settings, before any
+// section
+fn set_in_text(text: &str, key: &str, value: &str) -> String {
+    let setting = format!("{} = {}", key, value);
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    let sections = lines.iter().position(|line| line.trim().starts_with('[')).unwrap_or(lines.len());
+    let existing = lines[..sections]
+        .iter()
+        .position(|line| line.split_once('=').map(|(k, _)| k.trim()) == Some(key));
+    match existing {
+        Some(i) => lines[i] = setting,
+        None => {
+            let mut at = sections;
+            while at > 0 && lines[at - 1].trim().is_empty() {
+                at -= 1;
+            }
+            lines.insert(at, setting);
+        }
+    }
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+// Write global settings into the config file, keeping everything else in it
+pub fn save_settings(settings: &[(&str, String)]) -> io::Result<()> {
+    let dir = config_dir().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no home directory"))?;
+    let file = dir.join("config");
+    let mut text = match fs::read_to_string(&file) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e),
+    };
+    for (key, value) in settings {
+        text = set_in_text(&text, key, value);
+    }
+    fs::create_dir_all(&dir)?;
+    fs::write(file, text)
+}
+
+impl Config {
+    // Missing or unreadable config files just give the defaults
+    pub fn load() -> Self {
+        config_dir()
+            .and_then(|dir| fs::read_to_string(dir.join("config")).ok())
+            .map(|text| Config::parse(&text))
+            .unwrap_or_default()
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut config = Config::default();
+        let mut section: Option<String> = None;
+        let mut companions = Vec::new();
+
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let ext = name.trim().trim_start_matches("*.").trim_start_matches('.');
+                section = Some(ext.to_string());
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+
+            // The on/off settings all parse the same way
+            if let (None, Some(field)) = (&section, config.flag_mut(key)) {
+                if let Ok(on) = value.parse() {
+                    *field = on;
+                }
+                continue;
+            }
+            match &section {
+                None => match key {
+                    "tab_width" => {
+                        if let Some(width) = parse_tab_width(value) {
+                            config.indent.tab_width = width;
+                        }
+                    }
+                    "indent_style" => {
+                        if let Some(style) = parse_indent_style(value) {
+                            config.indent.style = style;
+                        }
+                    }
+                    "fallback_encoding" => config.fallback_encoding = Encoding::parse(value),
+                    // In megabytes; 0 never asks. Too many to count in bytes
+                    // is ignored like any other bad value.
+                    "large_file_size" => {
+                        if let Some(size) = value.parse::<u64>().ok().and_then(|mb| mb.checked_mul(1024 * 1024)) {
+                            config.large_file_size = Some(size).filter(|&size| size > 0);
+                        }
+                    }
+                    "undo_checkpoints" => {
+                        if let Ok(count) = value.parse() {
+                            config.undo_checkpoints = count;
+                        }
+                    }
+                    "undo_share_size" => {
+                        if let Ok(bytes) = value.parse() {
+                            config.undo_share_size = bytes;
+                        }
+                    }
+                    "cursor_style" => {
+                        if let Some(shape) = parse_cursor_style(value) {
+                            config.cursor_shape = shape;
+                        }
+                    }
+                    "hide_cursor_after" => {
+                        if let Ok(seconds) = value.parse() {
+                            config.hide_cursor_after = Some(seconds).filter(|&s| s > 0).map(Duration::from_secs);
+                        }
+                    }
+                    "scrolloff" => {
+                        if let Ok(lines) = value.parse() {
+                            config.scrolloff = lines;
+                        }
+                    }
+                    "center_width" => {
+                        if let Some(width) = value.parse().ok().filter(|&width| width > 0) {
+                            config.center_width = width;
+                        }
+                    }
+                    "keymap" => {
+                        if let Some(keymap) = Keymap::parse(value) {
+                            config.keymap = keymap;
+                        }
+                    }
+                    "line_numbers" => {
+                        if let Some(mode) = parse_line_numbers(value) {
+                            config.line_numbers = mode;
+                        }
+                    }
+                    "line_length_limit" => {
+                        if let Ok(limit) = value.parse() {
+                            config.line_length_limit = Some(limit).filter(|&limit| limit > 0);
+                        }
+                    }
+                    "line_length_color" => {
+                        if let Ok(color) = Color::try_from(value) {
+                            config.line_length_color = color;
+                        }
+                    }
+                    // One rule per line, all kept
+                    "companion" => companions.extend(Rule::parse(value)),
+                    _ => {}
+                },
+                Some(ext) => {
+                    let filetype = config.filetypes.entry(ext.clone()).or_default();
+                    match key {
+                        "tab_width" => filetype.tab_width = parse_tab_width(value),
+                        "indent_style" => filetype.style = parse_indent_style(value),
+                        "dedent_closing_brace" => filetype.dedent_brace = value.parse().ok(),
+                        "check_mixed_indent" => filetype.check_mixed_indent = value.parse().ok(),
+                        "trim_trailing_whitespace" => filetype.trim_trailing_whitespace = value.parse().ok(),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        companions.extend(companion::default_rules());
+        config.companions = companions;
+        config
+    }
+
+    fn flag(&self, key: &str) -> Option<bool> {
+        FLAGS.iter().find(|(name, ..)| *name == key).map(|(_, _, get, _)| get(self))
+    }
+
+    fn flag_mut(&mut self, key: &str) -> Option<&mut bool> {
+        FLAGS.iter().find(|(name, ..)| *name == key).map(|(.., field)| field(self))
+    }
+
+    // Current value of one of live_settings(), as the config file spells it
+    pub fn setting(&self, key: &str) -> Option<String> {
+        match key {
+            "line_numbers" => Some(self.line_numbers.name().to_string()),
+            _ => self.flag(key).map(|on| on.to_string()),
+        }
+    }
+
+    // Flip an on/off setting, or step to the next choice of any other
+    pub fn toggle_setting(&mut self, key: &str) {
+        match key {
+            "line_numbers" => self.line_numbers = self.line_numbers.next(),
+            _ => {
+                if let Some(on) = self.flag_mut(key) {
+                    *on = !*on;
+                }
+            }
+        }
+    }
+
+    fn filetype(&self, path: &Path) -> Option<&FileTypeConfig> {
+        let ext = path.extension()?.to_string_lossy();
+        self.filetypes.get(ext.as_ref())
+    }
+
+    // Effective indentation for a file, from its extension
+    pub fn indent_for(&self, path: &Path) -> IndentSettings {
+        match self.filetype(path) {
+            Some(ft) => IndentSettings {
+                tab_width: ft.tab_width.unwrap_or(self.indent.tab_width),
+                style: ft.style.unwrap_or(self.indent.style),
+            },
+            None => self.indent,
+        }
+    }
+
+    // Unnamed buffers use the global setting
+    pub fn dedent_brace_for(&self, path: Option<&Path>) -> bool {
+        path.and_then(|p| self.filetype(p))
+            .and_then(|ft| ft.dedent_brace)
+            .unwrap_or(self.dedent_brace)
+    }
+
+    pub fn check_mixed_indent_for(&self, path: Option<&Path>) -> bool {
+        path.and_then(|p| self.filetype(p))
+            .and_then(|ft| ft.check_mixed_indent)
+            .unwrap_or(self.check_mixed_indent)
+    }
+
+    pub fn trim_trailing_whitespace_for(&self, path: Option<&Path>) -> bool {
+        path.and_then(|p| self.filetype(p))
+            .and_then(|ft| ft.trim_trailing_whitespace)
+            .unwrap_or(self.trim_trailing_whitespace)
+    }
+}
+
+fn parse_tab_width(value: &str) -> Option<usize> {
+    value.parse().ok().filter(|&width| width > 0)
+}
+
+fn parse_indent_style(value: &str) -> Option<IndentStyle> {
+    match value {
+        "spaces" => Some(IndentStyle::Spaces),
+        "tabs" => Some(IndentStyle::Tabs),
+        _ => None,
+    }
+}
+
+fn parse_line_numbers(value: &str) -> Option<LineNumbers> {
+    [LineNumbers::Off, LineNumbers::Absolute, LineNumbers::Relative, LineNumbers::Hybrid]
+        .into_iter()
+        .find(|mode| mode.name() == value)
+}
+
+// `drawn` is the in-text underline, for terminals that ignore cursor styling
+fn parse_cursor_style(value: &str) -> Option<Option<CursorShape>> {
+    match value {
+        "block" => Some(Some(CursorShape::Block)),
+        "bar" => Some(Some(CursorShape::Bar)),
+        "underline" => Some(Some(CursorShape::Underline)),
+        "drawn" => Some(None),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_toggle_and_are_written_back_in_place() {
+        let mut config = Config::default();
+        for key in live_settings() {
+            let before = config.setting(key).unwrap();
+            config.toggle_setting(key);
+            assert_ne!(config.setting(key).unwrap(), before, "{}", key);
+        }
+
+        let text = "# mine\ntab_width = 2\nminimap = false\n\n[go]\nminimap = false\n";
+        let text = set_in_text(text, "minimap", "true");
+        let text = set_in_text(&text, "centered", "true");
+        assert_eq!(
+            text,
+            "# mine\ntab_width = 2\nminimap = true\ncentered = true\n\n[go]\nminimap = false\n"
+        );
+        assert_eq!(set_in_text("", "centered", "true"), "centered = true\n");
+        let config = Config::parse(&text);
+        assert!(config.minimap && config.centered);
+    }
+
+    #[test]
+    fn every_flag_is_read_from_the_file() {
+        let defaults = Config::default();
+        for &(name, _, get, _) in FLAGS {
+            let config = Config::parse(&format!("{} = {}\n", name, !get(&defaults)));
+            assert_ne!(get(&config), get(&defaults), "{}", name);
+            assert_eq!(config.flag(name), Some(get(&config)));
+        }
+        // In a section they're per file type instead
+        assert!(!Config::parse("[py]\ncheck_mixed_indent = true\n").check_mixed_indent);
+    }
+}
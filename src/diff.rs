@@ -0,0 +1,148 @@
+// Line diff between the on-disk snapshot and the current buffer, used to
+// draw the change markers in the gutter.
+
+use std::ops::Range;
+
+// Above this many cells the LCS table gets too big to build every frame, so
+// we fall back to comparing lines position by position.
+const LCS_CELL_LIMIT: usize = 4_000_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineChange {
+    Added,
+    Modified,
+    // One or more baseline lines were removed just before this line
+    Removed,
+}
+
+// Markers for the current lines in `visible`. The common prefix and suffix
+// are trimmed first, so the diff itself only runs when the visible range
+// overlaps the part of the file that actually changed.
+pub fn visible_changes(
+    base: &[&str],
+    current: &[&str],
+    visible: Range<usize>,
+) -> Vec<Option<LineChange>> {
+    let visible = visible.start.min(current.len())..visible.end.min(current.len());
+    let mut markers = vec![None; visible.len()];
+    if visible.is_empty() {
+        return markers;
+    }
+
+    let prefix = base
+        .iter()
+        .zip(current.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let max_suffix = base.len().min(current.len()) - prefix;
+    let suffix = base
+        .iter()
+        .rev()
+        .zip(current.iter().rev())
+        .take(max_suffix)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let base_mid = &base[prefix..base.len() - suffix];
+    let cur_mid = &current[prefix..current.len() - suffix];
+    if base_mid.is_empty() && cur_mid.is_empty() {
+        return markers;
+    }
+
+    // Removed lines are reported on the line that follows them (or the last
+    // line), so the first suffix line may carry a marker too.
+    let last = current.len() - 1;
+    let changed = prefix.min(last)..=(prefix + cur_mid.len()).min(last);
+    if *changed.end() < visible.start || *changed.start() >= visible.end {
+        return markers;
+    }
+
+    let mid_changes = if base_mid.len().saturating_mul(cur_mid.len()) <= LCS_CELL_LIMIT {
+        lcs_changes(base_mid, cur_mid)
+    } else {
+        naive_changes(base_mid, cur_mid)
+    };
+
+    for (i, change) in mid_changes.into_iter().enumerate() {
+        // Lines removed from the end of the file have no following line, so
+        // they are flagged on the last one instead.
+        let line = (prefix + i).min(last);
+        if change.is_some() && visible.contains(&line) && markers[line - visible.start].is_none() {
+            markers[line - visible.start] = change;
+        }
+    }
+    markers
+}
+
+// One entry per current line plus a trailing slot for lines removed at the
+// very end of the range.
+fn lcs_changes(base: &[&str], current: &[&str]) -> Vec<Option<LineChange>> {
+    let (n, m) = (base.len(), current.len());
+    let mut table = vec![0u32; (n + 1) * (m + 1)];
+    let idx = |i: usize, j: usize| i * (m + 1) + j;
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            table[idx(i, j)] = if base[i] == current[j] {
+                table[idx(i + 1, j + 1)] + 1
+            } else {
+                table[idx(i + 1, j)].max(table[idx(i, j + 1)])
+            };
+        }
+    }
+
+    let mut changes = vec![None; m + 1];
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && base[i] == current[j] {
+            i += 1;
+            j += 1;
+            continue;
+        }
+        // Collect a run of removals followed by additions
+        let mut removed = 0;
+        while i < n && (j == m || table[idx(i + 1, j)] >= table[idx(i, j + 1)]) {
+            if j < m && base[i] == current[j] {
+                break;
+            }
+            removed += 1;
+            i += 1;
+        }
+        let start = j;
+        while j < m && (i == n || table[idx(i, j + 1)] > table[idx(i + 1, j)]) {
+            if i < n && base[i] == current[j] {
+                break;
+            }
+            j += 1;
+        }
+        let added = j - start;
+        for (k, slot) in changes[start..j].iter_mut().enumerate() {
+            *slot = Some(if k < removed {
+                LineChange::Modified
+            } else {
+                LineChange::Added
+            });
+        }
+        if removed > added && changes[j].is_none() {
+            changes[j] = Some(LineChange::Removed);
+        }
+    }
+    changes
+}
+
+fn naive_changes(base: &[&str], current: &[&str]) -> Vec<Option<LineChange>> {
+    let mut changes: Vec<Option<LineChange>> = current
+        .iter()
+        .enumerate()
+        .map(|(i, line)| match base.get(i) {
+            Some(old) if old == line => None,
+            Some(_) => Some(LineChange::Modified),
+            None => Some(LineChange::Added),
+        })
+        .collect();
+    changes.push(if base.len() > current.len() {
+        Some(LineChange::Removed)
+    } else {
+        None
+    });
+    changes
+}
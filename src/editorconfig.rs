@@ -0,0 +1,267 @@
+// Project settings from `.editorconfig` files (https://editorconfig.org).
+// Every `.editorconfig` from the file's directory up to one marked
+// `root = true` is read; nearer files, and later sections within a file,
+// win. Only the properties the editor has a use for are kept:
+//
+//     root = true
+//
+//     [*]
+//     indent_style = space
+//     indent_size = 4
+//     trim_trailing_whitespace = true
+//
+//     [{Makefile,*.go}]
+//     indent_style = tab
+//     tab_width = 8
+//
+//     [docs/**.md]
+//     trim_trailing_whitespace = false
+
+use std::fs;
+use std::path::Path;
+
+use crate::config::{IndentSettings, IndentStyle};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Properties {
+    pub indent_style: Option<IndentStyle>,
+    pub indent_size: Option<usize>,
+    pub tab_width: Option<usize>,
+    pub trim_trailing_whitespace: Option<bool>,
+}
+
+impl Properties {
+    // `base` with whatever the project sets laid over it. For tabs the
+    // width is how wide one is drawn; for spaces it's the indent size.
+    pub fn indent(&self, base: IndentSettings) -> IndentSettings {
+        let style = self.indent_style.unwrap_or(base.style);
+        let width = match style {
+            IndentStyle::Tabs => self.tab_width.or(self.indent_size),
+            IndentStyle::Spaces => self.indent_size.or(self.tab_width),
+        };
+        IndentSettings {
+            tab_width: width.unwrap_or(base.tab_width),
+            style,
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        let value = value.to_ascii_lowercase();
+        match key {
+            "indent_style" => {
+                self.indent_style = match value.as_str() {
+                    "space" => Some(IndentStyle::Spaces),
+                    "tab" => Some(IndentStyle::Tabs),
+                    _ => None,
+                }
+            }
+            // `indent_size = tab` means "whatever tab_width is"
+            "indent_size" => self.indent_size = value.parse().ok().filter(|&size| size > 0),
+            "tab_width" => self.tab_width = value.parse().ok().filter(|&width| width > 0),
+            "trim_trailing_whitespace" => self.trim_trailing_whitespace = value.parse().ok(),
+            _ => {}
+        }
+    }
+}
+
+// Properties for `path`, which needn't exist yet. Unreadable files count as
+// missing.
+pub fn for_file(path: &Path) -> Properties {
+    let path = match path.canonicalize() {
+        Ok(path) => path,
+        Err(_) => std::env::current_dir().map(|dir| dir.join(path)).unwrap_or_else(|_| path.to_path_buf()),
+    };
+    let mut found = Vec::new();
+    for dir in path.ancestors().skip(1) {
+        if let Ok(text) = fs::read_to_string(dir.join(".editorconfig")) {
+            let root = is_root(&text);
+            found.push((dir, text));
+            if root {
+                break;
+            }
+        }
+    }
+
+    let mut props = Properties::default();
+    for (dir, text) in found.iter().rev() {
+        let relative = path.strip_prefix(dir).unwrap_or(&path);
+        let relative: Vec<_> = relative.iter().map(|part| part.to_string_lossy()).collect();
+        apply(&mut props, text, &relative.join("/"));
+    }
+    props
+}
+
+// `root = true` before the first section
+fn is_root(text: &str) -> bool {
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') {
+            break;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            if key.trim() == "root" && value.trim().eq_ignore_ascii_case("true") {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+// Lay the sections of one file that match `relative` (the file's path from
+// that file's directory, `/`-separated) over `props`
+fn apply(props: &mut Properties, text: &str, relative: &str) {
+    let mut matching = false;
+    for line in text.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            matching = section_matches(glob, relative);
+            continue;
+        }
+        if let (true, Some((key, value))) = (matching, line.split_once('=')) {
+            props.set(&key.trim().to_ascii_lowercase(), value.trim());
+        }
+    }
+}
+
+// A glob without a `/` matches the file name in any directory; one with a
+// `/` matches the path from the `.editorconfig`'s directory
+fn section_matches(glob: &str, relative: &str) -> bool {
+    let (glob, target) = if glob.contains('/') {
+        (glob.trim_start_matches('/'), relative)
+    } else {
+        (glob, relative.rsplit('/').next().unwrap_or(relative))
+    };
+    let target: Vec<char> = target.chars().collect();
+    expand_braces(glob)
+        .iter()
+        .any(|glob| glob_matches(&glob.chars().collect::<Vec<_>>(), &target))
+}
+
+// `{a,b}c` becomes `ac` and `bc`. Braces without a comma are kept as they
+// are.
+fn expand_braces(glob: &str) -> Vec<String> {
+    let Some(open) = glob.find('{') else {
+        return vec![glob.to_string()];
+    };
+    let mut depth = 0;
+    let mut parts = Vec::new();
+    let mut start = open + 1;
+    for (i, c) in glob[open..].char_indices().map(|(i, c)| (open + i, c)) {
+        match c {
+            '{' => depth += 1,
+            ',' if depth == 1 => {
+                parts.push(&glob[start..i]);
+                start = i + 1;
+            }
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    if parts.is_empty() {
+                        break;
+                    }
+                    parts.push(&glob[start..i]);
+                    let rest = &glob[i + 1..];
+                    return parts
+                        .iter()
+                        .flat_map(|part| expand_braces(&format!("{}{}{}", &glob[..open], part, rest)))
+                        .collect();
+                }
+            }
+            _ => {}
+        }
+    }
+    vec![glob.to_string()]
+}
+
+// `*` is anything but `/`, `**` anything at all, `?` one character and
+// `[abc]`/`[!abc]` one of (or none of) a set
+fn glob_matches(glob: &[char], name: &[char]) -> bool {
+    match glob.first() {
+        None => name.is_empty(),
+        Some('*') if glob.get(1) == Some(&'*') => {
+            let rest = &glob[2..];
+            (0..=name.len()).any(|skip| glob_matches(rest, &name[skip..]))
+        }
+        Some('*') => {
+            let rest = &glob[1..];
+            let stop = name.iter().position(|&c| c == '/').unwrap_or(name.len());
+            (0..=stop).any(|skip| glob_matches(rest, &name[skip..]))
+        }
+        Some('?') => matches!(name.first(), Some(&c) if c != '/') && glob_matches(&glob[1..], &name[1..]),
+        Some('[') => match glob.iter().position(|&c| c == ']') {
+            Some(close) if close > 1 => {
+                let (negated, set) = match glob[1] {
+                    '!' => (true, &glob[2..close]),
+                    _ => (false, &glob[1..close]),
+                };
+                matches!(name.first(), Some(&c) if in_set(set, c) != negated)
+                    && glob_matches(&glob[close + 1..], &name[1..])
+            }
+            _ => name.first() == Some(&'[') && glob_matches(&glob[1..], &name[1..]),
+        },
+        Some(&c) => name.first() == Some(&c) && glob_matches(&glob[1..], &name[1..]),
+    }
+}
+
+// `set` is the inside of `[...]`, where `a-z` is a range
+fn in_set(set: &[char], c: char) -> bool {
+    let mut i = 0;
+    while i < set.len() {
+        if i + 2 < set.len() && set[i + 1] == '-' {
+            if (set[i]..=set[i + 2]).contains(&c) {
+                return true;
+            }
+            i += 3;
+        } else {
+            if set[i] == c {
+                return true;
+            }
+            i += 1;
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::TempDir;
+
+    #[test]
+    fn nearer_files_and_later_sections_win() {
+        let dir = TempDir::new("editorconfig");
+        let sub = dir.join("project").join("src");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(dir.join(".editorconfig"), "root = true\n[*]\nindent_size = 8\ntrim_trailing_whitespace = true\n").unwrap();
+        fs::write(
+            dir.join("project").join(".editorconfig"),
+            "[*]\nindent_style = space\nindent_size = 2\n\n[{Makefile,*.go}]\nindent_style = tab\ntab_width = 4\n\n[src/**.md]\ntrim_trailing_whitespace = false\n",
+        )
+        .unwrap();
+
+        let props = for_file(&sub.join("main.rs"));
+        assert_eq!(props.indent(IndentSettings::default()), IndentSettings { tab_width: 2, style: IndentStyle::Spaces });
+        assert_eq!(props.trim_trailing_whitespace, Some(true));
+        let props = for_file(&sub.join("main.go"));
+        assert_eq!(props.indent(IndentSettings::default()), IndentSettings { tab_width: 4, style: IndentStyle::Tabs });
+        assert_eq!(for_file(&sub.join("notes.md")).trim_trailing_whitespace, Some(false));
+        assert_eq!(for_file(&dir.join("notes.md")).trim_trailing_whitespace, Some(true));
+    }
+
+    #[test]
+    fn globs_match_like_editorconfig() {
+        let matches = |glob, path| section_matches(glob, path);
+        assert!(matches("*", "a/b.txt"));
+        assert!(matches("*.{js,ts}", "web/app.ts"));
+        assert!(!matches("*.{js,ts}", "web/app.rs"));
+        assert!(matches("lib/*.rs", "lib/x.rs"));
+        assert!(!matches("lib/*.rs", "lib/deep/x.rs"));
+        assert!(matches("/lib/**.rs", "lib/deep/x.rs"));
+        assert!(matches("file[0-9!].?", "file!.c"));
+        assert!(matches("[!.]*", "Makefile"));
+        assert!(!matches("[!.]*", ".hidden"));
+        assert!(matches("{single}", "{single}"));
+    }
+}
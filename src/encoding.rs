@@ -0,0 +1,111 @@
+// Text encodings for files that aren't valid UTF-8. Buffers are always
+// UTF-8 in memory; these are only used when reading and writing files.
+
+use std::io;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Windows1252,
+}
+
+// Windows-1252 code points for bytes 0x80..=0x9F. The five unassigned bytes
+// map to the matching C1 control, as browsers do, so decoding never fails.
+const WINDOWS_1252_HIGH: [char; 32] = [
+    '\u{20AC}', '\u{0081}', '\u{201A}', '\u{0192}', '\u{201E}', '\u{2026}', '\u{2020}', '\u{2021}',
+    '\u{02C6}', '\u{2030}', '\u{0160}', '\u{2039}', '\u{0152}', '\u{008D}', '\u{017D}', '\u{008F}',
+    '\u{0090}', '\u{2018}', '\u{2019}', '\u{201C}', '\u{201D}', '\u{2022}', '\u{2013}', '\u{2014}',
+    '\u{02DC}', '\u{2122}', '\u{0161}', '\u{203A}', '\u{0153}', '\u{009D}', '\u{017E}', '\u{0178}',
+];
+
+impl Encoding {
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Latin1 => "Latin-1",
+            Encoding::Windows1252 => "Windows-1252",
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "latin-1" | "latin1" | "iso-8859-1" => Some(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Some(Encoding::Windows1252),
+            _ => None,
+        }
+    }
+}
+
+// Decode bytes in one of the single-byte encodings. Every byte maps to a
+// character, so this can't fail.
+pub fn decode_single_byte(bytes: &[u8], encoding: Encoding) -> String {
+    bytes
+        .iter()
+        .map(|&b| match (encoding, b) {
+            (Encoding::Windows1252, 0x80..=0x9F) => WINDOWS_1252_HIGH[(b - 0x80) as usize],
+            _ => b as char,
+        })
+        .collect()
+}
+
+// How much of the start of a file `is_binary` looks at
+pub const BINARY_SAMPLE: usize = 8 * 1024;
+
+// A NUL byte, or lots of control characters other than the usual
+// whitespace, means the file isn't text in any encoding this editor knows,
+// and saving it would mangle it
+pub fn is_binary(sample: &[u8]) -> bool {
+    if sample.contains(&0) {
+        return true;
+    }
+    let control = sample
+        .iter()
+        .filter(|&&b| b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0C | 0x1B))
+        .count();
+    control * 10 > sample.len()
+}
+
+pub fn binary_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "looks like a binary file, not opening it")
+}
+
+// Decode a whole file at once, the same way the loader does chunk by chunk:
+// UTF-8 if it is, otherwise `fallback`, otherwise a guess between Latin-1
+// and Windows-1252
+pub fn decode(bytes: Vec<u8>, fallback: Option<Encoding>) -> (String, Encoding) {
+    let bytes = match String::from_utf8(bytes) {
+        Ok(text) => return (text, Encoding::Utf8),
+        Err(e) => e.into_bytes(),
+    };
+    let encoding = match fallback {
+        Some(Encoding::Utf8) | None if bytes.iter().any(|b| (0x80..=0x9F).contains(b)) => Encoding::Windows1252,
+        Some(Encoding::Utf8) | None => Encoding::Latin1,
+        Some(encoding) => encoding,
+    };
+    (decode_single_byte(&bytes, encoding), encoding)
+}
+
+pub fn encode(text: &str, encoding: Encoding) -> io::Result<Vec<u8>> {
+    if encoding == Encoding::Utf8 {
+        return Ok(text.as_bytes().to_vec());
+    }
+    text.chars()
+        .map(|c| {
+            let high = WINDOWS_1252_HIGH.iter().position(|&h| h == c);
+            match (encoding, c as u32, high) {
+                (Encoding::Windows1252, _, Some(i)) => Ok(0x80 + i as u8),
+                (Encoding::Windows1252, 0x80..=0x9F, None) => Err(c),
+                (_, code @ 0..=0xFF, _) => Ok(code as u8),
+                _ => Err(c),
+            }
+        })
+        .collect::<Result<Vec<u8>, char>>()
+        .map_err(|c| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("'{}' can't be written as {}", c, encoding.name()),
+            )
+        })
+}
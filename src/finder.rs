@@ -0,0 +1,101 @@
+// Finding files to open: the directory walk for the Ctrl+T file finder, and
+// file names mentioned in the text
+
+use std::collections::{HashSet, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Directory names never descended into
+const IGNORED_DIRS: &[&str] = &[".git", "target", "node_modules"];
+
+// Stop walking after this many files so a huge tree can't hang the UI
+pub const MAX_FILES: usize = 10_000;
+
+// Files under `root`, breadth first so shallow files show up even when the
+// cap is hit. Unreadable directories are skipped, and symlinked directories
+// are only entered once, which also breaks symlink loops.
+pub fn list_files(root: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut visited = HashSet::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(root.to_path_buf());
+
+    while let Some(dir) = queue.pop_front() {
+        let Ok(canonical) = fs::canonicalize(&dir) else {
+            continue;
+        };
+        if !visited.insert(canonical) {
+            continue;
+        }
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut entries: Vec<_> = entries.filter_map(Result::ok).collect();
+        entries.sort_by_key(|e| e.file_name());
+
+        for entry in entries {
+            let path = entry.path();
+            // Follows symlinks; broken links and permission errors are skipped
+            let Ok(metadata) = fs::metadata(&path) else {
+                continue;
+            };
+            if metadata.is_dir() {
+                let name = entry.file_name();
+                if !IGNORED_DIRS.iter().any(|ignored| name == *ignored) {
+                    queue.push_back(path);
+                }
+            } else if metadata.is_file() {
+                files.push(path.strip_prefix(root).map(Path::to_path_buf).unwrap_or(path));
+                if files.len() >= MAX_FILES {
+                    return files;
+                }
+            }
+        }
+    }
+    files
+}
+
+// Characters that can't be part of a path mentioned in text
+fn ends_path(c: char) -> bool {
+    c.is_whitespace() || "\"'`()<>[]{}".contains(c)
+}
+
+// The path written around byte `at` in `line`, with the line number from a
+// `path:line` or `path:line:col` suffix (counted from 1), if there is one
+pub fn path_at(line: &str, at: usize) -> Option<(String, Option<usize>)> {
+    let start = line[..at].rfind(ends_path).map_or(0, |i| i + line[i..].chars().next().unwrap().len_utf8());
+    let end = line[at..].find(ends_path).map_or(line.len(), |i| at + i);
+    // Trailing punctuation is more likely the sentence than the path
+    let mut token = line[start..end].trim_end_matches(['.', ',', ';', ':']);
+
+    let mut numbers = Vec::new();
+    while numbers.len() < 2 {
+        match token.rsplit_once(':') {
+            Some((rest, n)) if !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()) => {
+                numbers.push(n.parse().ok()?);
+                token = rest;
+            }
+            _ => break,
+        }
+    }
+    if token.is_empty() {
+        return None;
+    }
+    Some((token.to_string(), numbers.pop()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::path_at;
+
+    #[test]
+    fn paths_are_found_around_the_cursor() {
+        let line = "see \"src/main.rs:42\" and (notes.txt).";
+        assert_eq!(path_at(line, 8), Some(("src/main.rs".to_string(), Some(42))));
+        assert_eq!(path_at(line, 5), Some(("src/main.rs".to_string(), Some(42))));
+        assert_eq!(path_at(line, 30), Some(("notes.txt".to_string(), None)));
+        assert_eq!(path_at("a.rs:3:7", 0), Some(("a.rs".to_string(), Some(3))));
+        assert_eq!(path_at("x  y", 2), None);
+        assert_eq!(path_at("", 0), None);
+    }
+}
@@ -0,0 +1,58 @@
+// Small fuzzy matcher for the command palette: the pattern must appear in
+// the candidate as a case-insensitive subsequence, and matches that are
+// consecutive or start a word score higher.
+
+const MATCH_SCORE: i64 = 16;
+const CONSECUTIVE_BONUS: i64 = 24;
+const WORD_START_BONUS: i64 = 20;
+const GAP_PENALTY: i64 = 2;
+
+// Score `candidate` against `pattern`, or None if it doesn't match at all.
+pub fn score(pattern: &str, candidate: &str) -> Option<i64> {
+    let mut total = 0;
+    let mut last_match: Option<usize> = None;
+    let mut prev: Option<char> = None;
+    let mut chars = candidate.chars().enumerate();
+
+    for p in pattern.chars().filter(|c| !c.is_whitespace()) {
+        let p = p.to_ascii_lowercase();
+        loop {
+            let (i, c) = chars.next()?;
+            let before = prev.replace(c);
+            if c.to_ascii_lowercase() != p {
+                continue;
+            }
+            total += MATCH_SCORE;
+            match last_match {
+                Some(last) if last + 1 == i => total += CONSECUTIVE_BONUS,
+                Some(last) => total -= GAP_PENALTY * (i - last - 1) as i64,
+                None => total -= GAP_PENALTY * i as i64,
+            }
+            let word_start = match before {
+                None => true,
+                Some(b) => !b.is_alphanumeric() || (b.is_lowercase() && c.is_uppercase()),
+            };
+            if word_start {
+                total += WORD_START_BONUS;
+            }
+            last_match = Some(i);
+            break;
+        }
+    }
+    Some(total)
+}
+
+// Indices of the matching candidates, best first. Ties keep the shorter
+// candidate first, then the original order.
+pub fn rank<S: AsRef<str>>(pattern: &str, candidates: &[S]) -> Vec<usize> {
+    if pattern.trim().is_empty() {
+        return (0..candidates.len()).collect();
+    }
+    let mut scored: Vec<(i64, usize, usize)> = candidates
+        .iter()
+        .enumerate()
+        .filter_map(|(i, c)| score(pattern, c.as_ref()).map(|s| (s, c.as_ref().len(), i)))
+        .collect();
+    scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+    scored.into_iter().map(|(_, _, i)| i).collect()
+}
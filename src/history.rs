@@ -0,0 +1,189 @@
+// Undo history as a tree. Undoing and then making a new edit starts a new
+// branch beside the old one instead of throwing the old one away, so every
+// state the text has been in can still be reached.
+
+pub struct History<T> {
+    nodes: Vec<Node<T>>, // nodes[0] is the root, the text before any action
+    current: usize,      // The last action applied, or the root
+}
+
+struct Node<T> {
+    action: Option<T>, // None only for the root
+    parent: usize,
+    children: Vec<usize>, // Oldest first
+    redo: usize,          // The child redo goes to: the newest, or the one last visited
+    depth: usize,
+}
+
+impl<T: Clone> History<T> {
+    pub fn new() -> Self {
+        History {
+            nodes: vec![Node {
+                action: None,
+                parent: 0,
+                children: Vec::new(),
+                redo: 0,
+                depth: 0,
+            }],
+            current: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        *self = History::new();
+    }
+
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    // Actions undo can take back from here
+    pub fn depth(&self) -> usize {
+        self.nodes[self.current].depth
+    }
+
+    // Actions redo can bring back from here
+    pub fn redo_len(&self) -> usize {
+        let (mut node, mut len) = (self.current, 0);
+        while let Some(next) = self.redo_child(node) {
+            node = next;
+            len += 1;
+        }
+        len
+    }
+
+    fn redo_child(&self, node: usize) -> Option<usize> {
+        let node = &self.nodes[node];
+        node.children.get(node.redo).copied()
+    }
+
+    // `node`, its parent, and so on up to the root
+    pub fn ancestors(&self, mut node: usize) -> impl Iterator<Item = usize> + '_ {
+        let mut done = false;
+        std::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+            let this = node;
+            done = this == 0;
+            node = self.nodes[this].parent;
+            Some(this)
+        })
+    }
+
+    // A new action after the current one, on a branch of its own if the
+    // current one already has some
+    pub fn push(&mut self, action: T) {
+        let id = self.nodes.len();
+        let parent = &mut self.nodes[self.current];
+        parent.children.push(id);
+        parent.redo = parent.children.len() - 1;
+        let depth = parent.depth + 1;
+        self.nodes.push(Node {
+            action: Some(action),
+            parent: self.current,
+            children: Vec::new(),
+            redo: 0,
+            depth,
+        });
+        self.current = id;
+    }
+
+    // The action to take back, with the current node moved to its parent
+    pub fn undo(&mut self) -> Option<T> {
+        let node = &self.nodes[self.current];
+        let action = node.action.clone()?;
+        self.current = node.parent;
+        Some(action)
+    }
+
+    // The action to apply again, along the branch last visited
+    pub fn redo(&mut self) -> Option<T> {
+        let next = self.redo_child(self.current)?;
+        self.current = next;
+        self.nodes[next].action.clone()
+    }
+
+    // The actions to undo and then redo, in order, to get from the current
+    // node to `target`, which becomes current. Redo follows the way taken
+    // afterwards.
+    pub fn go_to(&mut self, target: usize) -> (Vec<T>, Vec<T>) {
+        let mut up = self.current;
+        let mut down = target;
+        let (mut undo, mut redo) = (Vec::new(), Vec::new());
+        while up != down {
+            if self.nodes[up].depth >= self.nodes[down].depth {
+                undo.extend(self.nodes[up].action.clone());
+                up = self.nodes[up].parent;
+            } else {
+                let parent = self.nodes[down].parent;
+                let position = self.nodes[parent].children.iter().position(|&c| c == down).unwrap();
+                self.nodes[parent].redo = position;
+                redo.extend(self.nodes[down].action.clone());
+                down = parent;
+            }
+        }
+        redo.reverse();
+        self.current = target;
+        (undo, redo)
+    }
+
+    // The branch beside the current node's, counting from its parent,
+    // wrapping round; None when it has no siblings
+    pub fn sibling(&self, forward: bool) -> Option<usize> {
+        let parent = &self.nodes[self.nodes[self.current].parent];
+        let count = parent.children.len();
+        if self.current == 0 || count < 2 {
+            return None;
+        }
+        let position = parent.children.iter().position(|&c| c == self.current)?;
+        let next = if forward { (position + 1) % count } else { (position + count - 1) % count };
+        Some(parent.children[next])
+    }
+
+    // One line per node, each with its id, in the order of the tree. Later
+    // branches are indented under the node they grew from.
+    pub fn outline(&self, describe: impl Fn(&T) -> String) -> Vec<(usize, String)> {
+        let mut lines = Vec::new();
+        let mut stack = vec![(0, 0)];
+        while let Some((id, indent)) = stack.pop() {
+            let node = &self.nodes[id];
+            let marker = if id == self.current { '*' } else { ' ' };
+            let text = node.action.as_ref().map_or("(start)".to_string(), &describe);
+            lines.push((id, format!("{}{}{:>4} {}", "  ".repeat(indent), marker, node.depth, text)));
+            for (i, &child) in node.children.iter().enumerate().rev() {
+                stack.push((child, if i == 0 { indent } else { indent + 1 }));
+            }
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undone_branches_are_kept_and_reachable() {
+        let mut history = History::new();
+        history.push("a");
+        history.push("b");
+        assert_eq!(history.undo(), Some("b"));
+        history.push("c");
+        assert_eq!((history.depth(), history.redo_len()), (2, 0));
+
+        // b is a sibling of c now, not gone
+        let b = history.sibling(true).unwrap();
+        assert_eq!(history.go_to(b), (vec!["c"], vec!["b"]));
+        assert_eq!(history.undo(), Some("b"));
+        assert_eq!(history.redo(), Some("b"));
+
+        assert_eq!(history.go_to(0), (vec!["b", "a"], vec![]));
+        assert_eq!(history.redo_len(), 2);
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.ancestors(b).collect::<Vec<_>>(), [b, 1, 0]);
+
+        let outline: Vec<String> = history.outline(|a| a.to_string()).into_iter().map(|(_, l)| l).collect();
+        assert_eq!(outline, ["*   0 (start)", "    1 a", "    2 b", "      2 c"]);
+    }
+}
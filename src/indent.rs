@@ -0,0 +1,134 @@
+// Guess a file's indentation style from its content, so editing an existing
+// file keeps to the style it already uses, and tidy up files that mix
+// styles or leave whitespace at line ends.
+
+use crate::column;
+use crate::config::{IndentSettings, IndentStyle};
+
+// Only look at this many indented lines; the top of a file is enough
+const SAMPLE_LINES: usize = 100;
+
+// ...and give up after this many lines of any kind
+const SCAN_LINES: usize = 1000;
+
+// Widths above this are more likely alignment than indentation
+const MAX_WIDTH: usize = 8;
+
+// The detected settings, or None when the file has no indentation or mixes
+// tabs and spaces too much to tell. `tab_width` is kept for tab-indented
+// files, since the content can't say how wide a tab should look.
+pub fn detect(content: &str, tab_width: usize) -> Option<IndentSettings> {
+    let mut tab_lines = 0;
+    let mut space_lines = 0;
+    let mut deltas = [0usize; MAX_WIDTH + 1];
+    let mut prev_spaces = 0;
+
+    for line in content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .take(SCAN_LINES)
+    {
+        let spaces = line.chars().take_while(|&c| c == ' ').count();
+        if line.starts_with('\t') {
+            tab_lines += 1;
+        } else if spaces > 0 {
+            space_lines += 1;
+        }
+        if !line.starts_with('\t') {
+            // Indentation steps between neighbouring lines give the width
+            let delta = spaces.abs_diff(prev_spaces);
+            if (1..=MAX_WIDTH).contains(&delta) {
+                deltas[delta] += 1;
+            }
+            prev_spaces = spaces;
+        }
+        if tab_lines + space_lines >= SAMPLE_LINES {
+            break;
+        }
+    }
+
+    let total = tab_lines + space_lines;
+    if total == 0 {
+        return None;
+    }
+    // Allow a few stray lines, but a real mix is ambiguous
+    let minority = tab_lines.min(space_lines);
+    if minority * 10 > total {
+        return None;
+    }
+
+    if tab_lines > space_lines {
+        return Some(IndentSettings {
+            tab_width,
+            style: IndentStyle::Tabs,
+        });
+    }
+    // Most common step wins; ties go to the smaller width
+    let (width, count) = deltas
+        .iter()
+        .enumerate()
+        .skip(1)
+        .fold((0, 0), |best, (w, &n)| if n > best.1 { (w, n) } else { best });
+    if count == 0 {
+        return None;
+    }
+    Some(IndentSettings {
+        tab_width: width,
+        style: IndentStyle::Spaces,
+    })
+}
+
+// Some lines indented with tabs and others with spaces, or a space before a
+// tab within one line's indentation. Spaces after tabs are alignment and
+// don't count. Takes the text a character at a time, so a rope's can be
+// checked without copying it out.
+pub fn is_mixed(chars: impl Iterator<Item = char>) -> bool {
+    let (mut tabs, mut spaces) = (false, false);
+    // What came before on this line: None at its start, Some(' ') or
+    // Some('\t') within the indentation, and Some('x') past it
+    let mut before = None;
+    for c in chars {
+        match (before, c) {
+            (_, '\n') => before = None,
+            (Some(' '), '\t') => return true,
+            (None, ' ' | '\t') => {
+                tabs |= c == '\t';
+                spaces |= c == ' ';
+                before = Some(c);
+            }
+            (Some(' ' | '\t'), ' ' | '\t') => before = Some(c),
+            _ => before = Some('x'),
+        }
+    }
+    tabs && spaces
+}
+
+// Every line's indentation redone in `settings`' style at the same width.
+// Tabs-style keeps spaces for any remainder narrower than a tab.
+pub fn normalize(content: &str, settings: IndentSettings) -> String {
+    let width = settings.tab_width;
+    content
+        .split_inclusive('\n')
+        .map(|line| {
+            let rest = line.trim_start_matches([' ', '\t']);
+            let cols = column::display_width(&line[..line.len() - rest.len()], width);
+            let indent = match settings.style {
+                IndentStyle::Spaces => " ".repeat(cols),
+                IndentStyle::Tabs => "\t".repeat(cols / width) + &" ".repeat(cols % width),
+            };
+            indent + rest
+        })
+        .collect()
+}
+
+// Spaces and tabs at the end of every line gone, keeping `\r\n` endings
+pub fn trim_trailing(content: &str) -> String {
+    content
+        .split_inclusive('\n')
+        .map(|line| {
+            let body = line.trim_end_matches(['\r', '\n']);
+            let ending = &line[body.len()..];
+            body.trim_end_matches([' ', '\t']).to_string() + ending
+        })
+        .collect()
+}
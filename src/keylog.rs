@@ -0,0 +1,110 @@
+// The last few keys pressed and the commands they ran, shown in a corner
+// of the screen for screencasts and for working out why a binding doesn't
+// do what's expected. Each fades after a few seconds, and a key pressed
+// again straight away counts up instead of taking another row.
+
+use crossterm::event::{KeyCode, KeyModifiers};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::command::{self, EditorCommand};
+
+const MAX_KEYS: usize = 6;
+pub const SHOW_FOR: Duration = Duration::from_secs(3);
+
+struct Entry {
+    key: (KeyCode, KeyModifiers),
+    command: Option<EditorCommand>,
+    count: usize,
+    at: Instant, // Last pressed
+}
+
+pub struct KeyLog {
+    keys: VecDeque<Entry>, // Oldest first
+}
+
+impl KeyLog {
+    pub fn new() -> Self {
+        KeyLog { keys: VecDeque::new() }
+    }
+
+    pub fn push(&mut self, code: KeyCode, modifiers: KeyModifiers, command: Option<EditorCommand>, now: Instant) {
+        if let Some(last) = self.keys.back_mut().filter(|last| last.key == (code, modifiers)) {
+            last.count += 1;
+            last.at = now;
+            return;
+        }
+        self.keys.push_back(Entry {
+            key: (code, modifiers),
+            command,
+            count: 1,
+            at: now,
+        });
+        if self.keys.len() > MAX_KEYS {
+            self.keys.pop_front();
+        }
+    }
+
+    // Drops the keys shown long enough, saying whether there were any
+    pub fn expire(&mut self, now: Instant) -> bool {
+        let before = self.keys.len();
+        self.keys.retain(|entry| now < entry.at + SHOW_FOR);
+        self.keys.len() != before
+    }
+
+    // When the oldest key shown is due to go
+    pub fn next_expiry(&self) -> Option<Instant> {
+        self.keys.iter().map(|entry| entry.at + SHOW_FOR).min()
+    }
+
+    // Oldest first, like `Ctrl+S  Save the file` or `Down ×3  Move down`
+    pub fn lines(&self) -> Vec<String> {
+        self.keys
+            .iter()
+            .map(|entry| {
+                let mut line = command::key_name(entry.key.0, entry.key.1);
+                if entry.count > 1 {
+                    line += &format!(" ×{}", entry.count);
+                }
+                if let Some(command) = entry.command {
+                    line += &format!("  {}", command.name());
+                }
+                line
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_named_counted_and_fade() {
+        assert_eq!(command::key_name(KeyCode::Char('z'), KeyModifiers::CONTROL | KeyModifiers::SHIFT), "Ctrl+Shift+Z");
+        assert_eq!(command::key_name(KeyCode::Char('A'), KeyModifiers::SHIFT), "A");
+        assert_eq!(command::key_name(KeyCode::Char(' '), KeyModifiers::NONE), "Space");
+        assert_eq!(command::key_name(KeyCode::Up, KeyModifiers::ALT), "Alt+Up");
+        assert_eq!(command::key_name(KeyCode::F(3), KeyModifiers::NONE), "F3");
+        assert_eq!(command::key_name(KeyCode::BackTab, KeyModifiers::SHIFT), "Shift+Tab");
+
+        let start = Instant::now();
+        let mut log = KeyLog::new();
+        log.push(KeyCode::Char('s'), KeyModifiers::CONTROL, Some(EditorCommand::Save), start);
+        let later = start + Duration::from_secs(1);
+        for _ in 0..3 {
+            log.push(KeyCode::Char('x'), KeyModifiers::NONE, None, later);
+        }
+        assert_eq!(log.lines(), ["Ctrl+S  Save the file", "x ×3"]);
+        assert_eq!(log.next_expiry(), Some(start + SHOW_FOR));
+
+        assert!(!log.expire(start + SHOW_FOR - Duration::from_millis(1)));
+        assert!(log.expire(start + SHOW_FOR));
+        assert_eq!(log.lines(), ["x ×3"]);
+
+        for c in "abcdefgh".chars() {
+            log.push(KeyCode::Char(c), KeyModifiers::NONE, None, later);
+        }
+        assert_eq!(log.lines().concat(), "cdefgh");
+    }
+}
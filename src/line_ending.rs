@@ -0,0 +1,66 @@
+// Line-ending bookkeeping. Buffers keep whatever endings the file had, so a
+// file edited on several platforms can end up with a mix.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LineEndings {
+    pub lf: usize,
+    pub crlf: usize,
+    pub cr: usize, // A `\r` not followed by `\n`
+}
+
+impl LineEndings {
+    pub fn count(chars: impl Iterator<Item = char>) -> Self {
+        let mut counts = LineEndings::default();
+        let mut after_cr = false;
+        for c in chars {
+            match c {
+                '\n' if after_cr => {
+                    counts.cr -= 1;
+                    counts.crlf += 1;
+                }
+                '\n' => counts.lf += 1,
+                '\r' => counts.cr += 1,
+                _ => {}
+            }
+            after_cr = c == '\r';
+        }
+        counts
+    }
+
+    pub fn is_mixed(&self) -> bool {
+        [self.lf, self.crlf, self.cr].iter().filter(|&&n| n > 0).count() > 1
+    }
+
+    // Short name for the status line; files without any line break count as LF
+    pub fn label(&self) -> &'static str {
+        if self.is_mixed() {
+            "Mixed"
+        } else if self.crlf > 0 {
+            "CRLF"
+        } else if self.cr > 0 {
+            "CR"
+        } else {
+            "LF"
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        for (n, name) in [(self.lf, "LF"), (self.crlf, "CRLF"), (self.cr, "CR")] {
+            if n > 0 {
+                parts.push(format!("{} {}", n, name));
+            }
+        }
+        parts.join(", ")
+    }
+}
+
+// Rewrite every line ending as `\r\n` or `\n`
+pub fn normalize(text: &str, crlf: bool) -> String {
+    let lf = text.replace("\r\n", "\n").replace('\r', "\n");
+    if crlf {
+        lf.replace('\n', "\r\n")
+    } else {
+        lf
+    }
+}
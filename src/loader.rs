@@ -0,0 +1,118 @@
+// Reads a file a chunk at a time into a balanced rope, so the main loop can
+// show progress (and stay responsive) while a large file loads.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::encoding::{self, Encoding};
+use crate::rope::{Rope, RopeBuilder};
+
+// Also the leaf size of the resulting rope
+const CHUNK_SIZE: usize = 64 * 1024;
+
+pub struct FileLoader {
+    pub path: PathBuf,
+    file: File,
+    total: u64,
+    read: u64,
+    builder: RopeBuilder,
+    encoding: Encoding,
+    fallback: Option<Encoding>,
+    pending: Vec<u8>, // Start of a UTF-8 sequence split across chunks
+    saw_c1: bool,     // Any byte in 0x80..=0x9F, for guessing Latin-1 vs Windows-1252
+}
+
+impl FileLoader {
+    pub fn open(path: &Path, fallback: Option<Encoding>) -> io::Result<Self> {
+        let mut file = File::open(path)?;
+        let total = file.metadata()?.len();
+        let mut sample = Vec::new();
+        (&mut file).take(encoding::BINARY_SAMPLE as u64).read_to_end(&mut sample)?;
+        if encoding::is_binary(&sample) {
+            return Err(encoding::binary_error());
+        }
+        file.rewind()?;
+        Ok(FileLoader {
+            path: path.to_path_buf(),
+            file,
+            total,
+            read: 0,
+            builder: RopeBuilder::new(),
+            encoding: Encoding::Utf8,
+            fallback,
+            pending: Vec::new(),
+            saw_c1: false,
+        })
+    }
+
+    pub fn percent(&self) -> u64 {
+        (self.read * 100).checked_div(self.total).map_or(100, |percent| percent.min(100))
+    }
+
+    // Read the next chunk; true once the whole file is in
+    pub fn step(&mut self) -> io::Result<bool> {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        let n = self.file.read(&mut chunk)?;
+        chunk.truncate(n);
+        self.read += n as u64;
+
+        if self.encoding != Encoding::Utf8 {
+            if n == 0 {
+                return Ok(true);
+            }
+            self.saw_c1 |= chunk.iter().any(|b| (0x80..=0x9F).contains(b));
+            self.builder
+                .push(encoding::decode_single_byte(&chunk, self.encoding));
+            return Ok(false);
+        }
+
+        let mut bytes = std::mem::take(&mut self.pending);
+        bytes.extend_from_slice(&chunk);
+        match String::from_utf8(bytes) {
+            Ok(text) => {
+                if !text.is_empty() {
+                    self.builder.push(text);
+                }
+            }
+            // A character cut off by the chunk boundary: keep its first
+            // bytes for the next chunk
+            Err(e) if n > 0 && e.utf8_error().error_len().is_none() => {
+                let valid = e.utf8_error().valid_up_to();
+                let mut bytes = e.into_bytes();
+                self.pending = bytes.split_off(valid);
+                self.builder.push(String::from_utf8(bytes).unwrap());
+            }
+            Err(_) => {
+                self.restart_single_byte()?;
+                return Ok(false);
+            }
+        }
+        Ok(n == 0)
+    }
+
+    // Not UTF-8 after all: start over in the fallback encoding
+    fn restart_single_byte(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.read = 0;
+        self.builder = RopeBuilder::new();
+        self.pending.clear();
+        self.encoding = match self.fallback {
+            Some(Encoding::Utf8) | None => Encoding::Windows1252,
+            Some(encoding) => encoding,
+        };
+        Ok(())
+    }
+
+    pub fn finish(self) -> (Rope, Encoding) {
+        // Without any of the bytes where they differ, the file is as much
+        // Latin-1 as Windows-1252, and Latin-1 is the more likely intent
+        let guessed = matches!(self.fallback, Some(Encoding::Utf8) | None);
+        let encoding = if guessed && self.encoding == Encoding::Windows1252 && !self.saw_c1 {
+            Encoding::Latin1
+        } else {
+            self.encoding
+        };
+        (self.builder.finish(), encoding)
+    }
+}
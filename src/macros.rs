@@ -0,0 +1,71 @@
+// Keyboard macros: keystrokes recorded into a named register and fed back
+// through the normal key handling on replay
+
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyModifiers};
+
+pub type Keys = Vec<(KeyCode, KeyModifiers)>;
+
+// Upper bound on one replay's repeat count, so a typo like `99999a` can't
+// hang the editor
+pub const MAX_REPLAYS: usize = 1000;
+
+#[derive(Default)]
+pub struct Macros {
+    registers: HashMap<char, Keys>,
+    recording: Option<(char, Keys)>,
+    last: Option<char>, // Register most recently recorded or replayed
+    pub replaying: bool,
+}
+
+impl Macros {
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|(register, _)| *register)
+    }
+
+    pub fn start(&mut self, register: char) {
+        self.recording = Some((register, Vec::new()));
+    }
+
+    // Stop recording, returning the register and how many keys it got
+    pub fn stop(&mut self) -> Option<(char, usize)> {
+        let (register, keys) = self.recording.take()?;
+        let len = keys.len();
+        self.registers.insert(register, keys);
+        self.last = Some(register);
+        Some((register, len))
+    }
+
+    pub fn record(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        if let Some((_, keys)) = self.recording.as_mut() {
+            keys.push((code, modifiers));
+        }
+    }
+
+    // Keys for `register`, or the last used one when None
+    pub fn keys(&mut self, register: Option<char>) -> Option<(char, Keys)> {
+        let register = register.or(self.last)?;
+        let keys = self.registers.get(&register)?.clone();
+        self.last = Some(register);
+        Some((register, keys))
+    }
+}
+
+pub fn is_register(c: char) -> bool {
+    c.is_ascii_lowercase()
+}
+
+// Replay prompt input: an optional count, then an optional register, e.g.
+// `a`, `5a` or `5` (last macro again)
+pub fn parse_replay(input: &str) -> Option<(usize, Option<char>)> {
+    let digits = input.chars().take_while(char::is_ascii_digit).count();
+    let count = if digits == 0 { 1 } else { input[..digits].parse().ok()? };
+    let mut rest = input[digits..].chars();
+    let register = match (rest.next(), rest.next()) {
+        (None, _) => None,
+        (Some(c), None) if is_register(c) => Some(c),
+        _ => return None,
+    };
+    Some((count, register))
+}
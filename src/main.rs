@@ -1,440 +1,4047 @@
+mod brackets;
+mod column;
+mod command;
+mod comment;
+mod companion;
+mod config;
+mod diff;
+mod editorconfig;
+mod encoding;
+mod finder;
+mod fuzzy;
+mod history;
+mod indent;
+mod keylog;
+mod line_ending;
+mod loader;
+mod macros;
+mod marks;
+mod mouse;
+mod overlay;
+mod paste;
+mod positions;
+mod prompt;
+mod rope;
+mod script;
+mod session;
+mod status;
+mod ui;
+
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
-    execute, queue,
-    style::{Color, Print, ResetColor, SetForegroundColor},
-    terminal::{self, Clear, ClearType},
     cursor,
+    event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseButton, MouseEvent, MouseEventKind},
+    execute,
+    terminal,
+    tty::IsTty,
 };
-use std::io::{self, stdout, Write};
-use std::rc::Rc;
+use std::cell::RefCell;
+use std::io::{self, stdout, Read, Write};
 use std::fs;
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
 
-// Rope data structure
+use command::{Edit, EditorCommand};
+use config::{Config, IndentSettings, IndentStyle};
+use encoding::Encoding;
+use history::History;
+use keylog::KeyLog;
+use line_ending::LineEndings;
+use loader::FileLoader;
+use macros::Macros;
+use marks::JumpList;
+use mouse::Clicks;
+use overlay::{Overlay, OverlayKind};
+use prompt::{AfterDiscard, Prompt, PromptKind};
+use rope::Rope;
+use session::Session;
+use ui::{Output, Screen};
+
+// Width of the change-marker gutter drawn left of the text
+const GUTTER_WIDTH: u16 = 2;
+
+// How long each frame spends reading while a file streams in
+const LOAD_SLICE: Duration = Duration::from_millis(30);
+
+// How long the visual bell keeps the status line inverted
+const FLASH_TIME: Duration = Duration::from_millis(100);
+
+// Enough text for indent detection to see its usual number of lines
+const INDENT_SAMPLE_CHARS: usize = 64 * 1024;
+
+// Files kept for "Reopen last closed file"
+const MAX_CLOSED: usize = 20;
+
+// Actions between undo checkpoints
+const CHECKPOINT_INTERVAL: usize = 50;
+
+// Undo/Redo action
 #[derive(Clone)]
-enum RopeNode {
-    Leaf(String),
-    Internal {
-        left: Rc<RopeNode>,
-        right: Rc<RopeNode>,
-        weight: usize, // Length of left subtree
-    },
+enum Action {
+    Insert { index: usize, text: UndoText },
+    Delete { index: usize, text: UndoText },
+    Replace { index: usize, old: UndoText, new: UndoText },
 }
 
+// Text an action put in or took out: a copy, or for long text (over
+// `undo_share_size`) a slice of the rope it was in, whose leaves are shared
+// with the buffer rather than held twice
 #[derive(Clone)]
-struct Rope {
-    root: Rc<RopeNode>,
+enum UndoText {
+    Owned(String),
+    Shared(Rope),
 }
 
-impl Rope {
-    fn new() -> Self {
-        Rope {
-            root: Rc::new(RopeNode::Leaf(String::new())),
+impl UndoText {
+    fn len(&self) -> usize {
+        match self {
+            UndoText::Owned(text) => text.len(),
+            UndoText::Shared(rope) => rope.len(),
         }
     }
 
-    fn from_string(s: &str) -> Self {
-        Rope {
-            root: Rc::new(RopeNode::Leaf(s.to_string())),
+    fn insert_into(&self, rope: &Rope, index: usize) -> Rope {
+        match self {
+            UndoText::Owned(text) => rope.insert(index, text),
+            UndoText::Shared(text) => rope.insert_rope(index, text),
         }
     }
 
-    fn len(&self) -> usize {
-        self.total_len(&self.root)
+    // The first `max` characters or so, for showing in the undo tree
+    fn head(&self, max: usize) -> String {
+        match self {
+            UndoText::Owned(text) => text.chars().take(max).collect(),
+            UndoText::Shared(rope) => rope.chars().take(max).collect(),
+        }
     }
-    
-    fn total_len(&self, node: &Rc<RopeNode>) -> usize {
-        match node.as_ref() {
-            RopeNode::Leaf(s) => s.len(),
-            RopeNode::Internal { left, right, .. } => {
-                self.total_len(left) + self.total_len(right)
-            }
+}
+
+impl From<String> for UndoText {
+    fn from(text: String) -> Self {
+        UndoText::Owned(text)
+    }
+}
+
+// Text editor state
+struct Editor {
+    rope: Rope,
+    cursor: usize,
+    scroll_offset: usize, // First line shown on screen
+    view_rows: usize,     // Text rows on screen as of the last render
+    col_offset: usize,    // First screen column shown, for lines wider than the screen
+    view_cols: usize,     // Text columns on screen as of the last render
+    wrapped_line: Option<usize>, // Line shown wrapped over several rows, while the cursor is on it
+    overwrite: bool, // Typing replaces the character under the cursor
+    anchor: Option<usize>, // Fixed end of the selection; the cursor is the moving end
+    history: History<Action>,
+    // The text every CHECKPOINT_INTERVAL actions, with the history node it
+    // was taken at, for jumping back without replaying each action
+    checkpoints: Vec<(usize, Rope)>,
+    filename: Option<String>,
+    encoding: Encoding, // How the file is stored on disk
+    dirty: bool,
+    last_key_time: Instant,
+    status_message: Option<String>,
+    baseline: Rope, // Content as last loaded/saved, for the diff gutter
+    overlay: Option<Overlay>,
+    prompt: Option<Prompt>,
+    should_quit: bool,
+    config: Config,
+    indent: IndentSettings, // Effective settings for the current file
+    indent_detected: bool,  // `indent` came from the file content
+    project: editorconfig::Properties, // From the current file's `.editorconfig` files
+    link: Option<String>, // Status line note when the file is a symlink or has hard links
+    screen: Screen,
+    output: Output,
+    dirty_display: bool, // Something visible changed since the last render
+    loading: Option<FileLoader>,
+    macros: Macros,
+    jumps: JumpList,
+    last_position: Option<usize>, // Cursor before the latest jump, for SwapPosition
+    // Selections (anchor, cursor) that ExpandSelection grew from, and the
+    // one it left, so any other change to the selection can be noticed
+    scopes: Vec<(Option<usize>, usize)>,
+    scoped: Option<(Option<usize>, usize)>,
+    search: Option<String>,       // Last search query
+    highlight_matches: bool,      // Draw every match of `search`
+    // How many matches a query has in a version of the text, and where
+    // they start, kept between frames so they're only searched for again
+    // after an edit or a new query. A rope is the same version only if it
+    // is the very same tree.
+    match_count: RefCell<Option<(String, Rope, usize)>>,
+    match_starts: RefCell<Option<(String, Rope, Vec<usize>)>>,
+    // Gutter markers for a (text, baseline) pair, and the line endings of a
+    // version of the text, so frames don't redo whole-file work
+    gutter: RefCell<Option<ui::Gutter>>,
+    endings: RefCell<Option<(Rope, LineEndings)>>,
+    closed: Vec<PathBuf>,         // Files left this session, most recent last
+    open_at_line: Option<usize>,  // Where to put the cursor once loading finishes
+    // Cursor and view for a session's file once it loads, and what to say
+    // then about the files the session lost
+    session_view: Option<(session::View, Option<String>)>,
+    last_edit: Vec<Edit>,         // Steps of the latest run of edits, for RepeatLastEdit
+    edit_open: bool,              // The next edit step extends `last_edit`
+    repeating_edit: bool,
+    flash_until: Option<Instant>, // Visual bell showing until then
+    ring: bool,                   // Audible bell due with the next frame
+    idle: bool,                   // No input for `hide_cursor_after`, so the cursor is hidden
+    clicks: Clicks,
+    debug_hud: bool,
+    key_log: Option<KeyLog>, // Recent keys shown on screen, when turned on
+    render_time: Duration, // Drawing and writing out the last frame
+}
+
+impl Editor {
+    fn new(config: Config) -> Self {
+        Editor {
+            rope: Rope::new(),
+            cursor: 0,
+            scroll_offset: 0,
+            view_rows: 0,
+            col_offset: 0,
+            view_cols: 0,
+            wrapped_line: None,
+            overwrite: false,
+            anchor: None,
+            history: History::new(),
+            checkpoints: vec![(0, Rope::new())],
+            filename: None,
+            encoding: Encoding::Utf8,
+            dirty: false,
+            last_key_time: Instant::now(),
+            status_message: None,
+            baseline: Rope::new(),
+            overlay: None,
+            prompt: None,
+            should_quit: false,
+            indent: config.indent,
+            indent_detected: false,
+            project: editorconfig::Properties::default(),
+            link: None,
+            config,
+            screen: Screen::new(),
+            output: Output::Stdout(stdout()),
+            dirty_display: true,
+            loading: None,
+            macros: Macros::default(),
+            jumps: JumpList::default(),
+            last_position: None,
+            scopes: Vec::new(),
+            scoped: None,
+            search: None,
+            highlight_matches: false,
+            match_count: RefCell::new(None),
+            match_starts: RefCell::new(None),
+            gutter: RefCell::new(None),
+            endings: RefCell::new(None),
+            closed: Vec::new(),
+            open_at_line: None,
+            session_view: None,
+            last_edit: Vec::new(),
+            edit_open: false,
+            repeating_edit: false,
+            flash_until: None,
+            ring: false,
+            idle: false,
+            clicks: Clicks::default(),
+            debug_hud: false,
+            key_log: None,
+            render_time: Duration::ZERO,
         }
     }
-    
 
-    fn weight(&self, node: &Rc<RopeNode>) -> usize {
-        match node.as_ref() {
-            RopeNode::Leaf(s) => s.len(),
-            RopeNode::Internal { weight, .. } => *weight,
+    fn load_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        let mut loader = FileLoader::open(path.as_ref(), self.config.fallback_encoding)?;
+        while !loader.step()? {}
+        self.finish_loading(loader);
+        Ok(())
+    }
+
+    // Stream a file in over several frames; `continue_loading` does the work
+    fn start_loading(&mut self, path: &Path) -> io::Result<()> {
+        let loader = FileLoader::open(path, self.config.fallback_encoding)?;
+        self.open_at_line = None;
+        self.session_view = None;
+        self.status_message = Some(format!("Loading {}...", path.display()));
+        self.loading = Some(loader);
+        Ok(())
+    }
+
+    // Read chunks for one time slice, then hand back to the main loop
+    fn continue_loading(&mut self) {
+        let Some(loader) = self.loading.as_mut() else {
+            return;
+        };
+        let started = Instant::now();
+        let done = loop {
+            match loader.step() {
+                Ok(true) => break true,
+                Ok(false) if started.elapsed() < LOAD_SLICE => {}
+                Ok(false) => break false,
+                Err(e) => {
+                    self.status_message = Some(format!("Open failed: {}", e));
+                    self.loading = None;
+                    return;
+                }
+            }
+        };
+        if done {
+            let loader = self.loading.take().unwrap();
+            self.finish_loading(loader);
+        } else {
+            self.status_message = Some(format!(
+                "Loading {}: {}% (Esc to cancel)",
+                loader.path.display(),
+                loader.percent()
+            ));
         }
+        self.dirty_display = true;
     }
 
-    fn concat(left: Rope, right: Rope) -> Rope {
-        let weight = left.len();
-        Rope {
-            root: Rc::new(RopeNode::Internal {
-                left: left.root,
-                right: right.root,
-                weight,
-            }),
+    fn finish_loading(&mut self, loader: FileLoader) {
+        let path = loader.path.clone();
+        let (rope, encoding) = loader.finish();
+        self.replace_buffer(rope, encoding, Some(&path));
+        if let Some(line) = self.open_at_line.take() {
+            self.cursor = self.rope.line_col_to_index(line, 0);
+        }
+        if let Some((view, warning)) = self.session_view.take() {
+            self.cursor = self.clamp_offset(self.rope.line_col_to_index(view.line, view.col));
+            self.scroll_offset = view.scroll.min(view.line);
+            if warning.is_some() {
+                self.status_message = warning;
+            }
         }
     }
 
-    fn split(&self, index: usize) -> (Rope, Rope) {
-        let index = index.min(self.len());
-        let (left, right) = self.split_node(&self.root, index);
-        (Rope { root: left }, Rope { root: right })
+    // The whole of stdin becomes an unnamed buffer, for `cat file | rope-editor -`
+    fn load_stdin(&mut self) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes)?;
+        if encoding::is_binary(&bytes[..bytes.len().min(encoding::BINARY_SAMPLE)]) {
+            return Err(encoding::binary_error());
+        }
+        let (text, encoding) = encoding::decode(bytes, self.config.fallback_encoding);
+        self.replace_buffer(Rope::from_string(&text), encoding, None);
+        Ok(())
     }
 
-    fn split_node(&self, node: &Rc<RopeNode>, index: usize) -> (Rc<RopeNode>, Rc<RopeNode>) {
-        match node.as_ref() {
-            RopeNode::Leaf(s) => {
-                let index = index.min(s.len());
-                let (left, right) = s.split_at(index);
-                (
-                    Rc::new(RopeNode::Leaf(left.to_string())),
-                    Rc::new(RopeNode::Leaf(right.to_string())),
-                )
+    // Fresh state for text that just came in from `path`, or from outside
+    // any file when None
+    fn replace_buffer(&mut self, rope: Rope, encoding: Encoding, path: Option<&Path>) {
+        self.encoding = encoding;
+        self.rope = rope;
+        self.baseline = self.rope.clone();
+        self.cursor = 0;
+        self.scroll_offset = 0;
+        self.col_offset = 0;
+        self.anchor = None;
+        self.jumps.clear();
+        self.last_position = None;
+        self.clear_history();
+        self.filename = path.map(|path| path.to_string_lossy().into_owned());
+        self.link = path.and_then(link_label);
+        self.project = match path {
+            Some(path) if self.config.editorconfig => editorconfig::for_file(path),
+            _ => editorconfig::Properties::default(),
+        };
+        self.indent = self.project.indent(match path {
+            Some(path) => self.config.indent_for(path),
+            None => self.config.indent,
+        });
+        self.indent_detected = false;
+        if self.config.detect_indent {
+            // The top of the file is all detection looks at anyway
+            let head: String = self.rope.chars().take(INDENT_SAMPLE_CHARS).collect();
+            if let Some(detected) = indent::detect(&head, self.indent.tab_width) {
+                self.indent = detected;
+                self.indent_detected = true;
             }
-            RopeNode::Internal { left, right, weight } => {
-                if index <= *weight {
-                    let (ll, lr) = self.split_node(left, index);
-                    (
-                        ll,
-                        Rc::new(RopeNode::Internal {
-                            left: lr.clone(),
-                            right: right.clone(),
-                            weight: self.total_len(&lr),
-                        }),
-                    )
-                } else {
-                    let (rl, rr) = self.split_node(right, index - weight);
-                    (
-                        Rc::new(RopeNode::Internal {
-                            left: left.clone(),
-                            right: rl.clone(),
-                            weight: self.total_len(&left),
-                        }),
-                        rr,
-                    )
-                }
+        }
+        if let (true, Some(path)) = (self.config.remember_position, path) {
+            // A position past the end (the file shrank) lands on the nearest
+            // line end or the end of the file
+            if let Some((line, col)) = positions::lookup(path) {
+                self.cursor = self.rope.line_col_to_index(line, col);
             }
         }
+        self.dirty = false;
+        let endings = LineEndings::count(self.rope.chars());
+        self.status_message = Some(if endings.is_mixed() {
+            format!("Warning: mixed line endings ({})", endings.describe())
+        } else if encoding == Encoding::Utf8 {
+            "File loaded successfully!".to_string()
+        } else {
+            format!("File loaded as {} (not valid UTF-8)", encoding.name())
+        });
     }
-    
 
-    fn insert(&self, index: usize, text: &str) -> Rope {
-        let (left, right) = self.split(index);
-        let middle = Rope::from_string(text);
-        Rope::concat(Rope::concat(left, middle), right)
+    fn save_file(&mut self) -> io::Result<()> {
+        match self.filename.clone() {
+            Some(filename) => self.save_file_as(filename),
+            None => Err(io::Error::other("No filename specified")),
+        }
     }
 
-    fn delete(&self, start: usize, len: usize) -> Rope {
-        let (left, rest) = self.split(start);
-        let rest_len = rest.len();
-        let len = len.min(rest_len);
-        let (_, right) = rest.split(len);
-        Rope::concat(left, right)
+    // The buffer takes the new name only once it's written there
+    fn save_file_as(&mut self, filename: String) -> io::Result<()> {
+        let path = self.write_file(Path::new(&filename), &self.rope)?;
+        self.filename = Some(filename);
+        self.link = link_label(&path);
+        self.baseline = self.rope.clone();
+        self.dirty = false;
+        Ok(())
     }
 
-    fn to_string(&self) -> String {
-        let mut result = String::new();
-        self.collect(&self.root, &mut result);
-        result
+    // Written to a temporary file beside the real one and renamed over it,
+    // so a failed write (a full disk, a character the encoding can't hold)
+    // leaves the old file as it was. A symlink stays a symlink and its
+    // target is what gets replaced, unless follow_symlinks is off. Gives
+    // back the path actually written.
+    fn write_file(&self, filename: &Path, rope: &Rope) -> io::Result<PathBuf> {
+        let path = match self.config.follow_symlinks {
+            true => fs::canonicalize(filename).unwrap_or_else(|_| filename.to_path_buf()),
+            false => filename.to_path_buf(),
+        };
+        if hard_links(&path) > 1 {
+            // A rename would leave the other names on the old contents,
+            // so hard-linked files are written in place instead
+            self.write_chunks(rope, &path, &path)?;
+        } else {
+            let temp = temp_path(&path);
+            if let Err(e) = self.write_chunks(rope, &temp, &path).and_then(|()| fs::rename(&temp, &path)) {
+                let _ = fs::remove_file(&temp);
+                return Err(e);
+            }
+        }
+        Ok(path)
     }
 
-    fn collect(&self, node: &Rc<RopeNode>, result: &mut String) {
-        match node.as_ref() {
-            RopeNode::Leaf(s) => result.push_str(s),
-            RopeNode::Internal { left, right, .. } => {
-                self.collect(left, result);
-                self.collect(right, result);
+    // A leaf at a time, so a big file isn't copied whole first. The new
+    // file gets the permissions of the one it replaces.
+    fn write_chunks(&self, rope: &Rope, temp: &Path, original: &Path) -> io::Result<()> {
+        let file = fs::File::create(temp)?;
+        if let Ok(metadata) = fs::metadata(original) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        let mut out = io::BufWriter::new(file);
+        for chunk in rope.chunks() {
+            match self.encoding {
+                Encoding::Utf8 => out.write_all(chunk.as_bytes())?,
+                encoding => out.write_all(&encoding::encode(chunk, encoding)?)?,
             }
         }
+        out.flush()?;
+        out.get_ref().sync_all()
     }
 
-    fn char_at(&self, index: usize) -> Option<char> {
-        self.get_char(&self.root, index)
+    // Replace the whole buffer, as a new buffer does. This is a fresh start,
+    // not an edit: the cursor goes back to the top and the undo history is
+    // cleared.
+    fn set_content(&mut self, text: &str) {
+        self.rope = Rope::from_string(text);
+        self.cursor = 0;
+        self.scroll_offset = 0;
+        self.col_offset = 0;
+        self.anchor = None;
+        self.jumps.clear();
+        self.last_position = None;
+        self.clear_history();
+        self.dirty = self.rope != self.baseline;
     }
 
-    fn get_char(&self, node: &Rc<RopeNode>, index: usize) -> Option<char> {
-        match node.as_ref() {
-            RopeNode::Leaf(s) => s.chars().nth(index),
-            RopeNode::Internal { left, right, weight } => {
-                if index < *weight {
-                    self.get_char(left, index)
-                } else {
-                    self.get_char(right, index - weight)
-                }
-            }
+    fn content(&self) -> String {
+        self.rope.to_string()
+    }
+
+    // Both from 0; the column counts characters
+    fn cursor_line_col(&self) -> (usize, usize) {
+        self.rope.index_to_line_col(self.cursor)
+    }
+
+    // Keep stored positions on the same text after `removed` bytes at
+    // `start` became `inserted` bytes
+    fn shift_marks(&mut self, start: usize, removed: usize, inserted: usize) {
+        self.jumps.adjust(start, removed, inserted);
+        if let Some(offset) = &mut self.last_position {
+            *offset = marks::adjust_offset(*offset, start, removed, inserted);
         }
     }
-}
 
-// Undo/Redo action
-#[derive(Clone)]
-enum Action {
-    Insert { index: usize, text: String },
-    Delete { index: usize, text: String },
-}
+    // Move somewhere far enough away that getting back should be one key
+    fn jump_to(&mut self, offset: usize) {
+        self.jumps.record(self.cursor);
+        self.last_position = Some(self.cursor);
+        self.cursor = offset;
+        self.status_message = None;
+    }
 
-// Text editor state
-struct Editor {
-    rope: Rope,
-    cursor: usize,
-    undo_stack: Vec<Action>,
-    redo_stack: Vec<Action>,
-    filename: Option<String>,
-    dirty: bool,
-    last_key_time: Instant,
-    status_message: Option<String>,
-}
+    // Jump list entries can be stale (e.g. the text shrank), so land on the
+    // nearest valid position
+    fn clamp_offset(&self, offset: usize) -> usize {
+        self.rope.floor_char_boundary(offset)
+    }
 
-impl Editor {
-    fn new() -> Self {
-        Editor {
-            rope: Rope::new(),
-            cursor: 0,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
-            filename: None,
-            dirty: false,
-            last_key_time: Instant::now(),
-            status_message: None,
+    fn jump_back(&mut self) {
+        match self.jumps.back(self.cursor) {
+            Some(offset) => self.move_to_jumped(offset),
+            None => self.status_message = Some("No earlier position".to_string()),
         }
     }
 
-    fn load_file<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
-        let content = fs::read_to_string(&path)?;
-        self.rope = Rope::from_string(&content);
-        self.filename = Some(path.as_ref().to_string_lossy().into_owned());
-        self.dirty = false;
-        self.status_message = Some("File loaded successfully!".to_string());
-        Ok(())
+    fn jump_forward(&mut self) {
+        match self.jumps.forward() {
+            Some(offset) => self.move_to_jumped(offset),
+            None => self.status_message = Some("No later position".to_string()),
+        }
     }
 
-    fn save_file(&mut self) -> io::Result<()> {
-        if let Some(filename) = &self.filename {
-            fs::write(filename, self.rope.to_string())?;
-            self.dirty = false;
-            Ok(())
-        } else {
-            Err(io::Error::new(io::ErrorKind::Other, "No filename specified"))
+    // Toggle between where the cursor is and where it was before the last
+    // jump; pressing it again comes straight back
+    fn swap_position(&mut self) {
+        match self.last_position {
+            Some(offset) => self.move_to_jumped(offset),
+            None => self.status_message = Some("No previous position".to_string()),
+        }
+    }
+
+    fn move_to_jumped(&mut self, offset: usize) {
+        self.last_position = Some(self.cursor);
+        self.cursor = self.clamp_offset(offset);
+    }
+
+    // Swap `start..end` for `text` as a single undo step, leaving the cursor
+    // after the new text
+    fn replace_range(&mut self, start: usize, end: usize, text: &str) {
+        let before = self.rope.clone();
+        self.rope = self.rope.replace_range(start, end, text);
+        self.shift_marks(start, end - start, text.len());
+        self.record_action(Action::Replace {
+            index: start,
+            old: self.undo_text(&before, start..end),
+            new: self.undo_text(&self.rope, start..start + text.len()),
+        });
+        self.cursor = start + text.len();
+        self.dirty = true;
+        self.status_message = None;
+    }
+
+    // Typed text; in overwrite mode it takes the place of the character under
+    // the cursor, except at the end of a line where there's nothing to replace
+    fn type_text(&mut self, text: &str) {
+        match self.char_after() {
+            Some(under) if self.overwrite && under != '\n' && insertable(text) => {
+                self.replace_range(self.cursor, self.cursor + under.len_utf8(), text);
+            }
+            _ => self.insert(text),
         }
     }
 
     fn insert(&mut self, text: &str) {
-        if text.chars().all(|c| c.is_ascii_graphic() || c.is_whitespace() || c == '\n') {
+        if insertable(text) {
             self.rope = self.rope.insert(self.cursor, text);
-            self.undo_stack.push(Action::Insert {
+            self.shift_marks(self.cursor, 0, text.len());
+            self.record_action(Action::Insert {
                 index: self.cursor,
-                text: text.to_string(),
+                text: text.to_string().into(),
             });
-            self.redo_stack.clear();
             self.cursor += text.len();
             self.dirty = true;
             self.status_message = None;
         }
     }
 
+    // A paste arrives as one string, so it goes in as one undoable insert
+    fn paste(&mut self, text: &str) {
+        if let Some(prompt) = self.prompt.as_mut() {
+            // Prompts are one line; keep the first
+            let line = text.lines().next().unwrap_or("");
+            if !prompt.is_single_key() {
+                prompt.input.extend(line.chars().filter(|c| !c.is_control()));
+            }
+            return;
+        }
+        if let Some(overlay) = self.overlay.as_mut() {
+            let line = text.lines().next().unwrap_or("");
+            line.chars().filter(|c| !c.is_control()).for_each(|c| overlay.push_char(c));
+            return;
+        }
+
+        let selected = self.take_selection();
+        self.record_edit(Edit::Paste(text.to_string()));
+        let text = paste::clean(text, self.config.normalize_paste, self.config.trim_paste);
+        match selected {
+            Some(range) => self.replace_range(range.start, range.end, &text),
+            None => self.insert_block(&text),
+        }
+    }
+
+    // Insert a whole block of text as one undo step, e.g. a paste
+    fn insert_block(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        self.rope = self.rope.insert(self.cursor, text);
+        self.shift_marks(self.cursor, 0, text.len());
+        self.record_action(Action::Insert {
+            index: self.cursor,
+            text: self.undo_text(&self.rope, self.cursor..self.cursor + text.len()),
+        });
+        self.cursor += text.len();
+        self.dirty = true;
+        self.status_message = None;
+    }
+
+    // Text in at a line and column, both from 0, as one undoable insert,
+    // e.g. from a script. The cursor ends up after it, as when typing.
+    fn insert_at_line_col(&mut self, line: usize, col: usize, text: &str) {
+        if !insertable(text) {
+            return;
+        }
+        let index = self.rope.line_col_to_index(line, col);
+        self.rope = self.rope.insert_str_at_line_col(line, col, text);
+        self.shift_marks(index, 0, text.len());
+        self.record_action(Action::Insert {
+            index,
+            text: self.undo_text(&self.rope, index..index + text.len()),
+        });
+        self.cursor = index + text.len();
+        self.dirty = true;
+        self.status_message = None;
+    }
+
+    // Like vim's `:r`. The text is decoded like a file being opened and
+    // takes on the buffer's line endings.
+    fn insert_file(&mut self, path: &str) {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                self.status_message = Some(format!("Can't read {}: {}", path, e));
+                return;
+            }
+        };
+        let (text, _) = encoding::decode(bytes, self.config.fallback_encoding);
+        let endings = LineEndings::count(self.rope.chars());
+        let text = line_ending::normalize(&text, endings.crlf > endings.lf);
+        self.insert_block(&text);
+        self.status_message = Some(format!("Inserted {} ({})", path, status::format_size(text.len())));
+    }
+
+    // Save a copy of the selection, or the whole buffer without one,
+    // leaving the buffer's own file and modified state alone
+    fn write_selection(&mut self, path: &Path, overwrite: bool) {
+        if !overwrite && path.exists() {
+            self.prompt = Some(Prompt::new(
+                PromptKind::ConfirmOverwrite(path.to_path_buf()),
+                "File exists. Overwrite? (y/n)",
+            ));
+            return;
+        }
+        let range = self.selection().unwrap_or(0..self.rope.len());
+        let written = self.write_file(path, &self.rope.slice(range.start, range.end));
+        self.status_message = Some(match written {
+            Ok(_) => format!("Wrote {} to {}", status::format_size(range.len()), path.display()),
+            Err(e) => format!("Write failed: {}", e),
+        });
+    }
+
+    // Only the few bytes around the cursor are copied, so moving through a
+    // huge line stays cheap
+    fn char_before(&self) -> Option<char> {
+        self.rope.substring(self.cursor.saturating_sub(4), self.cursor).chars().next_back()
+    }
+
+    fn char_after(&self) -> Option<char> {
+        self.rope.substring(self.cursor, self.cursor + 4).chars().next()
+    }
+
+    // Byte lengths of the characters either side of the cursor (0 at the
+    // ends), so motion and deletion never split a multi-byte character
+    fn char_len_before(&self) -> usize {
+        self.char_before().map_or(0, char::len_utf8)
+    }
+
+    fn char_len_after(&self) -> usize {
+        self.char_after().map_or(0, char::len_utf8)
+    }
+
+    // With `smart_backspace`, a cursor in a line's leading spaces deletes back
+    // to the previous tab stop; anywhere else it's one character
+    fn backspace_len(&self) -> usize {
+        match self.indent_stop_before() {
+            Some(len) if self.config.smart_backspace => len,
+            _ => self.char_len_before(),
+        }
+    }
+
+    // Distances to the tab stops either side of a cursor in a line's leading
+    // spaces, treating each indent level as one step. None anywhere else.
+    fn indent_stop_before(&self) -> Option<usize> {
+        let before = self.rope.substring(self.line_start(), self.cursor);
+        if before.is_empty() || !before.bytes().all(|b| b == b' ') {
+            return None;
+        }
+        let width = self.indent.tab_width;
+        let col = before.len();
+        Some(col - (col - 1) / width * width)
+    }
+
+    fn indent_stop_after(&self) -> Option<usize> {
+        let start = self.line_start();
+        let line = self.rope.substring(start, self.line_end());
+        let (before, after) = line.split_at(self.cursor - start);
+        let spaces = after.bytes().take_while(|&b| b == b' ').count();
+        if spaces == 0 || !before.bytes().all(|b| b == b' ') {
+            return None;
+        }
+        let width = self.indent.tab_width;
+        Some((width - before.len() % width).min(spaces))
+    }
+
     fn delete(&mut self) {
-        if self.cursor > 0 {
-            let deleted_char = self.rope.char_at(self.cursor - 1).unwrap_or_default().to_string();
-            self.rope = self.rope.delete(self.cursor - 1, 1);
-            self.cursor -= 1;
-            self.undo_stack.push(Action::Delete {
+        if self.cursor == 0 {
+            self.bell();
+        } else {
+            let len = self.backspace_len();
+            let deleted_char = self.rope.substring(self.cursor - len, self.cursor);
+            self.rope = self.rope.delete_range(self.cursor - len, self.cursor);
+            self.shift_marks(self.cursor - len, len, 0);
+            self.cursor -= len;
+            self.record_action(Action::Delete {
                 index: self.cursor,
-                text: deleted_char,
+                text: deleted_char.into(),
             });
-            self.redo_stack.clear();
             self.dirty = true;
             self.status_message = None;
         }
     }
 
-    fn undo(&mut self) {
-        if let Some(action) = self.undo_stack.pop() {
-            match action {
-                Action::Insert { index, text } => {
-                    self.rope = self.rope.delete(index, text.len());
-                    self.cursor = index;
-                    self.redo_stack.push(Action::Insert { index, text });
-                }
-                Action::Delete { index, text } => {
-                    self.rope = self.rope.insert(index, &text);
-                    self.cursor = index + text.len();
-                    self.redo_stack.push(Action::Delete { index, text });
-                }
+    // A new action goes after the current one in the history tree, as a
+    // new branch if there's undone history past it
+    fn record_action(&mut self, action: Action) {
+        self.history.push(action);
+        if self.config.undo_checkpoints == 0 {
+            return;
+        }
+        // A checkpoint at most every CHECKPOINT_INTERVAL actions along the
+        // way to here
+        let current = self.history.current();
+        let recent: Vec<usize> = self.history.ancestors(current).take(CHECKPOINT_INTERVAL).collect();
+        if recent.len() == CHECKPOINT_INTERVAL && !self.checkpoints.iter().any(|(at, _)| recent.contains(at)) {
+            self.checkpoints.push((current, self.rope.clone()));
+            if self.checkpoints.len() > self.config.undo_checkpoints {
+                self.checkpoints.remove(0);
+            }
+        }
+    }
+
+    // `range` of `rope` for an undo action to keep
+    fn undo_text(&self, rope: &Rope, range: Range<usize>) -> UndoText {
+        match range.len() > self.config.undo_share_size {
+            true => UndoText::Shared(rope.slice(range.start, range.end)),
+            false => UndoText::Owned(rope.substring(range.start, range.end)),
+        }
+    }
+
+    // The text as it is now is where history starts
+    fn clear_history(&mut self) {
+        self.history.clear();
+        self.checkpoints = vec![(self.history.current(), self.rope.clone())];
+    }
+
+    // Apply an action, or take it back, moving the cursor and marks with it
+    fn apply_action(&mut self, action: &Action, forward: bool) {
+        let (index, removed, inserted) = match (action, forward) {
+            (Action::Insert { index, text }, true) | (Action::Delete { index, text }, false) => {
+                self.rope = text.insert_into(&self.rope, *index);
+                (*index, 0, text.len())
             }
+            (Action::Insert { index, text }, false) | (Action::Delete { index, text }, true) => {
+                self.rope = self.rope.delete_range(*index, *index + text.len());
+                (*index, text.len(), 0)
+            }
+            (Action::Replace { index, old, new }, true) => {
+                self.rope = new.insert_into(&self.rope.delete_range(*index, *index + old.len()), *index);
+                (*index, old.len(), new.len())
+            }
+            (Action::Replace { index, old, new }, false) => {
+                self.rope = old.insert_into(&self.rope.delete_range(*index, *index + new.len()), *index);
+                (*index, new.len(), old.len())
+            }
+        };
+        self.shift_marks(index, removed, inserted);
+        self.cursor = index + inserted;
+    }
+
+    // Straight back to the latest checkpoint before the current text. The
+    // actions skipped can be redone as if undone one at a time, but the
+    // rope is the checkpoint's, so none of them is replayed.
+    fn revert_to_checkpoint(&mut self) {
+        let current = self.history.current();
+        let found = self.history.ancestors(current).skip(1).find_map(|node| {
+            self.checkpoints.iter().find(|(at, _)| *at == node).cloned()
+        });
+        let Some((target, rope)) = found else {
+            self.status_message = Some("No checkpoint to revert to".to_string());
+            self.bell();
+            return;
+        };
+        let (undone, _) = self.history.go_to(target);
+        for action in &undone {
+            let (index, removed, inserted) = match action {
+                Action::Insert { index, text } => (*index, text.len(), 0),
+                Action::Delete { index, text } => (*index, 0, text.len()),
+                Action::Replace { index, old, new } => (*index, new.len(), old.len()),
+            };
+            self.shift_marks(index, removed, inserted);
+            self.cursor = index + inserted;
+        }
+        self.rope = rope;
+        self.dirty = true;
+        self.scroll_to_cursor();
+        self.status_message = Some(format!("Reverted {} actions to a checkpoint", undone.len()));
+    }
+
+    fn undo(&mut self) {
+        if let Some(action) = self.history.undo() {
+            self.apply_action(&action, false);
             self.dirty = true;
+            self.scroll_to_cursor();
             self.status_message = Some("Undo performed".to_string());
         } else {
             self.status_message = Some("Nothing to undo".to_string());
+            self.bell();
         }
     }
 
     fn redo(&mut self) {
-        if let Some(action) = self.redo_stack.pop() {
-            match action {
-                Action::Insert { index, text } => {
-                    self.rope = self.rope.insert(index, &text);
-                    self.cursor = index + text.len();
-                    self.undo_stack.push(Action::Insert { index, text });
-                }
-                Action::Delete { index, text } => {
-                    self.rope = self.rope.delete(index, text.len());
-                    self.cursor = index;
-                    self.undo_stack.push(Action::Delete { index, text });
-                }
-            }
+        if let Some(action) = self.history.redo() {
+            self.apply_action(&action, true);
             self.dirty = true;
+            self.scroll_to_cursor();
             self.status_message = Some("Redo performed".to_string());
         } else {
             self.status_message = Some("Nothing to redo".to_string());
+            self.bell();
+        }
+    }
+
+    // Undo and redo along the tree until the text is as it was after
+    // `node`
+    fn go_to_history(&mut self, node: usize) {
+        let (undo, redo) = self.history.go_to(node);
+        for action in &undo {
+            self.apply_action(action, false);
+        }
+        for action in &redo {
+            self.apply_action(action, true);
+        }
+        if !undo.is_empty() || !redo.is_empty() {
+            self.dirty = true;
+            self.scroll_to_cursor();
+        }
+    }
+
+    // Over to the branch beside the one the last action is on, where an
+    // undo followed by a different edit left the other
+    fn switch_branch(&mut self, forward: bool) {
+        if let Some(node) = self.history.sibling(forward) {
+            self.go_to_history(node);
+            self.status_message = Some(format!("Switched to another branch, {} actions in", self.history.depth()));
+        } else {
+            self.status_message = Some("No other branch here".to_string());
+            self.bell();
         }
     }
 
+    // Left/right motion stops at line boundaries unless `wrap_motion` is on
     fn move_cursor_left(&mut self) {
-        if self.cursor > 0 {
-            self.cursor -= 1;
+        if self.cursor == 0 || (!self.config.wrap_motion && self.char_before() == Some('\n')) {
+            self.bell();
+        } else {
+            self.cursor -= match self.indent_stop_before() {
+                Some(len) if self.config.soft_tab_motion => len,
+                _ => self.char_len_before(),
+            };
+            // Combining marks go with the character they're drawn over
+            while matches!(self.char_after(), Some(c) if column::is_zero_width(c)) && self.cursor > 0 {
+                self.cursor -= self.char_len_before();
+            }
             self.status_message = None;
         }
     }
 
     fn move_cursor_right(&mut self) {
-        if self.cursor < self.rope.len() {
-            self.cursor += 1;
+        if self.cursor == self.rope.len() || (!self.config.wrap_motion && self.char_after() == Some('\n')) {
+            self.bell();
+        } else {
+            self.cursor += match self.indent_stop_after() {
+                Some(len) if self.config.soft_tab_motion => len,
+                _ => self.char_len_after(),
+            };
+            while matches!(self.char_after(), Some(c) if column::is_zero_width(c)) {
+                self.cursor += self.char_len_after();
+            }
             self.status_message = None;
         }
     }
 
-    fn render(&self) -> io::Result<()> {
-        let content = self.rope.to_string();
-        let (_term_width, term_height) = terminal::size()?;
-        let mut stdout = stdout();
-
-        queue!(stdout, Clear(ClearType::All), cursor::MoveTo(0, 0))?;
-
-        let lines: Vec<&str> = content.split('\n').collect();
-        // for (i, line) in lines.iter().take(term_height as usize - 1).enumerate() {
-        //     queue!(stdout, cursor::MoveTo(0, i as u16), Print(line))?;
-        // }
-
-        let cursor_line = content[..self.cursor].chars().filter(|&c| c == '\n').count();
-        let cursor_col = content[..self.cursor]
-            .lines()
-            .last()
-            .map(|l| l.chars().count())
-            .unwrap_or(0);
-
-        
-        use crossterm::style::{Attribute, SetAttribute, Print, Stylize};
-
-        for (i, line) in lines.iter().enumerate().take(term_height as usize - 1) {
-            queue!(stdout, cursor::MoveTo(0, i as u16))?;
-        
-            if i == cursor_line {
-                let mut chars = line.chars().collect::<Vec<_>>();
-                let col = cursor_col.min(chars.len());
-        
-                for (j, ch) in chars.iter().enumerate() {
-                    if j == col {
-                        queue!(
-                            stdout,
-                            SetAttribute(Attribute::Underlined),
-                            Print(ch),
-                            SetAttribute(Attribute::NoUnderline)
-                        )?;
-                    } else {
-                        queue!(stdout, Print(ch))?;
-                    }
-                }
-        
-                // Underline a space if cursor is at end of line
-                if col == chars.len() {
-                    queue!(
-                        stdout,
-                        SetAttribute(Attribute::Underlined),
-                        SetForegroundColor(Color::Cyan),
-                        Print(" "),
-                        SetAttribute(Attribute::NoUnderline)
-                    )?;
+    // Same column on the line above/below, or the end of that line if it's
+    // shorter
+    fn move_cursor_vertically(&mut self, down: bool) {
+        let (line, col) = self.cursor_line_col();
+        let (last_line, _) = self.rope.index_to_line_col(self.rope.len());
+        let target = if down {
+            if line == last_line {
+                self.bell();
+                return;
+            }
+            line + 1
+        } else {
+            match line.checked_sub(1) {
+                Some(line) => line,
+                None => {
+                    self.bell();
+                    return;
                 }
-        
-            } else {
-                queue!(stdout, Print(line))?;
             }
+        };
+        self.move_cursor_to_line(line, col, target);
+        self.status_message = None;
+    }
+
+    // Keep the screen column rather than the character count, so moving
+    // past lines indented with tabs doesn't make the cursor jump sideways
+    fn move_cursor_to_line(&mut self, line: usize, col: usize, target: usize) {
+        let text = |line: usize| {
+            let range = self.rope.line_range(line);
+            self.rope.substring(range.start, range.end)
+        };
+        let tab_width = self.indent.tab_width;
+        let x = column::display_col(&text(line), col, tab_width);
+        let col = column::char_col_at(&text(target), x, tab_width);
+        self.cursor = self.rope.line_col_to_index(target, col);
+    }
+
+    // Furthest the view scrolls: until the last line is at the bottom of
+    // the screen, or with scroll_past_end, at the top
+    fn max_scroll(&self) -> usize {
+        let (last_line, _) = self.rope.index_to_line_col(self.rope.len());
+        match self.config.scroll_past_end {
+            true => last_line,
+            false => (last_line + 1).saturating_sub(self.view_rows),
         }
-        
+    }
 
+    // Scroll so the cursor's line is on screen row `row`, as near as the
+    // ends of the file and `scrolloff` allow, like vim's zz/zt/zb
+    fn place_cursor_line(&mut self, row: usize) {
+        let (line, _) = self.cursor_line_col();
+        let margin = self.config.scrolloff.min(self.view_rows.saturating_sub(1) / 2);
+        let row = row.clamp(margin, self.view_rows.saturating_sub(1 + margin).max(margin));
+        self.scroll_offset = line.saturating_sub(row).min(self.max_scroll());
+    }
 
-       
+    // Move the view a line, leaving the cursor where it is unless it would
+    // leave the screen (or come within `scrolloff` of its edge), in which
+    // case it moves just far enough to stay
+    fn scroll_view(&mut self, down: bool) {
+        let (last_line, _) = self.rope.index_to_line_col(self.rope.len());
+        let top = match down {
+            true if self.scroll_offset < self.max_scroll() => self.scroll_offset + 1,
+            false if self.scroll_offset > 0 => self.scroll_offset - 1,
+            _ => return self.bell(),
+        };
+        self.scroll_offset = top;
+        if self.view_rows == 0 {
+            return;
+        }
+        let margin = self.config.scrolloff.min((self.view_rows - 1) / 2);
+        let bottom = top + self.view_rows - 1;
+        let (line, col) = self.cursor_line_col();
+        let target = if top > 0 && line < top + margin {
+            top + margin
+        } else if last_line > bottom && line + margin > bottom {
+            bottom - margin
+        } else {
+            line
+        };
+        if target != line {
+            self.move_cursor_to_line(line, col, target.min(last_line));
+        }
+    }
 
-        queue!(stdout, cursor::MoveTo(cursor_col as u16, cursor_line as u16))?;
+    // To the end of the current or next word
+    fn move_word_right(&mut self) {
+        let rest = self.rope.chunks_from(self.cursor).flat_map(str::chars);
+        let (mut offset, mut in_word) = (0, false);
+        for c in rest {
+            if (c == '\n' && !self.config.wrap_motion) || (in_word && !is_word_char(c)) {
+                break;
+            }
+            in_word |= is_word_char(c);
+            offset += c.len_utf8();
+        }
+        self.cursor += offset;
+        self.status_message = None;
+    }
 
-        let status = self.status_message.as_deref().unwrap_or("");
-        queue!(
-            stdout,
-            cursor::MoveTo(0, term_height - 1),
-            SetForegroundColor(Color::Cyan),
-            Print(format!(
-                "File: {} | Cursor: {} | {} | {}",
-                self.filename.as_deref().unwrap_or("Untitled"),
-                self.cursor,
-                if self.dirty { "[Modified]" } else { "" },
-                status
-            )),
-            ResetColor
-        )?;
+    // To the start of the current or previous word
+    fn move_word_left(&mut self) {
+        let before = self.rope.chunks_before(self.cursor).flat_map(|chunk| chunk.chars().rev());
+        let (mut back, mut word_start) = (0, None);
+        for c in before {
+            if (c == '\n' && !self.config.wrap_motion) || (word_start.is_some() && !is_word_char(c)) {
+                break;
+            }
+            back += c.len_utf8();
+            if is_word_char(c) {
+                word_start = Some(self.cursor - back);
+            }
+        }
+        // With no word to reach, the start of the line (or buffer)
+        self.cursor = word_start.unwrap_or(self.cursor - back);
+        self.status_message = None;
+    }
 
-        stdout.flush()?;
-        Ok(())
+    // Like vim's `{` and `}`: past any blank lines, then past the paragraph,
+    // to the start of the blank line beyond it, or the end of the buffer
+    fn move_paragraph(&mut self, down: bool) {
+        let content = self.rope.to_string();
+        let lines = line_ranges(&content);
+        let current = lines.iter().position(|line| self.cursor <= line.end).unwrap_or(0);
+        let blank = |i: usize| content[lines[i].clone()].trim().is_empty();
+        let mut i = current;
+        self.cursor = if down {
+            while i < lines.len() && blank(i) {
+                i += 1;
+            }
+            while i < lines.len() && !blank(i) {
+                i += 1;
+            }
+            lines.get(i).map_or(content.len(), |line| line.start)
+        } else {
+            while i > 0 && blank(i) {
+                i -= 1;
+            }
+            while i > 0 && !blank(i) {
+                i -= 1;
+            }
+            if blank(i) { lines[i].start } else { 0 }
+        };
+        self.status_message = None;
     }
-}
 
+    // The lines around the cursor up to the blank lines either side, with
+    // the last one's newline; on a blank line, the run of blank lines
+    fn select_paragraph(&mut self) {
+        let content = self.rope.to_string();
+        let lines = line_ranges(&content);
+        let current = lines.iter().position(|line| self.cursor <= line.end).unwrap_or(0);
+        let blank = |i: usize| content[lines[i].clone()].trim().is_empty();
+        let kind = blank(current);
+        let (mut first, mut last) = (current, current);
+        while first > 0 && blank(first - 1) == kind {
+            first -= 1;
+        }
+        while last + 1 < lines.len() && blank(last + 1) == kind {
+            last += 1;
+        }
+        let end = lines.get(last + 1).map_or(content.len(), |line| line.start);
+        self.anchor = Some(lines[first].start);
+        self.cursor = end;
+    }
 
-fn main() -> io::Result<()> {
-    let mut editor = Editor::new();
-    if let Some(filename) = std::env::args().nth(1) {
-        editor.load_file(filename)?;
+    // Grow the selection to the next scope out: the word, the line(s), the
+    // inside of the brackets around it, the brackets too, and at last the
+    // whole buffer
+    fn expand_selection(&mut self) {
+        if self.scoped != Some((self.anchor, self.cursor)) {
+            // The selection was changed some other way since
+            self.scopes.clear();
+        }
+        let content = self.rope.to_string();
+        let range = self.selection().unwrap_or(self.cursor..self.cursor);
+        let Some(scope) = next_scope(&content, range) else {
+            self.bell();
+            return;
+        };
+        self.scopes.push((self.anchor, self.cursor));
+        self.anchor = Some(scope.start);
+        self.cursor = scope.end;
+        self.scoped = Some((self.anchor, self.cursor));
     }
 
-    terminal::enable_raw_mode()?;
-    execute!(stdout(), terminal::EnterAlternateScreen)?;
+    // Back to the selection the last expansion grew from
+    fn shrink_selection(&mut self) {
+        let previous = match self.scopes.pop() {
+            Some(previous) if self.scoped == Some((self.anchor, self.cursor)) => previous,
+            _ => {
+                self.scopes.clear();
+                self.bell();
+                return;
+            }
+        };
+        (self.anchor, self.cursor) = previous;
+        self.scoped = Some(previous);
+    }
 
-    const DEBOUNCE_DURATION: Duration = Duration::from_millis(10);
+    // Selected byte range, if anything is selected
+    fn selection(&self) -> Option<Range<usize>> {
+        let anchor = self.anchor?;
+        match anchor.cmp(&self.cursor) {
+            std::cmp::Ordering::Less => Some(anchor..self.cursor),
+            std::cmp::Ordering::Greater => Some(self.cursor..anchor),
+            std::cmp::Ordering::Equal => None,
+        }
+    }
 
-    loop {
-        editor.render()?;
-        if let Event::Key(KeyEvent { code, modifiers, .. }) = event::read()? {
-            let now = Instant::now();
-            if now.duration_since(editor.last_key_time) < DEBOUNCE_DURATION {
-                continue;
+    // Select from the bracket under the cursor to its match, or else the
+    // innermost pair around the cursor, with or without the brackets
+    // themselves. With a pair already selected it grows to the next pair
+    // out.
+    fn select_to_bracket(&mut self, inside: bool) {
+        let content = self.rope.to_string();
+        let pair = match self.selection() {
+            // The brackets just outside an inside selection are the pair
+            // it already covers
+            Some(range)
+                if inside
+                    && range.start > 0
+                    && brackets::pair_at(&content, range.start - 1) == Some((range.start - 1, range.end)) =>
+            {
+                brackets::enclosing(&content, range.start - 1, range.end + 1)
             }
-            editor.last_key_time = now;
+            Some(range) => brackets::enclosing(&content, range.start, range.end),
+            None => brackets::pair_at(&content, self.cursor)
+                .or_else(|| brackets::enclosing(&content, self.cursor, self.cursor)),
+        };
+        let Some((opener, closer)) = pair else {
+            self.status_message = Some("No matching bracket".to_string());
+            self.bell();
+            return;
+        };
+        let (start, end) = if inside { (opener + 1, closer) } else { (opener, closer + 1) };
+        self.anchor = Some(start);
+        self.cursor = end;
+    }
 
-            match (code, modifiers) {
-                (KeyCode::Char('a'), KeyModifiers::CONTROL) => break,
-                (KeyCode::Char('s'), KeyModifiers::CONTROL) => {
-                    match editor.save_file() {
-                        Ok(()) => editor.status_message = Some("File saved successfully!".to_string()),
-                        Err(e) => editor.status_message = Some(format!("Save failed: {}", e)),
-                    }
-                }
-                (KeyCode::Char('m'), KeyModifiers::CONTROL) => {
-                    editor.status_message = Some("Menu opened".to_string());
-                    // show_popup()?;
-                }
-                (KeyCode::Char('z'), KeyModifiers::CONTROL) => editor.undo(),
-                (KeyCode::Char('y'), KeyModifiers::CONTROL) => editor.redo(),
-                (KeyCode::Backspace, _) => editor.delete(),
-                (KeyCode::Left, _) => editor.move_cursor_left(),
-                (KeyCode::Right, _) => editor.move_cursor_right(),
-                (KeyCode::Enter, _) => editor.insert("\n"),
-                (KeyCode::Char('x'), KeyModifiers::CONTROL) => {
-                    editor.filename = Some("newname".to_string());
-                    
-                }
-                (KeyCode::Char(c), KeyModifiers::SHIFT) => editor.insert(&c.to_string().to_uppercase()),
-                (KeyCode::Char(c), KeyModifiers::NONE) => editor.insert(&c.to_string()),
-                _ => {}
+    // Characters and lines the selection covers, whichever way round it
+    // was made
+    fn selection_size(&self) -> Option<(usize, usize)> {
+        let range = self.selection()?;
+        // Characters are counted where they are, lines from the tree
+        let (mut chars, mut left) = (0, range.len());
+        for chunk in self.rope.chunks_from(range.start) {
+            if left == 0 {
+                break;
             }
+            let part = &chunk[..left.min(chunk.len())];
+            chars += part.chars().count();
+            left -= part.len();
         }
+        let lines = self.rope.line_at(range.end) - self.rope.line_at(range.start) + 1;
+        Some((chars, lines))
     }
 
-    execute!(stdout(), terminal::LeaveAlternateScreen)?;
-    terminal::disable_raw_mode()?;
-    Ok(())
+    // The selection, now dropped, for typing or deleting over
+    fn take_selection(&mut self) -> Option<Range<usize>> {
+        let selected = self.selection();
+        self.anchor = None;
+        selected
+    }
+
+    fn extend_selection(&mut self, right: bool) {
+        if self.anchor.is_none() {
+            self.anchor = Some(self.cursor);
+        }
+        if right {
+            self.move_cursor_right();
+        } else {
+            self.move_cursor_left();
+        }
+    }
+
+    // Same range, with the cursor at the other end, so extending carries on
+    // from there
+    fn swap_selection_ends(&mut self) {
+        match self.anchor {
+            Some(anchor) if anchor != self.cursor => {
+                self.anchor = Some(self.cursor);
+                self.cursor = anchor;
+                self.scroll_to_cursor();
+            }
+            _ => self.status_message = Some("Nothing selected".to_string()),
+        }
+    }
+
+    // Swap the characters either side of the cursor and step past them. At
+    // the end of a line or the buffer, the two before the cursor swap
+    // instead; at the very start, the first two.
+    fn transpose_chars(&mut self) {
+        // Two characters either side is all that can take part
+        let before_text = self.rope.substring(self.cursor.saturating_sub(8), self.cursor);
+        let after_text = self.rope.substring(self.cursor, self.cursor + 8);
+        let before = before_text.chars().next_back();
+        let after = after_text.chars().next().filter(|&c| c != '\n');
+        let (start, end) = match (before, after) {
+            (Some(b), Some(a)) => (self.cursor - b.len_utf8(), self.cursor + a.len_utf8()),
+            (None, Some(a)) => match after_text[a.len_utf8()..].chars().next() {
+                Some(second) => (0, a.len_utf8() + second.len_utf8()),
+                None => return,
+            },
+            (Some(b), None) => match before_text[..before_text.len() - b.len_utf8()].chars().next_back() {
+                Some(first) => (self.cursor - b.len_utf8() - first.len_utf8(), self.cursor),
+                None => return,
+            },
+            (None, None) => return,
+        };
+
+        let new: String = self.rope.substring(start, end).chars().rev().collect();
+        self.replace_range(start, end, &new);
+    }
+
+    // Reverse by character, so multi-byte text stays valid. The selection
+    // covers the same bytes afterwards, so it's kept as is.
+    fn reverse_selection(&mut self) {
+        let Some(range) = self.selection() else {
+            self.status_message = Some("Nothing selected".to_string());
+            return;
+        };
+        let content = self.rope.to_string();
+        let new: String = content[range.clone()].chars().rev().collect();
+        if new != content[range.clone()] {
+            let cursor = self.cursor;
+            self.replace_range(range.start, range.end, &new);
+            self.cursor = cursor;
+        }
+    }
+
+    // Wrap the selection in the file type's block comment, or unwrap it if
+    // it's already in one, as one undoable edit. The selection stays on the
+    // text itself, so toggling again undoes it.
+    fn toggle_block_comment(&mut self) {
+        let Some(range) = self.selection() else {
+            self.status_message = Some("Nothing selected".to_string());
+            return;
+        };
+        let Some((open, close)) = self.filename.as_deref().and_then(|f| comment::delimiters(Path::new(f))) else {
+            self.status_message = Some("No block comments known for this file type".to_string());
+            return;
+        };
+        let backwards = self.cursor < range.end;
+        let content = self.rope.to_string();
+        let (replaced, text, inner) = comment::toggle(&content, range, open, close);
+        self.replace_range(replaced.start, replaced.end, &text);
+        let (anchor, cursor) = if backwards { (inner.end, inner.start) } else { (inner.start, inner.end) };
+        self.anchor = Some(anchor);
+        self.cursor = cursor;
+    }
+
+    // Keep the cursor's line as it was but commented out, with a copy below
+    // to go on editing, in one undoable edit
+    fn duplicate_and_comment(&mut self) {
+        let content = self.rope.to_string();
+        let (start, end) = (self.line_start(), self.line_end());
+        let line = &content[start..end];
+        if line.trim().is_empty() {
+            self.status_message = Some("Nothing to duplicate on a blank line".to_string());
+            return;
+        }
+        let path = self.filename.as_deref().map(Path::new);
+        let Some(commented) = path.and_then(|path| comment::comment_line(line, path)) else {
+            self.status_message = Some("No comments known for this file type".to_string());
+            return;
+        };
+        let col = self.cursor - start;
+        let text = format!("{}\n{}", commented, line);
+        self.replace_range(start, end, &text);
+        self.cursor = start + commented.len() + 1 + col;
+    }
+
+    // Blank lines at the end of the buffer go, as one undoable edit; the
+    // last line with text keeps its line break. Only the end of the rope is
+    // read, in pieces that double until they reach some text.
+    fn delete_trailing_blank_lines(&mut self) {
+        // Only a buffer ending in whitespace can have any, which the tree
+        // answers without copying the tail out
+        if ![" ", "\t", "\r", "\n"].iter().any(|end| self.rope.ends_with(end)) {
+            self.status_message = Some("No trailing blank lines".to_string());
+            return;
+        }
+        let len = self.rope.len();
+        let mut size = 256;
+        let (start, tail) = loop {
+            let tail = self.rope.substring(len.saturating_sub(size), len);
+            if tail.len() == len || !tail.trim_end().is_empty() {
+                break (len - tail.len(), tail);
+            }
+            size *= 2;
+        };
+        let blank = &tail[tail.trim_end().len()..];
+        let cut = len - blank.len() + blank.find('\n').map_or(blank.len(), |i| i + 1);
+        let lines = tail[cut - start..].matches('\n').count();
+        if lines == 0 {
+            self.status_message = Some("No trailing blank lines".to_string());
+            return;
+        }
+        let removed = self.undo_text(&self.rope, cut..len);
+        self.rope = self.rope.delete_range(cut, len);
+        self.shift_marks(cut, len - cut, 0);
+        self.record_action(Action::Delete { index: cut, text: removed });
+        self.cursor = self.cursor.min(cut);
+        self.dirty = true;
+        let plural = if lines == 1 { "" } else { "s" };
+        self.status_message = Some(format!("Deleted {} trailing blank line{}", lines, plural));
+    }
+
+    // Pad the selected lines so the first `delimiter` on each starts in the
+    // same column, as one undoable edit. The selection grows to cover the
+    // whole lines.
+    fn align_on(&mut self, delimiter: &str) {
+        let Some(range) = self.selection() else {
+            return;
+        };
+        let content = self.rope.to_string();
+        let lines = line_ranges(&content);
+        let first = lines.iter().position(|l| range.start <= l.end).unwrap_or(0);
+        // A selection ending at the start of a line doesn't take that line
+        let last = lines.iter().position(|l| range.end <= l.end).unwrap_or(lines.len() - 1);
+        let last = if last > first && range.end == lines[last].start { last - 1 } else { last };
+        let (start, end) = (lines[first].start, lines[last].end);
+        let Some(new) = align(&content[start..end], delimiter, self.indent.tab_width) else {
+            self.status_message = Some(format!("No {} in the selected lines", delimiter));
+            return;
+        };
+        if new != content[start..end] {
+            self.replace_range(start, end, &new);
+        }
+        self.anchor = Some(start);
+        self.cursor = start + new.len();
+    }
+
+    // Redo every line's indentation in the buffer's indent style, as one
+    // undoable edit
+    fn normalize_indent(&mut self) {
+        let old = self.rope.to_string();
+        let new = indent::normalize(&old, self.indent);
+        if new != old {
+            let cursor = indent::normalize(&old[..self.cursor], self.indent).len().min(new.len());
+            self.replace_range(0, old.len(), &new);
+            self.cursor = cursor;
+        }
+    }
+
+    // Strip spaces and tabs from the end of every line as one undoable edit,
+    // keeping the cursor on its line
+    fn trim_trailing_whitespace(&mut self) {
+        let old = self.rope.to_string();
+        let new = indent::trim_trailing(&old);
+        if new != old {
+            let start = self.line_start();
+            let line_start = indent::trim_trailing(&old[..start]).len();
+            let line_end = new[line_start..].find(['\r', '\n']).map_or(new.len(), |i| line_start + i);
+            let cursor = (line_start + self.cursor - start).min(line_end);
+            self.replace_range(0, old.len(), &new);
+            self.cursor = cursor;
+        }
+    }
+
+    // Rewrite the whole buffer's endings as one undoable edit
+    fn normalize_line_endings(&mut self, crlf: bool) {
+        let old = self.rope.to_string();
+        let new = line_ending::normalize(&old, crlf);
+        let name = if crlf { "CRLF" } else { "LF" };
+        if new == old {
+            self.status_message = Some(format!("Line endings are already {}", name));
+            return;
+        }
+        // Keep the cursor on the same line and column
+        let cursor = line_ending::normalize(&old[..self.cursor], crlf).len().min(new.len());
+        self.replace_range(0, old.len(), &new);
+        self.cursor = cursor;
+        self.status_message = Some(format!("Line endings converted to {}", name));
+    }
+
+    fn execute(&mut self, command: EditorCommand) {
+        let selected = self.selection();
+        if !command.keeps_selection() {
+            self.anchor = None;
+        }
+        if command.is_edit() {
+            self.record_edit(Edit::Command(command));
+        } else if command != EditorCommand::RepeatLastEdit {
+            self.edit_open = false;
+        }
+        match command {
+            EditorCommand::Quit => self.should_quit = true,
+            EditorCommand::Save => match self.filename.clone() {
+                Some(filename) => self.save_and_report(filename),
+                None => self.prompt = Some(Prompt::new(PromptKind::SaveAs, "Save as:")),
+            },
+            EditorCommand::OpenMenu => {
+                self.status_message = Some("Menu opened".to_string());
+                // show_popup()?;
+            }
+            EditorCommand::SetFilename => {
+                self.prompt = Some(Prompt::new(PromptKind::SetFilename, "Filename:"));
+            }
+            EditorCommand::NewBuffer => self.guard_unsaved(AfterDiscard::NewBuffer),
+            EditorCommand::InsertFile => {
+                self.prompt = Some(Prompt::new(PromptKind::InsertFile, "Insert file:"));
+            }
+            EditorCommand::WriteSelection => {
+                self.prompt = Some(Prompt::new(PromptKind::WriteSelection, "Write to file:"));
+            }
+            EditorCommand::SelectionLength => {
+                self.status_message = Some(match (self.selection(), self.selection_size()) {
+                    (Some(range), Some((chars, lines))) => format!(
+                        "Selection: {} characters, {} lines, {} bytes",
+                        chars,
+                        lines,
+                        range.len()
+                    ),
+                    _ => "Nothing selected".to_string(),
+                });
+            }
+            EditorCommand::ToggleBlockComment => self.toggle_block_comment(),
+            EditorCommand::DuplicateAndComment => self.duplicate_and_comment(),
+            EditorCommand::DeleteTrailingBlankLines => self.delete_trailing_blank_lines(),
+            EditorCommand::AlignOn => match self.selection() {
+                Some(_) => self.prompt = Some(Prompt::new(PromptKind::AlignOn, "Align on:")),
+                None => self.status_message = Some("Nothing selected".to_string()),
+            },
+            EditorCommand::ReopenClosed => self.reopen_closed(),
+            EditorCommand::SaveSession => {
+                self.prompt = Some(Prompt::new(PromptKind::SaveSession, "Save session to:"))
+            }
+            EditorCommand::RestoreSession => {
+                self.prompt = Some(Prompt::new(PromptKind::RestoreSession, "Restore session from:"))
+            }
+            EditorCommand::RevertToCheckpoint => self.revert_to_checkpoint(),
+            EditorCommand::PreviousBranch => self.switch_branch(false),
+            EditorCommand::NextBranch => self.switch_branch(true),
+            EditorCommand::UndoTree => {
+                let (nodes, labels) = self.history.outline(describe_action).into_iter().unzip();
+                self.overlay = Some(Overlay::undo_tree(nodes, labels, self.history.current()));
+            }
+            EditorCommand::RevertToSaved => match self.filename {
+                Some(_) => self.guard_unsaved(AfterDiscard::Revert),
+                None => self.status_message = Some("No saved file to revert to".to_string()),
+            },
+            EditorCommand::OpenFileUnderCursor => self.open_file_under_cursor(),
+            EditorCommand::OpenCompanion => self.open_companion(),
+            EditorCommand::Undo => self.undo(),
+            EditorCommand::Redo => self.redo(),
+            EditorCommand::DeleteBackward => match selected {
+                Some(range) => self.replace_range(range.start, range.end, ""),
+                None => self.delete(),
+            },
+            EditorCommand::DeleteWordBackward => {
+                let end = self.cursor;
+                self.move_word_left();
+                self.delete_span(self.cursor, end);
+            }
+            EditorCommand::DeleteToLineStart => {
+                self.delete_span(self.line_start(), self.cursor);
+            }
+            // At the end of a line it joins the next one, as in Emacs
+            EditorCommand::DeleteToLineEnd => {
+                let end = match self.line_end() {
+                    end if end == self.cursor && end < self.rope.len() => end + 1,
+                    end => end,
+                };
+                self.delete_span(self.cursor, end);
+            }
+            EditorCommand::MoveLeft => self.move_cursor_left(),
+            EditorCommand::MoveRight => self.move_cursor_right(),
+            EditorCommand::MoveUp => self.move_cursor_vertically(false),
+            EditorCommand::MoveDown => self.move_cursor_vertically(true),
+            EditorCommand::WordLeft => self.move_word_left(),
+            EditorCommand::WordRight => self.move_word_right(),
+            EditorCommand::ParagraphUp => self.move_paragraph(false),
+            EditorCommand::ParagraphDown => self.move_paragraph(true),
+            EditorCommand::LineStart => self.cursor = self.line_start(),
+            EditorCommand::LineEnd => self.cursor = self.line_end(),
+            EditorCommand::ScrollUp => self.scroll_view(false),
+            EditorCommand::ScrollDown => self.scroll_view(true),
+            EditorCommand::CenterView => self.place_cursor_line(self.view_rows.saturating_sub(1) / 2),
+            EditorCommand::CursorToTop => self.place_cursor_line(0),
+            EditorCommand::CursorToBottom => self.place_cursor_line(self.view_rows.saturating_sub(1)),
+            EditorCommand::SelectToBracket => self.select_to_bracket(false),
+            EditorCommand::SelectInsideBrackets => self.select_to_bracket(true),
+            EditorCommand::ExpandSelection => self.expand_selection(),
+            EditorCommand::ShrinkSelection => self.shrink_selection(),
+            EditorCommand::SelectParagraph => self.select_paragraph(),
+            EditorCommand::BufferStart => self.jump_to(0),
+            EditorCommand::BufferEnd => self.jump_to(self.rope.len()),
+            EditorCommand::JumpBack => self.jump_back(),
+            EditorCommand::JumpForward => self.jump_forward(),
+            EditorCommand::SwapPosition => self.swap_position(),
+            EditorCommand::SelectLeft => self.extend_selection(false),
+            EditorCommand::SelectRight => self.extend_selection(true),
+            EditorCommand::TransposeChars => self.transpose_chars(),
+            EditorCommand::ReverseSelection => self.reverse_selection(),
+            EditorCommand::SwapSelectionEnds => self.swap_selection_ends(),
+            EditorCommand::WrapLine => {
+                let (line, _) = self.cursor_line_col();
+                self.wrapped_line = if self.wrapped_line == Some(line) { None } else { Some(line) };
+                self.col_offset = 0;
+            }
+            EditorCommand::InsertNewline => self.insert_newline(),
+            EditorCommand::InsertTab => self.insert_tab(),
+            EditorCommand::Dedent => self.dedent(),
+            EditorCommand::Cancel => self.cancel(),
+            EditorCommand::Redraw => self.screen.invalidate(),
+            EditorCommand::ToggleOverwrite => self.overwrite = !self.overwrite,
+            EditorCommand::RepeatLastEdit => self.repeat_last_edit(),
+            EditorCommand::RecordMacro => {
+                if let Some((register, len)) = self.macros.stop() {
+                    self.status_message = Some(format!("Recorded {} keys into {}", len, register));
+                } else {
+                    self.prompt = Some(Prompt::new(PromptKind::RecordMacro, "Record macro into register (a-z):"));
+                }
+            }
+            EditorCommand::ReplayMacro => {
+                if self.macros.replaying {
+                    self.status_message = Some("A macro can't replay another macro".to_string());
+                } else {
+                    self.prompt = Some(Prompt::new(PromptKind::ReplayMacro, "Replay macro ([count]register):"));
+                }
+            }
+            EditorCommand::Search => self.prompt = Some(Prompt::new(PromptKind::Search, "Search:")),
+            EditorCommand::SearchNext => self.search_next(),
+            EditorCommand::ToggleSearchHighlight => self.highlight_matches = !self.highlight_matches,
+            EditorCommand::ClearSearchHighlight => self.highlight_matches = false,
+            EditorCommand::ToggleMinimap => self.config.minimap = !self.config.minimap,
+            EditorCommand::ToggleCentered => self.config.centered = !self.config.centered,
+            EditorCommand::ToggleDebugHud => self.debug_hud = !self.debug_hud,
+            EditorCommand::ToggleKeyLog => {
+                let on = self.key_log.is_none();
+                self.key_log = on.then(KeyLog::new);
+                self.status_message = Some(format!("Keystroke display {}", if on { "on" } else { "off" }));
+            }
+            EditorCommand::Settings => {
+                let keys = config::live_settings();
+                let labels = keys.iter().map(|key| self.setting_label(key)).collect();
+                self.overlay = Some(Overlay::settings(keys, labels));
+            }
+            EditorCommand::ToggleLineNumbers => {
+                self.config.line_numbers = self.config.line_numbers.next();
+                self.status_message = Some(format!("Line numbers: {}", self.config.line_numbers.name()));
+            }
+            EditorCommand::LineEndingsToLf => self.normalize_line_endings(false),
+            EditorCommand::LineEndingsToCrlf => self.normalize_line_endings(true),
+            EditorCommand::CommandPalette => {
+                let commands = command::all_commands()
+                    .into_iter()
+                    .filter(|&cmd| cmd != EditorCommand::CommandPalette)
+                    .collect();
+                self.overlay = Some(Overlay::command_palette(commands));
+            }
+            EditorCommand::FindFile => {
+                let files = finder::list_files(Path::new("."));
+                if files.len() >= finder::MAX_FILES {
+                    self.status_message = Some(format!("Showing the first {} files", finder::MAX_FILES));
+                }
+                self.overlay = Some(Overlay::file_finder(files));
+            }
+        }
+    }
+
+    // Feed a recorded macro back through the key handler `count` times,
+    // stopping early once a run changes nothing (e.g. deleting from an
+    // already empty buffer)
+    fn replay_macro(&mut self, count: usize, register: Option<char>) {
+        let Some((register, keys)) = self.macros.keys(register) else {
+            self.status_message = Some("No such macro".to_string());
+            return;
+        };
+        let count = count.min(macros::MAX_REPLAYS);
+        self.macros.replaying = true;
+        let mut runs = 0;
+        while runs < count && !self.should_quit {
+            let (rope, cursor) = (self.rope.clone(), self.cursor);
+            for &(code, modifiers) in &keys {
+                self.handle_key(code, modifiers);
+            }
+            runs += 1;
+            if self.rope == rope && self.cursor == cursor {
+                break;
+            }
+        }
+        self.macros.replaying = false;
+        self.status_message = Some(format!("Replayed macro {} {} time(s)", register, runs));
+    }
+
+    // Typing, pasting and editing commands in a row are one edit; anything
+    // else (e.g. moving the cursor) ends it
+    fn record_edit(&mut self, edit: Edit) {
+        if self.repeating_edit {
+            return;
+        }
+        if !self.edit_open {
+            self.last_edit.clear();
+            self.edit_open = true;
+        }
+        self.last_edit.push(edit);
+    }
+
+    // Like vim's `.`: run the last edit again at the cursor
+    fn repeat_last_edit(&mut self) {
+        if self.last_edit.is_empty() {
+            self.status_message = Some("Nothing to repeat".to_string());
+            return;
+        }
+        let edits = std::mem::take(&mut self.last_edit);
+        self.repeating_edit = true;
+        for edit in &edits {
+            match edit {
+                Edit::Type(text) => self.type_key(text),
+                Edit::Paste(text) => self.paste(text),
+                Edit::Command(command) => self.execute(*command),
+            }
+        }
+        self.repeating_edit = false;
+        self.last_edit = edits;
+        self.edit_open = false;
+    }
+
+    // Back out of one transient state per press: a file still loading, then
+    // a prompt, the selection, search highlighting, and finally an overlay.
+    // With none of those it does nothing.
+    fn cancel(&mut self) {
+        if self.loading.take().is_some() {
+            self.status_message = Some("Loading cancelled".to_string());
+            self.open_at_line = None;
+            self.session_view = None;
+        } else if self.prompt.take().is_some() {
+            self.status_message = Some("Cancelled".to_string());
+        } else if self.anchor.is_some() {
+            self.anchor = None;
+        } else if self.highlight_matches {
+            self.highlight_matches = false;
+        } else {
+            self.overlay = None;
+        }
+    }
+
+    // Called whenever a file is left: saved, closed or replaced
+    fn remember_position(&self) {
+        if let (true, Some(filename)) = (self.config.remember_position, &self.filename) {
+            positions::remember(Path::new(filename), self.cursor_line_col());
+        }
+    }
+
+    // Files of the types that opt in are checked for indentation mixing tabs
+    // and spaces first, with an offer to fix it
+    fn save_and_report(&mut self, filename: String) {
+        let path = Some(Path::new(&filename));
+        if self.config.check_mixed_indent_for(path) && indent::is_mixed(self.rope.chars()) {
+            self.prompt = Some(Prompt::new(
+                PromptKind::ConfirmNormalizeIndent(filename),
+                "Indentation mixes tabs and spaces. Fix it before saving? (y/n)",
+            ));
+            return;
+        }
+        self.write_and_report(filename);
+    }
+
+    fn write_and_report(&mut self, filename: String) {
+        let path = Some(Path::new(&filename));
+        let trim = self.project.trim_trailing_whitespace;
+        if trim.unwrap_or_else(|| self.config.trim_trailing_whitespace_for(path)) {
+            self.trim_trailing_whitespace();
+        }
+        self.remember_position();
+        match self.save_file_as(filename) {
+            Ok(()) => self.status_message = Some("File saved successfully!".to_string()),
+            Err(e) => self.status_message = Some(format!("Save failed: {}", e)),
+        }
+    }
+
+    fn open_file(&mut self, path: &Path) {
+        let leaving = self.filename.clone();
+        self.remember_position();
+        match self.start_loading(path) {
+            Ok(()) => {
+                // Open again, so no longer one to reopen
+                self.closed.retain(|p| p != path);
+                self.push_closed(leaving);
+            }
+            Err(e) => self.status_message = Some(format!("Open failed: {}", e)),
+        }
+    }
+
+    fn push_closed(&mut self, filename: Option<String>) {
+        if let Some(filename) = filename {
+            let path = PathBuf::from(filename);
+            self.closed.retain(|p| *p != path);
+            self.closed.push(path);
+            if self.closed.len() > MAX_CLOSED {
+                self.closed.remove(0);
+            }
+        }
+    }
+
+    // Like vim's `gf`: open the file named around the cursor, relative to
+    // the current file's directory, at the line given with `path:line`
+    fn open_file_under_cursor(&mut self) {
+        let content = self.rope.to_string();
+        let start = self.line_start();
+        let end = content[start..].find('\n').map_or(content.len(), |i| start + i);
+        let Some((name, line)) = finder::path_at(&content[start..end], self.cursor - start) else {
+            self.status_message = Some("No file name under the cursor".to_string());
+            return;
+        };
+        let dir = self.filename.as_deref().and_then(|f| Path::new(f).parent());
+        let path = dir.unwrap_or(Path::new("")).join(&name);
+        if !path.is_file() {
+            self.status_message = Some(format!("No such file: {}", path.display()));
+            return;
+        }
+        self.guard_unsaved(match line {
+            Some(line) => AfterDiscard::OpenAt(path, line.saturating_sub(1)),
+            None => AfterDiscard::Open(path),
+        });
+    }
+
+    // The first companion (see companion.rs) that exists, or an offer to
+    // create the first one the rules give
+    fn open_companion(&mut self) {
+        let Some(filename) = self.filename.clone() else {
+            self.status_message = Some("No file to find a companion for".to_string());
+            return;
+        };
+        let candidates = companion::candidates(Path::new(&filename), &self.config.companions);
+        match candidates.iter().find(|path| path.is_file()) {
+            Some(path) => self.guard_unsaved(AfterDiscard::Open(path.clone())),
+            None => match candidates.into_iter().next() {
+                Some(path) => {
+                    let label = format!("{} doesn't exist. Create it? (y/n)", path.display());
+                    self.prompt = Some(Prompt::new(PromptKind::ConfirmCreate(path), label));
+                }
+                None => self.status_message = Some(format!("No companion rule matches {}", filename)),
+            },
+        }
+    }
+
+    // The file being edited and the ones closed before it, as a session
+    fn session(&self) -> Session {
+        let (line, col) = self.cursor_line_col();
+        let view = session::View { line, col, scroll: self.scroll_offset };
+        Session {
+            active: self.filename.as_ref().map(|filename| (session::absolute(Path::new(filename)), view)),
+            closed: self.closed.iter().map(|path| session::absolute(path)).collect(),
+        }
+    }
+
+    // Files that have gone since the session was saved are left out, with
+    // a warning once the rest is back. A session without a file to open
+    // leaves the buffer as it is.
+    fn restore_session(&mut self, path: &Path) -> io::Result<()> {
+        let mut session = Session::parse(&fs::read_to_string(path)?);
+        let missing = session.drop_missing();
+        let warning = (!missing.is_empty()).then(|| {
+            let names: Vec<String> = missing.iter().map(|path| path.display().to_string()).collect();
+            format!("Session restored without missing files: {}", names.join(", "))
+        });
+        self.remember_position();
+        self.closed = session.closed;
+        match session.active {
+            Some((file, view)) => {
+                self.start_loading(&file)?;
+                self.session_view = Some((view, warning));
+            }
+            None => {
+                self.status_message = Some(warning.unwrap_or_else(|| "Session restored".to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    // The cursor comes back too, through the remembered positions. The file
+    // stays on the list until it's actually opened, so backing out of the
+    // unsaved-changes prompt loses nothing.
+    fn reopen_closed(&mut self) {
+        let Some(path) = self.closed.last().cloned() else {
+            self.status_message = Some("No closed files to reopen".to_string());
+            return;
+        };
+        if self.filename.as_deref().map(Path::new) == Some(path.as_path()) {
+            self.status_message = Some(format!("{} is already open", path.display()));
+        } else {
+            self.guard_unsaved(AfterDiscard::Open(path));
+        }
+    }
+
+    // Throw away every change since the file was last saved, keeping the
+    // cursor on the same line and column as far as the file on disk allows
+    fn revert_to_saved(&mut self) {
+        let Some(filename) = self.filename.clone() else {
+            return;
+        };
+        let (line, col) = self.rope.index_to_line_col(self.cursor);
+        let scroll_offset = self.scroll_offset;
+        match self.load_file(&filename) {
+            Ok(()) => {
+                self.cursor = self.clamp_offset(self.rope.line_col_to_index(line, col));
+                self.scroll_offset = scroll_offset.min(line);
+                self.status_message = Some(format!("Reverted to the saved {}", filename));
+            }
+            Err(e) => self.status_message = Some(format!("Revert failed: {}", e)),
+        }
+    }
+
+    // Fresh, unnamed buffer with no history
+    fn new_buffer(&mut self) {
+        self.remember_position();
+        self.push_closed(self.filename.clone());
+        self.baseline = Rope::new();
+        self.set_content("");
+        self.filename = None;
+        self.encoding = Encoding::Utf8;
+        self.indent = self.config.indent;
+        self.indent_detected = false;
+        self.project = editorconfig::Properties::default();
+        self.link = None;
+        self.status_message = Some("New buffer".to_string());
+    }
+
+    // Run `then` straight away, or ask first if it would lose unsaved changes
+    fn guard_unsaved(&mut self, then: AfterDiscard) {
+        if self.dirty {
+            self.prompt = Some(Prompt::new(
+                PromptKind::ConfirmDiscard(then),
+                "Unsaved changes will be lost. Continue? (y/n)",
+            ));
+        } else {
+            self.after_discard(then);
+        }
+    }
+
+    // Opening a file over `large_file_size` asks first, since reading it
+    // could take a long while
+    fn after_discard(&mut self, then: AfterDiscard) {
+        let path = match &then {
+            AfterDiscard::Open(path) | AfterDiscard::OpenAt(path, _) => Some(path.as_path()),
+            AfterDiscard::NewBuffer
+            | AfterDiscard::Revert
+            | AfterDiscard::Create(_)
+            | AfterDiscard::RestoreSession(_) => None,
+        };
+        match path.and_then(|path| self.large_file_label(path)) {
+            Some(label) => self.prompt = Some(Prompt::new(PromptKind::ConfirmLargeFile(then), label)),
+            None => self.proceed(then),
+        }
+    }
+
+    fn large_file_label(&self, path: &Path) -> Option<String> {
+        let limit = self.config.large_file_size?;
+        let size = fs::metadata(path).ok()?.len();
+        (size > limit).then(|| format!("File is {}. Open anyway? (y/n)", status::format_size(size as usize)))
+    }
+
+    fn proceed(&mut self, then: AfterDiscard) {
+        match then {
+            AfterDiscard::NewBuffer => self.new_buffer(),
+            AfterDiscard::Open(path) => self.open_file(&path),
+            AfterDiscard::Revert => self.revert_to_saved(),
+            AfterDiscard::RestoreSession(path) => {
+                if let Err(e) = self.restore_session(&path) {
+                    self.status_message = Some(format!("Restore failed: {}", e));
+                }
+            }
+            // An empty buffer under the new name; the file itself is only
+            // written on save, but its directory is made now
+            AfterDiscard::Create(path) => {
+                if let Some(Err(e)) = path.parent().filter(|dir| !dir.as_os_str().is_empty()).map(fs::create_dir_all) {
+                    self.status_message = Some(format!("Create failed: {}", e));
+                    return;
+                }
+                self.new_buffer();
+                self.status_message = Some(format!("New file {}", path.display()));
+                self.filename = Some(path.to_string_lossy().into_owned());
+            }
+            AfterDiscard::OpenAt(path, line) => {
+                self.open_file(&path);
+                if self.loading.is_some() {
+                    self.open_at_line = Some(line);
+                }
+            }
+        }
+    }
+
+    // Keys go here while a prompt is showing
+    fn handle_prompt_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        let Some(prompt) = self.prompt.as_mut() else {
+            return;
+        };
+        if matches!(prompt.kind, PromptKind::RecordMacro) {
+            self.prompt = None;
+            match code {
+                KeyCode::Char(c) if macros::is_register(c) => {
+                    self.macros.start(c);
+                    self.status_message = Some(format!("Recording macro into {} (Ctrl+R to stop)", c));
+                }
+                _ => self.status_message = Some("Cancelled".to_string()),
+            }
+            return;
+        }
+        if prompt.is_single_key() {
+            let answer = match code {
+                KeyCode::Char('y') | KeyCode::Char('Y') => true,
+                KeyCode::Char('n') | KeyCode::Char('N') => false,
+                _ => return,
+            };
+            let prompt = self.prompt.take().unwrap();
+            match prompt.kind {
+                PromptKind::ConfirmDiscard(then) if answer => self.after_discard(then),
+                PromptKind::ConfirmOverwrite(path) if answer => self.write_selection(&path, true),
+                PromptKind::ConfirmLargeFile(then) if answer => self.proceed(then),
+                PromptKind::ConfirmCreate(path) if answer => self.guard_unsaved(AfterDiscard::Create(path)),
+                PromptKind::ConfirmNormalizeIndent(filename) => {
+                    if answer {
+                        self.normalize_indent();
+                    }
+                    self.write_and_report(filename);
+                }
+                _ => self.status_message = Some("Cancelled".to_string()),
+            }
+            return;
+        }
+
+        match code {
+            KeyCode::Backspace => {
+                prompt.input.pop();
+            }
+            KeyCode::Enter => {
+                let prompt = self.prompt.take().unwrap();
+                self.submit_prompt(prompt);
+            }
+            KeyCode::Char(c) if !modifiers.contains(KeyModifiers::CONTROL) => prompt.input.push(c),
+            _ => {}
+        }
+    }
+
+    fn submit_prompt(&mut self, prompt: Prompt) {
+        // Spaces can be part of what's searched for, and an empty query
+        // repeats the last one
+        if let PromptKind::Search = prompt.kind {
+            if !prompt.input.is_empty() {
+                self.search = Some(prompt.input);
+            }
+            self.highlight_matches = self.config.highlight_search;
+            self.search_next();
+            return;
+        }
+        let input = prompt.input.trim();
+        if input.is_empty() {
+            self.status_message = Some("Cancelled".to_string());
+            return;
+        }
+        match prompt.kind {
+            PromptKind::SaveAs => self.save_and_report(input.to_string()),
+            PromptKind::SetFilename => {
+                self.filename = Some(input.to_string());
+                self.status_message = Some(format!("Filename set to {}", input));
+            }
+            PromptKind::InsertFile => self.insert_file(input),
+            PromptKind::WriteSelection => self.write_selection(Path::new(input), false),
+            PromptKind::AlignOn => self.align_on(input),
+            PromptKind::SaveSession => match fs::write(input, self.session().format()) {
+                Ok(()) => self.status_message = Some(format!("Session saved to {}", input)),
+                Err(e) => self.status_message = Some(format!("Save failed: {}", e)),
+            },
+            PromptKind::RestoreSession => self.guard_unsaved(AfterDiscard::RestoreSession(PathBuf::from(input))),
+            PromptKind::ReplayMacro => match macros::parse_replay(input) {
+                Some((count, register)) => self.replay_macro(count, register),
+                None => self.status_message = Some(format!("Not a macro register: {}", input)),
+            },
+            PromptKind::Search
+            | PromptKind::ConfirmDiscard(_)
+            | PromptKind::ConfirmOverwrite(_)
+            | PromptKind::ConfirmNormalizeIndent(_)
+            | PromptKind::ConfirmLargeFile(_)
+            | PromptKind::ConfirmCreate(_)
+            | PromptKind::RecordMacro => {}
+        }
+    }
+
+    // Next match of the last search after the cursor, wrapping around to
+    // the top
+    fn search_next(&mut self) {
+        let Some(query) = self.search.clone() else {
+            self.status_message = Some("No previous search".to_string());
+            return;
+        };
+        let content = self.rope.to_string();
+        let from = self.cursor + self.char_len_after();
+        let found = content[from..]
+            .find(&query)
+            .map(|i| (from + i, false))
+            .or_else(|| content.find(&query).map(|i| (i, true)));
+        match found {
+            Some((offset, wrapped)) => {
+                self.jump_to(offset);
+                if wrapped {
+                    self.status_message = Some("Search wrapped to the top".to_string());
+                }
+            }
+            None => self.status_message = Some(format!("Not found: {}", query)),
+        }
+    }
+
+    // How many matches the query being typed into the search prompt has,
+    // so the count follows each keystroke; before anything is typed, the
+    // last search's
+    fn typed_match_count(&self) -> Option<usize> {
+        let query = match &self.prompt {
+            Some(Prompt { kind: PromptKind::Search, input, .. }) if !input.is_empty() => input,
+            _ => self.search.as_ref()?,
+        };
+        let mut cache = self.match_count.borrow_mut();
+        if !matches!(&*cache, Some((q, rope, _)) if q == query && Rope::ptr_eq(rope, &self.rope)) {
+            *cache = Some((query.clone(), self.rope.clone(), self.rope.count_matches(query)));
+        }
+        cache.as_ref().map(|&(_, _, count)| count)
+    }
+
+    // How many matches the last search has, and which one the cursor is at
+    // the start of, if any
+    fn search_position(&self) -> Option<(Option<usize>, usize)> {
+        let query = self.search.as_ref()?;
+        let mut cache = self.match_starts.borrow_mut();
+        if !matches!(&*cache, Some((q, rope, _)) if q == query && Rope::ptr_eq(rope, &self.rope)) {
+            *cache = Some((query.clone(), self.rope.clone(), self.rope.match_starts(query)));
+        }
+        let starts = &cache.as_ref()?.2;
+        let nth = starts.binary_search(&self.cursor).ok().map(|i| i + 1);
+        Some((nth, starts.len()))
+    }
+
+    fn setting_label(&self, key: &str) -> String {
+        format!("{:<18} {}", key, self.config.setting(key).unwrap_or_default())
+    }
+
+    // The settings view stays open: Enter or Space changes the highlighted
+    // setting straight away, and Ctrl+S writes the changed ones to the
+    // config file. Anything else filters the list as usual.
+    fn handle_settings_key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        let Some(Overlay { kind: OverlayKind::Settings(keys, changed), .. }) = &self.overlay else {
+            return false;
+        };
+        match (code, modifiers) {
+            (KeyCode::Enter | KeyCode::Char(' '), _) => {
+                let Some(idx) = self.overlay.as_ref().and_then(Overlay::selected_index) else {
+                    return true;
+                };
+                let key = keys[idx];
+                self.config.toggle_setting(key);
+                let label = self.setting_label(key);
+                let overlay = self.overlay.as_mut().unwrap();
+                overlay.relabel(idx, label);
+                if let OverlayKind::Settings(_, changed) = &mut overlay.kind {
+                    if !changed.contains(&key) {
+                        changed.push(key);
+                    }
+                }
+                true
+            }
+            (KeyCode::Char('s'), KeyModifiers::CONTROL) => {
+                let settings: Vec<(&str, String)> = changed
+                    .iter()
+                    .map(|&key| (key, self.config.setting(key).unwrap_or_default()))
+                    .collect();
+                self.status_message = Some(if settings.is_empty() {
+                    "No settings changed".to_string()
+                } else {
+                    match config::save_settings(&settings) {
+                        Ok(()) => format!("Saved {} setting(s) to the config file", settings.len()),
+                        Err(e) => format!("Saving settings failed: {}", e),
+                    }
+                });
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // Keys go here instead of the normal bindings while an overlay is open
+    fn handle_overlay_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        if self.handle_settings_key(code, modifiers) {
+            return;
+        }
+        let Some(overlay) = self.overlay.as_mut() else {
+            return;
+        };
+        match code {
+            KeyCode::Up => overlay.move_selection(-1),
+            KeyCode::Down => overlay.move_selection(1),
+            KeyCode::Backspace => overlay.pop_char(),
+            KeyCode::Enter => {
+                let overlay = self.overlay.take().unwrap();
+                if let Some(idx) = overlay.selected_index() {
+                    match overlay.kind {
+                        OverlayKind::Commands(commands) => self.execute(commands[idx]),
+                        OverlayKind::Files(mut files) => {
+                            self.guard_unsaved(AfterDiscard::Open(files.swap_remove(idx)))
+                        }
+                        OverlayKind::Settings(..) => {}
+                        OverlayKind::History(nodes) => {
+                            self.go_to_history(nodes[idx]);
+                            self.status_message = Some(format!("Moved to {} actions in", self.history.depth()));
+                        }
+                    }
+                }
+            }
+            KeyCode::Char(c) if !modifiers.contains(KeyModifiers::CONTROL) => overlay.push_char(c),
+            _ => {}
+        }
+    }
+
+    // Ends of the cursor's line, found through the newline counts in the
+    // tree rather than by searching the text
+    fn line_start(&self) -> usize {
+        self.rope.line_range(self.rope.line_at(self.cursor)).start
+    }
+
+    fn line_end(&self) -> usize {
+        self.rope.line_range(self.rope.line_at(self.cursor)).end
+    }
+
+    // One undo step, or the bell when there's nothing to delete
+    fn delete_span(&mut self, start: usize, end: usize) {
+        if start == end {
+            self.bell();
+        } else {
+            self.replace_range(start, end, "");
+        }
+    }
+
+    fn insert_tab(&mut self) {
+        match self.indent.style {
+            IndentStyle::Tabs => self.insert("\t"),
+            IndentStyle::Spaces => {
+                // Pad to the next tab stop rather than a fixed width
+                let width = self.indent.tab_width;
+                let col = column::display_width(&self.rope.substring(self.line_start(), self.cursor), width);
+                self.insert(&" ".repeat(width - col % width));
+            }
+        }
+    }
+
+    fn insert_newline(&mut self) {
+        // With open_line_at_eof off, Enter at the end of a file missing its
+        // final line break only adds that
+        let unterminated = self.cursor == self.rope.len() && !self.rope.is_empty() && !self.rope.ends_with("\n");
+        if unterminated && !self.config.open_line_at_eof {
+            self.insert("\n");
+            self.cursor -= 1;
+            return;
+        }
+        let line = self.rope.substring(self.line_start(), self.cursor);
+        // With trim_on_enter, the whitespace the line being left would end
+        // with goes in the same edit as the line break
+        let trim = match self.config.trim_on_enter {
+            true => line.len() - line.trim_end_matches([' ', '\t']).len(),
+            false => 0,
+        };
+        let indent: String = match self.config.auto_indent {
+            true => line.chars().take_while(|&c| c == ' ' || c == '\t').collect(),
+            false => String::new(),
+        };
+        let start = self.cursor - trim;
+        let between_pair = matches!(
+            (line[..line.len() - trim].chars().next_back(), self.char_after()),
+            (Some('{'), Some('}')) | (Some('['), Some(']')) | (Some('('), Some(')'))
+        );
+        let (text, cursor_in_text) = if self.config.auto_indent && self.config.expand_brackets && between_pair {
+            // Enter between a pair opens an indented line between them and
+            // puts the closer on its own line, as one edit
+            let inner = match self.indent.style {
+                IndentStyle::Tabs => "\t".to_string(),
+                IndentStyle::Spaces => " ".repeat(self.indent.tab_width),
+            };
+            let first = format!("\n{}{}", indent, inner);
+            (format!("{}\n{}", first, indent), first.len())
+        } else {
+            let text = format!("\n{}", indent);
+            let len = text.len();
+            (text, len)
+        };
+        if trim > 0 {
+            self.replace_range(start, self.cursor, &text);
+        } else {
+            self.insert(&text);
+        }
+        self.cursor = start + cursor_in_text;
+    }
+
+    // Typing `}` on a blank indented line lines it up with its `{` line, as
+    // one edit replacing the leading whitespace
+    fn insert_closing_brace(&mut self) {
+        let path = self.filename.as_deref().map(Path::new);
+        if !self.config.dedent_brace_for(path) {
+            self.type_text("}");
+            return;
+        }
+        let content = self.rope.to_string();
+        let start = self.line_start();
+        let before = &content[start..self.cursor];
+        let blank = before.chars().all(|c| c == ' ' || c == '\t');
+        let opener = match brackets::find_opener(&content, self.cursor, '{', '}') {
+            Some(opener) if blank => opener,
+            _ => {
+                self.type_text("}");
+                return;
+            }
+        };
+
+        let opener_start = content[..opener].rfind('\n').map_or(0, |i| i + 1);
+        let indent: String = content[opener_start..]
+            .chars()
+            .take_while(|&c| c == ' ' || c == '\t')
+            .collect();
+        let end = self.cursor;
+        self.replace_range(start, end, &format!("{}}}", indent));
+    }
+
+    // Remove one indent level from the start of the cursor's line
+    fn dedent(&mut self) {
+        let start = self.line_start();
+        let line = self.rope.substring(start, self.line_end());
+        let remove = if line.starts_with('\t') {
+            1
+        } else {
+            line.chars().take(self.indent.tab_width).take_while(|&c| c == ' ').count()
+        };
+        if remove == 0 {
+            return;
+        }
+        let text = line[..remove].to_string();
+        self.rope = self.rope.delete_range(start, start + remove);
+        self.shift_marks(start, remove, 0);
+        self.record_action(Action::Delete { index: start, text: text.into() });
+        self.cursor = if self.cursor >= start + remove { self.cursor - remove } else { start };
+        self.dirty = true;
+        self.status_message = None;
+    }
+
+    fn indent_label(&self) -> String {
+        let style = match self.indent.style {
+            IndentStyle::Spaces => "Spaces",
+            IndentStyle::Tabs => "Tabs",
+        };
+        let source = if self.indent_detected { " (detected)" } else { "" };
+        format!("{}: {}{}", style, self.indent.tab_width, source)
+    }
+
+    // Something couldn't be done (e.g. moving past the end of the buffer):
+    // flash and/or beep, as configured
+    fn bell(&mut self) {
+        if self.config.visual_bell {
+            self.flash_until = Some(Instant::now() + FLASH_TIME);
+        }
+        self.ring |= self.config.audible_bell;
+    }
+
+    // Once the flash has been up long enough, or the cursor has gone
+    // unused long enough, the next frame shows it
+    fn check_timers(&mut self) {
+        let now = Instant::now();
+        if matches!(self.flash_until, Some(until) if now >= until) {
+            self.flash_until = None;
+            self.dirty_display = true;
+        }
+        if matches!(self.hide_cursor_at(), Some(at) if now >= at) {
+            self.idle = true;
+            self.dirty_display = true;
+        }
+        if matches!(self.key_log.as_mut().map(|log| log.expire(now)), Some(true)) {
+            self.dirty_display = true;
+        }
+    }
+
+    fn hide_cursor_at(&self) -> Option<Instant> {
+        match self.config.hide_cursor_after {
+            Some(after) if !self.idle => Some(self.last_key_time + after),
+            _ => None,
+        }
+    }
+
+    // When the main loop next has something to do without any input
+    fn next_timer(&self) -> Option<Instant> {
+        let fade = self.key_log.as_ref().and_then(KeyLog::next_expiry);
+        [self.flash_until, self.hide_cursor_at(), fade].into_iter().flatten().min()
+    }
+
+    // A click places the cursor, a double click selects the word there and
+    // a triple click the whole line
+    fn click(&mut self, term_width: u16, column: u16, row: u16, count: usize) {
+        let Some(offset) = self.offset_at(term_width, column, row) else {
+            return;
+        };
+        self.edit_open = false;
+        self.status_message = None;
+        let content = self.rope.to_string();
+        let (start, end) = match count {
+            1 => (offset, offset),
+            2 => {
+                let start = content[..offset]
+                    .char_indices()
+                    .rev()
+                    .take_while(|&(_, c)| is_word_char(c))
+                    .last()
+                    .map_or(offset, |(i, _)| i);
+                let end = content[offset..]
+                    .find(|c| !is_word_char(c))
+                    .map_or(content.len(), |i| offset + i);
+                (start, end)
+            }
+            _ => {
+                let start = content[..offset].rfind('\n').map_or(0, |i| i + 1);
+                let end = content[offset..].find('\n').map_or(content.len(), |i| offset + i + 1);
+                (start, end)
+            }
+        };
+        self.anchor = Some(start).filter(|&start| start != end);
+        self.cursor = end;
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        match event {
+            // Only presses count: some platforms also report releases, which a
+            // time-based debounce used to filter but which also ate fast input
+            Event::Key(KeyEvent { code, modifiers, kind: KeyEventKind::Press | KeyEventKind::Repeat, .. }) => {
+                self.last_key_time = Instant::now();
+                self.idle = false;
+                self.dirty_display = true;
+                self.handle_key(code, modifiers);
+            }
+            Event::Paste(text) => {
+                self.last_key_time = Instant::now();
+                self.idle = false;
+                self.dirty_display = true;
+                if self.loading.is_none() {
+                    self.paste(&text);
+                }
+            }
+            Event::Mouse(MouseEvent { kind: MouseEventKind::Down(MouseButton::Left), column, row, .. }) => {
+                self.last_key_time = Instant::now();
+                self.idle = false;
+                self.dirty_display = true;
+                if self.loading.is_none() && self.prompt.is_none() && self.overlay.is_none() {
+                    let (width, _) = terminal::size().unwrap_or((80, 24));
+                    let count = self.clicks.press(column, row, Instant::now());
+                    self.click(width, column, row, count);
+                }
+            }
+            Event::Resize(..) => self.dirty_display = true,
+            _ => {}
+        }
+    }
+
+    // A typed character; it replaces the selection, and `}` may re-indent
+    // its line
+    fn type_key(&mut self, text: &str) {
+        self.record_edit(Edit::Type(text.to_string()));
+        if let Some(range) = self.take_selection() {
+            if insertable(text) {
+                self.replace_range(range.start, range.end, text);
+            }
+        } else if text == "}" {
+            self.insert_closing_brace();
+        } else {
+            self.type_text(text);
+        }
+    }
+
+    // Where every key press goes, from the terminal or a macro being replayed
+    fn handle_key(&mut self, code: KeyCode, modifiers: KeyModifiers) {
+        // Cancel works the same in every state, so it's handled before the
+        // prompt or overlay get to see the key
+        let cancel = command::lookup(code, modifiers, self.config.keymap) == Some(EditorCommand::Cancel);
+
+        // With the command it runs, unless a prompt or overlay takes the key
+        if let Some(log) = self.key_log.as_mut().filter(|_| !self.macros.replaying) {
+            let bound = cancel || (self.prompt.is_none() && self.overlay.is_none());
+            let command = bound.then(|| command::lookup(code, modifiers, self.config.keymap)).flatten();
+            log.push(code, modifiers, command, Instant::now());
+        }
+
+        // The buffer is about to be replaced, so only cancelling makes sense
+        if self.loading.is_some() {
+            if cancel {
+                self.cancel();
+            }
+            return;
+        }
+
+        // Checked first so the keys that start and stop recording aren't recorded
+        let recording = self.macros.recording().is_some() && !self.macros.replaying;
+
+        if cancel {
+            self.execute(EditorCommand::Cancel);
+        } else if self.prompt.is_some() {
+            self.handle_prompt_key(code, modifiers);
+        } else if self.overlay.is_some() {
+            self.handle_overlay_key(code, modifiers);
+        } else if let Some(command) = command::lookup(code, modifiers, self.config.keymap) {
+            self.execute(command);
+        } else {
+            match (code, modifiers) {
+                (KeyCode::Char(c), KeyModifiers::SHIFT) => self.type_key(&c.to_uppercase().to_string()),
+                (KeyCode::Char(c), KeyModifiers::NONE) => self.type_key(&c.to_string()),
+                _ => self.anchor = None,
+            }
+        }
+
+        if recording {
+            self.macros.record(code, modifiers);
+        }
+    }
+
+}
+
+
+// Where a symlink points, or how many names a hard-linked file has
+fn link_label(path: &Path) -> Option<String> {
+    if fs::symlink_metadata(path).ok()?.file_type().is_symlink() {
+        let target = fs::read_link(path).ok()?;
+        return Some(format!("-> {}", target.display()));
+    }
+    match hard_links(path) {
+        1 => None,
+        links => Some(format!("{} hard links", links)),
+    }
+}
+
+#[cfg(unix)]
+fn hard_links(path: &Path) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    fs::metadata(path).map_or(1, |metadata| metadata.nlink())
+}
+
+#[cfg(not(unix))]
+fn hard_links(_path: &Path) -> u64 {
+    1
+}
+
+// Hidden, and in the same directory so the rename stays on one filesystem
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().map_or("untitled".into(), |name| name.to_string_lossy());
+    path.with_file_name(format!(".{}.rope-editor-save", name))
+}
+
+// Text the editor accepts from typing
+fn insertable(text: &str) -> bool {
+    text.chars().all(|c| c.is_ascii_graphic() || c.is_whitespace() || c == '\n')
+}
+
+// The smallest scope that holds `range` and more: the word around it, its
+// lines, the inside of the innermost brackets around it, those brackets
+// too, or the whole text
+fn next_scope(content: &str, range: Range<usize>) -> Option<Range<usize>> {
+    let mut scopes = Vec::new();
+    if content[range.clone()].chars().all(is_word_char) {
+        let before = &content[..range.start];
+        let start = before.trim_end_matches(is_word_char).len();
+        let after = &content[range.end..];
+        let end = range.end + after.len() - after.trim_start_matches(is_word_char).len();
+        scopes.push(start..end);
+    }
+    let line_start = content[..range.start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = content[range.end..].find('\n').map_or(content.len(), |i| range.end + i);
+    scopes.push(line_start..line_end);
+    if let Some((opener, closer)) = brackets::enclosing(content, range.start, range.end) {
+        scopes.push(opener + 1..closer);
+        scopes.push(opener..closer + 1);
+    }
+    scopes.push(0..content.len());
+    scopes
+        .into_iter()
+        .filter(|scope| scope.start <= range.start && range.end <= scope.end && scope.len() > range.len())
+        .min_by_key(|scope| scope.len())
+}
+
+// One line for the undo tree: what went in and what came out
+fn describe_action(action: &Action) -> String {
+    let quote = |text: &UndoText| {
+        let escaped: String = text.head(25).escape_debug().collect();
+        match escaped.char_indices().nth(24) {
+            Some((i, _)) => format!("\"{}...\"", &escaped[..i]),
+            None => format!("\"{}\"", escaped),
+        }
+    };
+    match action {
+        Action::Insert { text, .. } => format!("+{}", quote(text)),
+        Action::Delete { text, .. } => format!("-{}", quote(text)),
+        Action::Replace { old, new, .. } => format!("{} -> {}", quote(old), quote(new)),
+    }
+}
+
+// Byte range of each line, without its newline
+fn line_ranges(content: &str) -> Vec<Range<usize>> {
+    let mut start = 0;
+    let mut lines = Vec::new();
+    for line in content.split('\n') {
+        lines.push(start..start + line.len());
+        start += line.len() + 1;
+    }
+    lines
+}
+
+// `block` with spaces before the first `delimiter` on each line, so they
+// all start in the same screen column. None if no line has one.
+fn align(block: &str, delimiter: &str, tab_width: usize) -> Option<String> {
+    let found: Vec<_> = block.split('\n').map(|line| line.find(delimiter)).collect();
+    let column = block
+        .split('\n')
+        .zip(&found)
+        .filter_map(|(line, at)| Some(column::display_width(&line[..(*at)?], tab_width)))
+        .max()?;
+    let lines: Vec<String> = block
+        .split('\n')
+        .zip(&found)
+        .map(|(line, at)| match *at {
+            Some(at) => {
+                let pad = column - column::display_width(&line[..at], tab_width);
+                format!("{}{}{}", &line[..at], " ".repeat(pad), &line[at..])
+            }
+            None => line.to_string(),
+        })
+        .collect();
+    Some(lines.join("\n"))
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn main() -> io::Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--script" || arg == "--exec") {
+        std::process::exit(script::main(&args));
+    }
+    // `-` reads the text from stdin; the keyboard is then read from the
+    // terminal itself
+    let from_stdin = args.first().map(String::as_str) == Some("-");
+    // With the text coming through a pipe, a redirected stdout is where the
+    // result goes. Otherwise raw mode and the alternate screen would only
+    // write escape codes into whatever stdout was redirected to.
+    let pipe_output = !stdout().is_tty();
+    if pipe_output && !from_stdin {
+        eprintln!("rope-editor: stdout is not a terminal");
+        eprintln!("To edit without the terminal UI, use: rope-editor --script FILE --exec SCRIPT");
+        std::process::exit(1);
+    }
+
+    let mut editor = Editor::new(Config::load());
+    let opened = if from_stdin {
+        editor.load_stdin()
+    } else if args.first().map(String::as_str) == Some("--session") {
+        match args.get(1) {
+            Some(file) => editor.restore_session(Path::new(file)),
+            None => Err(io::Error::new(io::ErrorKind::InvalidInput, "--session needs a session file")),
+        }
+    } else if let Some(filename) = args.first() {
+        // A file that's too big starts as a question instead
+        let path = Path::new(&filename);
+        match editor.large_file_label(path) {
+            Some(label) => {
+                let then = AfterDiscard::Open(path.to_path_buf());
+                editor.prompt = Some(Prompt::new(PromptKind::ConfirmLargeFile(then), label));
+                Ok(())
+            }
+            None => editor.start_loading(path),
+        }
+    } else {
+        Ok(())
+    };
+    if let Err(e) = opened {
+        let name = args.get(1).filter(|_| args[0] == "--session").or(args.first());
+        eprintln!("rope-editor: {}: {}", name.map_or("-", String::as_str), e);
+        std::process::exit(1);
+    }
+    if pipe_output {
+        editor.output = Output::tty()?;
+    }
+
+    terminal::enable_raw_mode()?;
+    execute!(editor.output, terminal::EnterAlternateScreen, event::EnableBracketedPaste)?;
+    if editor.config.mouse {
+        execute!(editor.output, event::EnableMouseCapture)?;
+    }
+
+    // Every event already queued is handled before the next frame, so a
+    // burst of input (e.g. a paste) costs one render instead of one per key
+    loop {
+        editor.continue_loading();
+        editor.check_timers();
+        if editor.dirty_display {
+            editor.render()?;
+            editor.dirty_display = false;
+        }
+        // Don't block on input while a file is still streaming in, or past
+        // the end of a flash or the idle time
+        let timeout = match editor.next_timer() {
+            _ if editor.loading.is_some() => Some(Duration::ZERO),
+            Some(at) => Some(at.saturating_duration_since(Instant::now())),
+            None => None,
+        };
+        if timeout.map_or(Ok(true), event::poll)? {
+            editor.handle_event(event::read()?);
+        }
+        while !editor.should_quit && event::poll(Duration::ZERO)? {
+            editor.handle_event(event::read()?);
+        }
+        if editor.should_quit {
+            break;
+        }
+    }
+    editor.remember_position();
+
+    execute!(
+        editor.output,
+        cursor::SetCursorStyle::DefaultUserShape,
+        cursor::Show,
+        event::DisableMouseCapture,
+        event::DisableBracketedPaste,
+        terminal::LeaveAlternateScreen
+    )?;
+    terminal::disable_raw_mode()?;
+    if pipe_output {
+        let bytes = encoding::encode(&editor.content(), editor.encoding)?;
+        stdout().write_all(&bytes)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Feed keys through the same path as the terminal's
+    fn press(editor: &mut Editor, keys: &[(KeyCode, KeyModifiers)]) {
+        for &(code, modifiers) in keys {
+            editor.handle_key(code, modifiers);
+        }
+    }
+
+    fn type_str(editor: &mut Editor, text: &str) {
+        for c in text.chars() {
+            editor.handle_key(KeyCode::Char(c), KeyModifiers::NONE);
+        }
+    }
+
+    // A fresh directory under the system temp dir, removed again when the
+    // test ends, even if it fails
+    pub(crate) struct TempDir(PathBuf);
+
+    impl TempDir {
+        pub(crate) fn new(name: &str) -> TempDir {
+            let dir = std::env::temp_dir().join(format!("rope-editor-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl std::ops::Deref for TempDir {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn indent_settings_follow_file_type() {
+        let config = Config::parse("tab_width = 4\n\n[rs]\ntab_width = 8\n\n[go]\nindent_style = tabs\n");
+        let dir = TempDir::new("indent");
+        for name in ["a.py", "b.rs", "c.go"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let mut editor = Editor::new(config);
+        editor.load_file(dir.join("a.py")).unwrap();
+        assert_eq!(editor.indent.tab_width, 4);
+        editor.load_file(dir.join("b.rs")).unwrap();
+        assert_eq!(editor.indent.tab_width, 8);
+        assert_eq!(editor.indent.style, IndentStyle::Spaces);
+        editor.load_file(dir.join("c.go")).unwrap();
+        assert_eq!(editor.indent, IndentSettings { tab_width: 4, style: IndentStyle::Tabs });
+
+        editor.insert_tab();
+        assert_eq!(editor.rope.to_string(), "\t");
+    }
+
+    #[test]
+    fn transpose_and_reverse_handle_edges() {
+        let mut editor = Editor::new(Config::default());
+        editor.rope = Rope::from_string("abc");
+
+        editor.transpose_chars();
+        assert_eq!(editor.rope.to_string(), "bac");
+        assert_eq!(editor.cursor, 2);
+        editor.cursor = 3;
+        editor.transpose_chars();
+        assert_eq!(editor.rope.to_string(), "bca");
+        editor.undo();
+        assert_eq!(editor.rope.to_string(), "bac");
+
+        editor.rope = Rope::from_string("xé€y");
+        editor.anchor = Some(1);
+        editor.cursor = 6;
+        editor.reverse_selection();
+        assert_eq!(editor.rope.to_string(), "x€éy");
+        assert_eq!(editor.selection(), Some(1..6));
+    }
+
+    #[test]
+    fn replace_range_is_one_undo_step() {
+        let rope = Rope::from_string("hello world");
+        assert!(rope.replace_range(6, 11, "rope") == rope.delete(6, 5).insert(6, "rope"));
+
+        let mut editor = Editor::new(Config::default());
+        editor.rope = rope;
+        editor.replace_range(0, 5, "goodbye");
+        assert_eq!(editor.rope.to_string(), "goodbye world");
+        assert_eq!(editor.cursor, 7);
+        assert_eq!(editor.history.depth(), 1);
+        editor.undo();
+        assert_eq!(editor.rope.to_string(), "hello world");
+        editor.redo();
+        assert_eq!(editor.rope.to_string(), "goodbye world");
+    }
+
+    #[test]
+    fn macros_replay_through_key_handler() {
+        let mut editor = Editor::new(Config::default());
+        let ctrl = |c| (KeyCode::Char(c), KeyModifiers::CONTROL);
+        let key = |c| (KeyCode::Char(c), KeyModifiers::NONE);
+
+        press(&mut editor, &[ctrl('r'), key('a'), key('h'), key('i'), ctrl('r')]);
+        press(&mut editor, &[ctrl('e'), key('3'), key('a'), (KeyCode::Enter, KeyModifiers::NONE)]);
+        assert_eq!(editor.rope.to_string(), "hihihihi");
+
+        // Stops once there's nothing left to delete instead of running 1000 times
+        press(&mut editor, &[ctrl('r'), key('b'), (KeyCode::Backspace, KeyModifiers::NONE), ctrl('r')]);
+        editor.replay_macro(1000, Some('b'));
+        assert_eq!(editor.rope.to_string(), "");
+        assert_eq!(editor.status_message.as_deref(), Some("Replayed macro b 8 time(s)"));
+    }
+
+    #[test]
+    fn overwrite_replaces_whole_characters() {
+        let mut editor = Editor::new(Config::default());
+        editor.rope = Rope::from_string("é€\n");
+        editor.overwrite = true;
+        editor.type_text("a");
+        editor.type_text("b");
+        editor.type_text("c");
+        assert_eq!(editor.rope.to_string(), "abc\n");
+        editor.undo();
+        assert_eq!(editor.rope.to_string(), "ab\n");
+        editor.undo();
+        assert_eq!(editor.rope.to_string(), "a€\n");
+    }
+
+    #[test]
+    fn motion_can_stop_at_line_boundaries() {
+        let mut editor = Editor::new(Config::parse("wrap_motion = false"));
+        editor.rope = Rope::from_string("one two\nthree");
+        editor.cursor = 7;
+        editor.move_cursor_right();
+        assert_eq!(editor.cursor, 7);
+        editor.move_word_left();
+        assert_eq!(editor.cursor, 4);
+        editor.cursor = 8;
+        editor.move_cursor_left();
+        editor.move_word_left();
+        assert_eq!(editor.cursor, 8);
+        editor.move_cursor_vertically(false);
+        assert_eq!(editor.cursor, 0);
+
+        editor.config.wrap_motion = true;
+        editor.cursor = 7;
+        editor.move_word_right();
+        assert_eq!(editor.cursor, 13);
+    }
+
+    #[test]
+    fn word_motion_and_transpose_read_across_leaves() {
+        let mut editor = Editor::new(Config::default());
+        editor.rope = Rope::concat(Rope::from_string("héllo wö"), Rope::from_string("rld, €x"));
+        editor.move_word_right();
+        assert_eq!(editor.cursor, 6);
+        editor.move_word_right();
+        assert_eq!(editor.cursor, 13);
+        editor.transpose_chars();
+        assert_eq!(editor.content(), "héllo wörl,d €x");
+        editor.move_word_left();
+        assert_eq!(editor.cursor, 13);
+        editor.move_word_left();
+        assert_eq!(editor.cursor, 7);
+        editor.cursor = editor.rope.len();
+        editor.transpose_chars();
+        assert_eq!(editor.content(), "héllo wörl,d x€");
+    }
+
+    #[test]
+    fn scripted_keys_edit_move_and_select() {
+        let mut editor = Editor::new(Config::default());
+        type_str(&mut editor, "hello world");
+        press(&mut editor, &[(KeyCode::Enter, KeyModifiers::NONE)]);
+        type_str(&mut editor, "abc");
+        assert_eq!(editor.content(), "hello world\nabc");
+        assert_eq!(editor.cursor_line_col(), (1, 3));
+
+        press(&mut editor, &[(KeyCode::Up, KeyModifiers::NONE), (KeyCode::Right, KeyModifiers::CONTROL)]);
+        assert_eq!(editor.cursor_line_col(), (0, 5));
+        let shift_right = (KeyCode::Right, KeyModifiers::SHIFT);
+        press(&mut editor, &[shift_right; 6]);
+        press(&mut editor, &[(KeyCode::Char('r'), KeyModifiers::ALT)]);
+        assert_eq!(editor.content(), "hellodlrow \nabc");
+
+        press(&mut editor, &[(KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.content(), "hello world\nabc");
+        press(&mut editor, &[(KeyCode::Backspace, KeyModifiers::NONE)]);
+        assert_eq!(editor.content(), "hello worl\nabc");
+    }
+
+    #[test]
+    fn smart_backspace_removes_an_indent_level() {
+        let mut editor = Editor::new(Config::parse("smart_backspace = true\ntab_width = 4"));
+        editor.set_content("        x");
+        editor.cursor = 8;
+        editor.delete();
+        assert_eq!(editor.content(), "    x");
+        assert_eq!(editor.history.depth(), 1);
+        editor.cursor = 3;
+        editor.delete();
+        assert_eq!(editor.content(), " x");
+        editor.undo();
+        editor.undo();
+        assert_eq!(editor.content(), "        x");
+
+        // Past the indentation it's one character again
+        editor.cursor = 9;
+        editor.delete();
+        assert_eq!(editor.content(), "        ");
+    }
+
+    #[test]
+    fn scrolling_the_view_leaves_the_cursor_until_it_reaches_the_edge() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content(&"line\n".repeat(100));
+        editor.view_rows = 10;
+        editor.cursor = editor.rope.line_col_to_index(5, 2);
+        let down = (KeyCode::Down, KeyModifiers::CONTROL | KeyModifiers::SHIFT);
+        let up = (KeyCode::Up, KeyModifiers::CONTROL | KeyModifiers::SHIFT);
+
+        press(&mut editor, &[down, down, down, up]);
+        editor.scroll_to_cursor(); // As the next render would
+        assert_eq!(editor.scroll_offset, 2);
+        assert_eq!(editor.cursor_line_col(), (5, 2));
+
+        // Past the cursor, it's carried along on the top row
+        press(&mut editor, &[down, down, down, down]);
+        editor.scroll_to_cursor();
+        assert_eq!(editor.scroll_offset, 6);
+        assert_eq!(editor.cursor_line_col(), (6, 2));
+
+        // ...and with scrolloff, kept that many rows in from the edge
+        editor.config.scrolloff = 2;
+        press(&mut editor, &[up]);
+        assert_eq!(editor.cursor_line_col(), (7, 2));
+        press(&mut editor, &[up, up, up, up, up]);
+        editor.scroll_to_cursor();
+        assert_eq!(editor.scroll_offset, 0);
+        assert_eq!(editor.cursor_line_col(), (7, 2));
+    }
+
+    #[test]
+    fn the_cursor_line_can_be_centered_or_sent_to_the_top_or_bottom() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content(&"line\n".repeat(100));
+        editor.view_rows = 10;
+        editor.cursor = editor.rope.line_col_to_index(50, 1);
+        let place = |editor: &mut Editor, command| {
+            editor.execute(command);
+            editor.scroll_to_cursor(); // As the next render would
+            assert_eq!(editor.cursor_line_col(), (50, 1));
+            editor.scroll_offset
+        };
+        assert_eq!(place(&mut editor, EditorCommand::CenterView), 46);
+        assert_eq!(place(&mut editor, EditorCommand::CursorToTop), 50);
+        assert_eq!(place(&mut editor, EditorCommand::CursorToBottom), 41);
+        editor.config.scrolloff = 3;
+        assert_eq!(place(&mut editor, EditorCommand::CursorToTop), 47);
+        assert_eq!(place(&mut editor, EditorCommand::CursorToBottom), 44);
+
+        // Near the start there's nothing above to show
+        editor.cursor = editor.rope.line_col_to_index(2, 1);
+        editor.execute(EditorCommand::CenterView);
+        assert_eq!(editor.scroll_offset, 0);
+    }
+
+    #[test]
+    fn scrolling_past_the_end_only_when_configured() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content(&"line\n".repeat(20)); // 21 lines, the last empty
+        editor.view_rows = 10;
+        let scroll_to_end = |editor: &mut Editor| {
+            for _ in 0..30 {
+                editor.execute(EditorCommand::ScrollDown);
+            }
+            editor.scroll_offset
+        };
+        assert_eq!(scroll_to_end(&mut editor), 11);
+        editor.cursor = editor.rope.len();
+        editor.execute(EditorCommand::CursorToTop);
+        assert_eq!(editor.scroll_offset, 11);
+
+        editor.config.scroll_past_end = true;
+        editor.execute(EditorCommand::CursorToTop);
+        assert_eq!(editor.scroll_offset, 20);
+        editor.scroll_offset = 0;
+        editor.cursor = 0;
+        assert_eq!(scroll_to_end(&mut editor), 20);
+        // The cursor was pulled along, but no further than the last line
+        assert_eq!(editor.cursor_line_col(), (20, 0));
+        editor.scroll_to_cursor();
+        assert_eq!(editor.scroll_offset, 20);
+
+        // Turned off, the next render brings the last line to the bottom
+        editor.config.scroll_past_end = false;
+        editor.scroll_to_cursor();
+        assert_eq!(editor.scroll_offset, 11);
+    }
+
+    #[test]
+    fn readline_keys_edit_and_move_only_in_the_readline_keymap() {
+        let mut editor = Editor::new(Config::default());
+        let ctrl = |c| (KeyCode::Char(c), KeyModifiers::CONTROL);
+        editor.set_content("echo hello world\nnext");
+        editor.cursor = 16;
+        press(&mut editor, &[(KeyCode::Backspace, KeyModifiers::ALT)]);
+        assert_eq!(editor.content(), "echo hello \nnext");
+        // Ctrl+W means nothing by default
+        press(&mut editor, &[ctrl('w')]);
+        assert_eq!(editor.content(), "echo hello \nnext");
+
+        editor.config.keymap = command::Keymap::Readline;
+        press(&mut editor, &[ctrl('w')]);
+        assert_eq!(editor.content(), "echo \nnext");
+        press(&mut editor, &[ctrl('a')]);
+        assert_eq!(editor.cursor, 0);
+        assert!(!editor.should_quit);
+        press(&mut editor, &[ctrl('e'), ctrl('k')]);
+        assert_eq!(editor.content(), "echo next");
+        press(&mut editor, &[ctrl('k')]);
+        assert_eq!(editor.content(), "echo ");
+        press(&mut editor, &[ctrl('u'), (KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.content(), "echo ");
+        press(&mut editor, &[ctrl('q')]);
+        assert!(editor.should_quit);
+    }
+
+    #[test]
+    fn large_pastes_undo_exactly_without_a_copy() {
+        let mut config = Config::default();
+        config.undo_share_size = 100;
+        let mut editor = Editor::new(config);
+        editor.set_content("before after");
+        editor.cursor = 7;
+        let big: String = (0..200).map(|i| format!("line {} é\n", i)).collect();
+        editor.paste(&big);
+        let pasted = editor.content();
+        // The last action, peeked at by stepping the history back and forth
+        let action = editor.history.undo();
+        editor.history.redo();
+        assert!(matches!(action, Some(Action::Insert { text: UndoText::Shared(_), .. })));
+        editor.execute(EditorCommand::Undo);
+        assert_eq!(editor.content(), "before after");
+        editor.execute(EditorCommand::Redo);
+        assert_eq!(editor.content(), pasted);
+
+        // Pasted over a selection, both the old and new text are kept shared
+        editor.anchor = Some(7);
+        editor.cursor = 7 + big.len();
+        editor.paste(&big.to_uppercase());
+        editor.execute(EditorCommand::Undo);
+        assert_eq!(editor.content(), pasted);
+        editor.execute(EditorCommand::Redo);
+        assert_eq!(editor.content(), format!("before {}after", big.to_uppercase()));
+        editor.execute(EditorCommand::Undo);
+        editor.execute(EditorCommand::Undo);
+        assert_eq!(editor.content(), "before after");
+    }
+
+    #[test]
+    fn trailing_blank_lines_go_in_one_undo_step() {
+        let mut editor = Editor::new(Config::default());
+        let text = format!("{}end  \n\n \t\n\r\n\n", "x".repeat(1000));
+        editor.set_content(&text);
+        editor.cursor = text.len() - 2;
+        editor.execute(EditorCommand::DeleteTrailingBlankLines);
+        assert_eq!(editor.content(), format!("{}end  \n", "x".repeat(1000)));
+        assert_eq!(editor.cursor, 1006);
+        assert_eq!(editor.status_message.as_deref(), Some("Deleted 4 trailing blank lines"));
+        editor.execute(EditorCommand::DeleteTrailingBlankLines);
+        assert_eq!(editor.status_message.as_deref(), Some("No trailing blank lines"));
+        editor.execute(EditorCommand::Undo);
+        assert_eq!(editor.content(), text);
+
+        // No final line break to keep, and a buffer of nothing but blank lines
+        editor.set_content("a\n\n ");
+        editor.execute(EditorCommand::DeleteTrailingBlankLines);
+        assert_eq!(editor.content(), "a\n");
+        editor.set_content("\n\n");
+        editor.execute(EditorCommand::DeleteTrailingBlankLines);
+        assert_eq!(editor.content(), "\n");
+        editor.set_content("a");
+        editor.execute(EditorCommand::DeleteTrailingBlankLines);
+        assert_eq!(editor.status_message.as_deref(), Some("No trailing blank lines"));
+    }
+
+    #[test]
+    fn duplicate_and_comment_keeps_the_old_line_above() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("fn f() {\n    g(1);\n}\n");
+        editor.cursor = 15;
+        editor.execute(EditorCommand::DuplicateAndComment);
+        assert_eq!(editor.status_message.as_deref(), Some("No comments known for this file type"));
+
+        editor.filename = Some("f.rs".to_string());
+        editor.execute(EditorCommand::DuplicateAndComment);
+        assert_eq!(editor.content(), "fn f() {\n    // g(1);\n    g(1);\n}\n");
+        assert_eq!(editor.cursor, 28);
+        press(&mut editor, &[(KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.content(), "fn f() {\n    g(1);\n}\n");
+
+        editor.cursor = editor.content().len();
+        editor.execute(EditorCommand::DuplicateAndComment);
+        assert_eq!(editor.content(), "fn f() {\n    g(1);\n}\n");
+    }
+
+    #[test]
+    fn checkpoints_revert_many_actions_at_once() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("start\n");
+        editor.cursor = 6;
+        for _ in 0..CHECKPOINT_INTERVAL + 10 {
+            type_str(&mut editor, "x");
+        }
+        assert_eq!(editor.checkpoints.len(), 2);
+
+        // Back to the checkpoint, then to the start, with redo still able
+        // to bring every action back
+        editor.execute(EditorCommand::RevertToCheckpoint);
+        assert_eq!(editor.content(), format!("start\n{}", "x".repeat(CHECKPOINT_INTERVAL)));
+        assert_eq!(editor.cursor, 6 + CHECKPOINT_INTERVAL);
+        editor.execute(EditorCommand::RevertToCheckpoint);
+        assert_eq!(editor.content(), "start\n");
+        editor.execute(EditorCommand::RevertToCheckpoint);
+        assert_eq!(editor.status_message.as_deref(), Some("No checkpoint to revert to"));
+        for _ in 0..CHECKPOINT_INTERVAL + 10 {
+            editor.execute(EditorCommand::Redo);
+        }
+        assert_eq!(editor.content(), format!("start\n{}", "x".repeat(CHECKPOINT_INTERVAL + 10)));
+
+        // A new edit after undoing branches off before the later
+        // checkpoint, which is kept but no longer on the way back
+        for _ in 0..20 {
+            editor.execute(EditorCommand::Undo);
+        }
+        type_str(&mut editor, "y");
+        assert_eq!(editor.checkpoints.len(), 2);
+        editor.execute(EditorCommand::RevertToCheckpoint);
+        assert_eq!(editor.content(), "start\n");
+    }
+
+    #[test]
+    fn undone_edits_stay_reachable_as_branches() {
+        let mut editor = Editor::new(Config::default());
+        type_str(&mut editor, "ab");
+        editor.execute(EditorCommand::Undo);
+        type_str(&mut editor, "c");
+        assert_eq!(editor.content(), "ac");
+        editor.execute(EditorCommand::Redo);
+        assert_eq!(editor.status_message.as_deref(), Some("Nothing to redo"));
+
+        editor.execute(EditorCommand::PreviousBranch);
+        assert_eq!((editor.content().as_str(), editor.cursor), ("ab", 2));
+        editor.execute(EditorCommand::NextBranch);
+        assert_eq!(editor.content(), "ac");
+
+        // Redo follows the branch last visited
+        editor.execute(EditorCommand::PreviousBranch);
+        editor.execute(EditorCommand::Undo);
+        editor.execute(EditorCommand::Redo);
+        assert_eq!(editor.content(), "ab");
+
+        // The tree opens on the current edit, and Enter goes to the one picked
+        editor.execute(EditorCommand::UndoTree);
+        let overlay = editor.overlay.as_ref().unwrap();
+        let OverlayKind::History(nodes) = &overlay.kind else { panic!("not the undo tree") };
+        assert_eq!(nodes[overlay.selected_index().unwrap()], editor.history.current());
+        assert_eq!(nodes.len(), 4);
+        press(&mut editor, &[(KeyCode::Down, KeyModifiers::NONE), (KeyCode::Enter, KeyModifiers::NONE)]);
+        assert_eq!(editor.content(), "ac");
+        assert!(editor.overlay.is_none());
+
+        editor.execute(EditorCommand::Undo);
+        editor.execute(EditorCommand::NextBranch);
+        assert_eq!(editor.status_message.as_deref(), Some("No other branch here"));
+    }
+
+    #[test]
+    fn brackets_select_and_grow_outwards() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("f(a, [b, c]) x");
+        let outer = (KeyCode::Char('m'), KeyModifiers::ALT);
+        let inner = (KeyCode::Char('M'), KeyModifiers::ALT | KeyModifiers::SHIFT);
+
+        editor.cursor = 1;
+        press(&mut editor, &[outer]);
+        assert_eq!(editor.selection(), Some(1..12));
+
+        editor.anchor = None;
+        editor.cursor = 7;
+        press(&mut editor, &[inner]);
+        assert_eq!(editor.selection(), Some(6..10));
+        press(&mut editor, &[inner]);
+        assert_eq!(editor.selection(), Some(2..11));
+        press(&mut editor, &[outer]);
+        assert_eq!(editor.selection(), Some(1..12));
+
+        press(&mut editor, &[outer]);
+        assert_eq!(editor.status_message.as_deref(), Some("No matching bracket"));
+        assert_eq!(editor.selection(), Some(1..12));
+    }
+
+    #[test]
+    fn selection_expands_by_scope_and_shrinks_back() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("fn f() {\n    call(first, second);\n}\n");
+        let expand = (KeyCode::Up, KeyModifiers::ALT);
+        let shrink = (KeyCode::Down, KeyModifiers::ALT);
+        editor.cursor = 21;
+
+        let mut grown = Vec::new();
+        for _ in 0..6 {
+            press(&mut editor, &[expand]);
+            grown.push(editor.selection().unwrap());
+        }
+        // Word, brackets' inside, brackets, line, the braces' inside, braces
+        assert_eq!(grown, [18..23, 18..31, 17..32, 9..33, 8..34, 7..35]);
+        // ...then the lines they're on, and everything
+        press(&mut editor, &[expand, expand]);
+        assert_eq!(editor.selection(), Some(0..36));
+
+        press(&mut editor, &[shrink, shrink, shrink]);
+        assert_eq!(editor.selection(), Some(8..34));
+
+        // Changing the selection by hand starts afresh
+        press(&mut editor, &[(KeyCode::Left, KeyModifiers::SHIFT), shrink]);
+        assert_eq!(editor.selection(), Some(8..33));
+    }
+
+    #[test]
+    fn undo_scrolls_the_change_back_into_view() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content(&"line\n".repeat(100));
+        editor.view_rows = 10;
+        type_str(&mut editor, "edited ");
+        editor.cursor = editor.rope.len();
+        editor.scroll_to_cursor(); // As the next render would
+        assert_eq!(editor.scroll_offset, 91);
+
+        let visible = |editor: &Editor| {
+            let (line, _) = editor.cursor_line_col();
+            (editor.scroll_offset..editor.scroll_offset + editor.view_rows).contains(&line)
+        };
+        press(&mut editor, &[(KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.cursor_line_col(), (0, 6));
+        assert!(visible(&editor));
+        editor.cursor = editor.rope.len();
+        press(&mut editor, &[(KeyCode::Char('y'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.cursor_line_col(), (0, 7));
+        assert!(visible(&editor));
+    }
+
+    #[test]
+    fn esc_backs_out_one_state_at_a_time() {
+        let mut editor = Editor::new(Config::default());
+        let esc = (KeyCode::Esc, KeyModifiers::NONE);
+        type_str(&mut editor, "abc");
+        press(&mut editor, &[(KeyCode::Left, KeyModifiers::SHIFT), (KeyCode::Char('p'), KeyModifiers::CONTROL)]);
+        assert!(editor.selection().is_some() && editor.overlay.is_some());
+        press(&mut editor, &[esc]);
+        assert!(editor.selection().is_none() && editor.overlay.is_some());
+        press(&mut editor, &[esc, esc]);
+        assert!(editor.overlay.is_none());
+
+        press(&mut editor, &[(KeyCode::Char('x'), KeyModifiers::CONTROL)]);
+        type_str(&mut editor, "out.txt");
+        press(&mut editor, &[esc]);
+        assert!(editor.prompt.is_none() && editor.filename.is_none());
+        assert_eq!(editor.content(), "abc");
+    }
+
+    #[test]
+    fn swap_position_toggles_and_follows_edits() {
+        let mut editor = Editor::new(Config::default());
+        let swap = (KeyCode::Char('6'), KeyModifiers::CONTROL);
+        press(&mut editor, &[swap]);
+        assert_eq!(editor.status_message.as_deref(), Some("No previous position"));
+
+        type_str(&mut editor, "hello\nworld");
+        editor.jump_to(0);
+        press(&mut editor, &[swap]);
+        assert_eq!(editor.cursor, 11);
+        press(&mut editor, &[swap]);
+        assert_eq!(editor.cursor, 0);
+
+        type_str(&mut editor, ">>");
+        press(&mut editor, &[swap]);
+        assert_eq!(editor.cursor, 13);
+        press(&mut editor, &[swap]);
+        assert_eq!(editor.cursor, 2);
+    }
+
+    #[test]
+    fn search_repeats_wraps_and_esc_clears_highlights() {
+        let mut editor = Editor::new(Config::default());
+        let enter = (KeyCode::Enter, KeyModifiers::NONE);
+        let search = (KeyCode::Char('f'), KeyModifiers::CONTROL);
+        type_str(&mut editor, "one two one");
+        editor.cursor = 0;
+        press(&mut editor, &[search]);
+        type_str(&mut editor, "one");
+        press(&mut editor, &[enter]);
+        assert_eq!(editor.cursor, 8);
+        assert!(editor.highlight_matches);
+
+        press(&mut editor, &[search, enter]);
+        assert_eq!(editor.cursor, 0);
+        assert_eq!(editor.status_message.as_deref(), Some("Search wrapped to the top"));
+        press(&mut editor, &[(KeyCode::F(3), KeyModifiers::NONE)]);
+        assert_eq!(editor.cursor, 8);
+
+        press(&mut editor, &[(KeyCode::Esc, KeyModifiers::NONE)]);
+        assert!(!editor.highlight_matches);
+        assert_eq!(editor.search.as_deref(), Some("one"));
+    }
+
+    #[test]
+    fn reopen_brings_back_the_last_closed_file() {
+        let dir = TempDir::new("reopen");
+        fs::write(dir.join("a.txt"), "first").unwrap();
+        fs::write(dir.join("b.txt"), "second").unwrap();
+        let mut config = Config::default();
+        config.remember_position = false;
+        let mut editor = Editor::new(config);
+        let reopen = (KeyCode::Char('T'), KeyModifiers::CONTROL | KeyModifiers::SHIFT);
+        let finish = |editor: &mut Editor| {
+            while editor.loading.is_some() {
+                editor.continue_loading();
+            }
+        };
+
+        editor.load_file(dir.join("a.txt")).unwrap();
+        editor.open_file(&dir.join("b.txt"));
+        finish(&mut editor);
+        assert_eq!(editor.content(), "second");
+        press(&mut editor, &[reopen]);
+        finish(&mut editor);
+        assert_eq!(editor.content(), "first");
+
+        // Reopening goes back and forth between the two
+        press(&mut editor, &[reopen]);
+        finish(&mut editor);
+        assert_eq!(editor.content(), "second");
+        editor.new_buffer();
+        press(&mut editor, &[reopen]);
+        finish(&mut editor);
+        assert_eq!(editor.content(), "second");
+
+        // Saying no to losing changes keeps the file to reopen later
+        type_str(&mut editor, "!");
+        press(&mut editor, &[reopen, (KeyCode::Char('n'), KeyModifiers::NONE)]);
+        assert_eq!(editor.closed, [dir.join("a.txt")]);
+    }
+
+    #[test]
+    fn sessions_bring_back_the_file_view_and_closed_files() {
+        let dir = TempDir::new("session");
+        fs::write(dir.join("a.txt"), "first").unwrap();
+        fs::write(dir.join("b.txt"), "one\ntwo\nthree\nfour\n").unwrap();
+        fs::write(dir.join("c.txt"), "third").unwrap();
+        let mut config = Config::default();
+        config.remember_position = false;
+        let mut editor = Editor::new(config.clone());
+        let enter = (KeyCode::Enter, KeyModifiers::NONE);
+        let finish = |editor: &mut Editor| {
+            while editor.loading.is_some() {
+                editor.continue_loading();
+            }
+        };
+
+        editor.load_file(dir.join("a.txt")).unwrap();
+        editor.open_file(&dir.join("c.txt"));
+        finish(&mut editor);
+        editor.open_file(&dir.join("b.txt"));
+        finish(&mut editor);
+        editor.cursor = 10; // Line 2, column 2
+        editor.scroll_offset = 1;
+        let file = dir.join("work.session");
+        editor.execute(EditorCommand::SaveSession);
+        type_str(&mut editor, &file.to_string_lossy());
+        press(&mut editor, &[enter]);
+        assert_eq!(editor.status_message, Some(format!("Session saved to {}", file.display())));
+
+        // A fresh editor is back on the same spot, with c.txt gone
+        fs::remove_file(dir.join("c.txt")).unwrap();
+        let mut editor = Editor::new(config);
+        editor.execute(EditorCommand::RestoreSession);
+        type_str(&mut editor, &file.to_string_lossy());
+        press(&mut editor, &[enter]);
+        finish(&mut editor);
+        assert_eq!(editor.content(), "one\ntwo\nthree\nfour\n");
+        assert_eq!((editor.cursor, editor.scroll_offset), (10, 1));
+        let warning = format!("Session restored without missing files: {}", dir.join("c.txt").display());
+        assert_eq!(editor.status_message, Some(warning));
+        press(&mut editor, &[(KeyCode::Char('T'), KeyModifiers::CONTROL | KeyModifiers::SHIFT)]);
+        finish(&mut editor);
+        assert_eq!(editor.content(), "first");
+
+        assert!(editor.restore_session(&dir.join("none.session")).is_err());
+    }
+
+    #[test]
+    fn revert_reloads_the_saved_file_after_asking() {
+        let dir = TempDir::new("revert");
+        let path = dir.join("revert.txt");
+        fs::write(&path, "one\ntwo\n").unwrap();
+        let mut config = Config::default();
+        config.remember_position = false;
+        let mut editor = Editor::new(config);
+        editor.execute(EditorCommand::RevertToSaved);
+        assert_eq!(editor.status_message.as_deref(), Some("No saved file to revert to"));
+
+        editor.load_file(&path).unwrap();
+        press(&mut editor, &[(KeyCode::Down, KeyModifiers::NONE)]);
+        type_str(&mut editor, "a much longer second line");
+        editor.execute(EditorCommand::RevertToSaved);
+        press(&mut editor, &[(KeyCode::Char('n'), KeyModifiers::NONE)]);
+        assert!(editor.dirty);
+        editor.execute(EditorCommand::RevertToSaved);
+        press(&mut editor, &[(KeyCode::Char('y'), KeyModifiers::NONE)]);
+        assert_eq!(editor.content(), "one\ntwo\n");
+        assert!(!editor.dirty);
+        assert_eq!(editor.history.depth(), 0);
+        // Still on the second line, as far along it as it now goes
+        assert_eq!(editor.cursor, 7);
+    }
+
+    #[test]
+    fn large_files_are_only_opened_once_confirmed() {
+        let dir = TempDir::new("large");
+        let path = dir.join("large.txt");
+        fs::write(&path, "x".repeat(2000)).unwrap();
+        let mut config = Config::parse("large_file_size = 0\n");
+        assert_eq!(config.large_file_size, None);
+        let huge = Config::parse(&format!("large_file_size = {}\n", u64::MAX));
+        assert_eq!(huge.large_file_size, Config::default().large_file_size);
+        config.large_file_size = Some(1000);
+        config.remember_position = false;
+        let mut editor = Editor::new(config);
+
+        editor.guard_unsaved(AfterDiscard::Open(path.clone()));
+        assert_eq!(editor.prompt.as_ref().unwrap().label, "File is 2.0 KB. Open anyway? (y/n)");
+        press(&mut editor, &[(KeyCode::Char('n'), KeyModifiers::NONE)]);
+        assert!(editor.loading.is_none());
+        assert_eq!(editor.content(), "");
+
+        editor.guard_unsaved(AfterDiscard::Open(path.clone()));
+        press(&mut editor, &[(KeyCode::Char('y'), KeyModifiers::NONE)]);
+        while editor.loading.is_some() {
+            editor.continue_loading();
+        }
+        assert_eq!(editor.content().len(), 2000);
+        assert_eq!(status::format_size(3 << 30), "3.0 GB");
+    }
+
+    #[test]
+    fn insert_file_is_one_undo_step_with_the_buffers_endings() {
+        let dir = TempDir::new("insert");
+        let path = dir.join("insert.txt");
+        fs::write(&path, b"caf\xe9\nbar\n").unwrap();
+        let mut editor = Editor::new(Config::default());
+        type_str(&mut editor, "a\r\nb");
+        press(&mut editor, &[(KeyCode::Left, KeyModifiers::NONE)]);
+
+        editor.insert_file(path.to_str().unwrap());
+        assert_eq!(editor.content(), "a\r\ncafé\r\nbar\r\nb");
+        press(&mut editor, &[(KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.content(), "a\r\nb");
+
+        fs::remove_file(&path).unwrap();
+        editor.insert_file(path.to_str().unwrap());
+        assert_eq!(editor.content(), "a\r\nb");
+        assert!(editor.status_message.unwrap().starts_with("Can't read"));
+    }
+
+    #[test]
+    fn write_selection_leaves_the_buffer_alone_and_asks_before_overwriting() {
+        let dir = TempDir::new("write");
+        let path = dir.join("write.txt");
+        let mut editor = Editor::new(Config::default());
+        editor.filename = Some("original.txt".to_string());
+        type_str(&mut editor, "hello world");
+        let select_left = (KeyCode::Left, KeyModifiers::SHIFT);
+        press(&mut editor, &[select_left, select_left, select_left, select_left, select_left]);
+
+        editor.write_selection(&path, false);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "world");
+        assert_eq!(editor.filename.as_deref(), Some("original.txt"));
+        assert!(editor.dirty);
+
+        editor.anchor = None;
+        editor.write_selection(&path, false);
+        assert!(editor.prompt.is_some());
+        press(&mut editor, &[(KeyCode::Char('n'), KeyModifiers::NONE)]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "world");
+        editor.write_selection(&path, false);
+        press(&mut editor, &[(KeyCode::Char('y'), KeyModifiers::NONE)]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello world");
+
+        // Written the way a save is: through a symlink to its target, with
+        // no temporary file left behind
+        #[cfg(unix)]
+        {
+            let link = dir.join("link.txt");
+            std::os::unix::fs::symlink("write.txt", &link).unwrap();
+            type_str(&mut editor, "!");
+            editor.write_selection(&link, true);
+            assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+            assert_eq!(fs::read_to_string(&path).unwrap(), "hello !world");
+            assert_eq!(dir.read_dir().unwrap().count(), 2);
+        }
+    }
+
+    #[test]
+    fn up_and_down_keep_the_screen_column_across_tabs() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("abcdefgh\n\tx\nab\tc");
+        editor.cursor = 5; // Under the f
+        press(&mut editor, &[(KeyCode::Down, KeyModifiers::NONE)]);
+        // The tab covers columns 0-3 and the x column 4, so column 5 is past
+        // the end of the line
+        assert_eq!(editor.cursor_line_col(), (1, 2));
+        editor.cursor = 4; // Under the e
+        press(&mut editor, &[(KeyCode::Down, KeyModifiers::NONE)]);
+        assert_eq!(editor.cursor_line_col(), (1, 1));
+        press(&mut editor, &[(KeyCode::Down, KeyModifiers::NONE)]);
+        assert_eq!(editor.cursor_line_col(), (2, 3));
+        press(&mut editor, &[(KeyCode::Up, KeyModifiers::NONE), (KeyCode::Up, KeyModifiers::NONE)]);
+        assert_eq!(editor.cursor_line_col(), (0, 4));
+    }
+
+    #[test]
+    fn soft_tab_motion_steps_over_indent_levels() {
+        let mut config = Config::default();
+        config.soft_tab_motion = true;
+        let mut editor = Editor::new(config);
+        editor.set_content("          x  y");
+        let (left, right) = ((KeyCode::Left, KeyModifiers::NONE), (KeyCode::Right, KeyModifiers::NONE));
+
+        let mut stops = Vec::new();
+        for _ in 0..5 {
+            press(&mut editor, &[right]);
+            stops.push(editor.cursor);
+        }
+        // Ten spaces at width 4: two full levels, then what's left; past the
+        // indent it's one character at a time again
+        assert_eq!(stops, [4, 8, 10, 11, 12]);
+        press(&mut editor, &[left, left]);
+        assert_eq!(editor.cursor, 10);
+        press(&mut editor, &[left]);
+        assert_eq!(editor.cursor, 8);
+        press(&mut editor, &[left, left]);
+        assert_eq!(editor.cursor, 0);
+    }
+
+    #[test]
+    fn open_file_under_cursor_goes_to_the_named_line() {
+        let dir = TempDir::new("gf");
+        fs::write(dir.join("notes.txt"), "see target.txt:3 or missing.txt").unwrap();
+        fs::write(dir.join("target.txt"), "one\ntwo\nthree\n").unwrap();
+        let mut config = Config::default();
+        config.remember_position = false;
+        let mut editor = Editor::new(config);
+        editor.load_file(dir.join("notes.txt")).unwrap();
+
+        editor.cursor = 22;
+        editor.open_file_under_cursor();
+        assert!(editor.status_message.as_deref().unwrap().starts_with("No such file"));
+
+        editor.cursor = 6;
+        press(&mut editor, &[(KeyCode::Enter, KeyModifiers::CONTROL)]);
+        while editor.loading.is_some() {
+            editor.continue_loading();
+        }
+        assert!(editor.filename.as_deref().unwrap().ends_with("target.txt"));
+        assert_eq!(editor.cursor_line_col(), (2, 0));
+    }
+
+    #[test]
+    fn companions_open_or_are_created() {
+        let dir = TempDir::new("companion");
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join("list.h"), "int len(void);\n").unwrap();
+        fs::write(dir.join("list.c"), "int len(void) { return 0; }\n").unwrap();
+        fs::write(dir.join("src").join("rope.rs"), "").unwrap();
+        let mut config = Config::default();
+        config.remember_position = false;
+        let mut editor = Editor::new(config);
+        let companion = (KeyCode::Char('o'), KeyModifiers::ALT);
+
+        editor.load_file(dir.join("list.h")).unwrap();
+        press(&mut editor, &[companion]);
+        while editor.loading.is_some() {
+            editor.continue_loading();
+        }
+        assert!(editor.filename.as_deref().unwrap().ends_with("list.c"));
+
+        // No tests/rope.rs yet: asked, then an empty buffer under that name
+        editor.load_file(dir.join("src").join("rope.rs")).unwrap();
+        press(&mut editor, &[companion, (KeyCode::Char('y'), KeyModifiers::NONE)]);
+        let tests = dir.join("tests").join("rope.rs");
+        assert_eq!(editor.filename.as_deref(), Some(tests.to_str().unwrap()));
+        assert_eq!(editor.content(), "");
+        assert!(dir.join("tests").is_dir());
+        editor.save_file().unwrap();
+        assert!(tests.is_file());
+
+        editor.filename = Some(dir.join("notes.txt").to_string_lossy().into_owned());
+        press(&mut editor, &[companion]);
+        assert!(editor.status_message.as_deref().unwrap().starts_with("No companion rule"));
+    }
+
+    #[test]
+    fn enter_can_trim_the_line_it_leaves() {
+        let mut editor = Editor::new(Config::default());
+        let enter = (KeyCode::Enter, KeyModifiers::NONE);
+        editor.set_content("    let x = 1;   \n");
+        editor.cursor = 17;
+        press(&mut editor, &[enter]);
+        assert_eq!(editor.content(), "    let x = 1;   \n    \n");
+
+        editor.config.trim_on_enter = true;
+        editor.set_content("    let x = 1;  \t\n");
+        editor.cursor = 17;
+        press(&mut editor, &[enter]);
+        assert_eq!(editor.content(), "    let x = 1;\n    \n");
+        assert_eq!(editor.cursor, 19);
+        press(&mut editor, &[(KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.content(), "    let x = 1;  \t\n");
+
+        // Between brackets, the pair still opens up
+        editor.set_content("f( )");
+        editor.cursor = 3;
+        press(&mut editor, &[enter]);
+        assert_eq!(editor.content(), "f(\n    \n)");
+        assert_eq!(editor.cursor, 7);
+    }
+
+    #[test]
+    fn enter_at_the_end_of_an_unterminated_file_can_just_end_the_line() {
+        let mut editor = Editor::new(Config::default());
+        let enter = (KeyCode::Enter, KeyModifiers::NONE);
+        editor.set_content("    one");
+        editor.cursor = editor.rope.len();
+        press(&mut editor, &[enter]);
+        assert_eq!((editor.content().as_str(), editor.cursor), ("    one\n    ", 12));
+
+        editor.config.open_line_at_eof = false;
+        editor.set_content("    one");
+        editor.cursor = editor.rope.len();
+        press(&mut editor, &[enter]);
+        assert_eq!((editor.content().as_str(), editor.cursor), ("    one\n", 7));
+        // Now it isn't the end of the file, so a line opens as usual
+        press(&mut editor, &[enter]);
+        assert_eq!((editor.content().as_str(), editor.cursor), ("    one\n    \n", 12));
+        press(&mut editor, &[(KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        press(&mut editor, &[(KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.content(), "    one");
+
+        // An empty buffer has no line to end, so Enter opens one
+        editor.set_content("");
+        press(&mut editor, &[enter]);
+        assert_eq!((editor.content().as_str(), editor.cursor), ("\n", 1));
+    }
+
+    #[test]
+    fn repeat_runs_the_last_edit_again() {
+        let mut editor = Editor::new(Config::default());
+        let repeat = (KeyCode::Char('.'), KeyModifiers::ALT);
+        press(&mut editor, &[repeat]);
+        assert_eq!(editor.status_message.as_deref(), Some("Nothing to repeat"));
+
+        type_str(&mut editor, "ab");
+        press(&mut editor, &[(KeyCode::Backspace, KeyModifiers::NONE), (KeyCode::Enter, KeyModifiers::NONE)]);
+        editor.paste("x");
+        assert_eq!(editor.content(), "a\nx");
+        press(&mut editor, &[(KeyCode::Up, KeyModifiers::NONE), (KeyCode::Left, KeyModifiers::NONE), repeat]);
+        assert_eq!(editor.content(), "a\nxa\nx");
+
+        // Moving ends the edit, so the next one starts afresh
+        press(&mut editor, &[(KeyCode::Down, KeyModifiers::NONE)]);
+        type_str(&mut editor, "!");
+        press(&mut editor, &[repeat, repeat]);
+        assert_eq!(editor.content(), "a\nxa\nx!!!");
+    }
+
+    #[test]
+    fn binary_files_are_refused() {
+        let dir = TempDir::new("binary");
+        fs::write(dir.join("text.txt"), "plain\ttext\r\n").unwrap();
+        fs::write(dir.join("image.png"), b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR").unwrap();
+        fs::write(dir.join("controls"), b"\x01\x02\x03 abc").unwrap();
+        let mut config = Config::default();
+        config.remember_position = false;
+        let mut editor = Editor::new(config);
+
+        editor.load_file(dir.join("text.txt")).unwrap();
+        let err = editor.load_file(dir.join("image.png")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(editor.load_file(dir.join("controls")).is_err());
+        assert_eq!(editor.content(), "plain\ttext\r\n");
+
+        editor.open_file(&dir.join("image.png"));
+        assert!(editor.loading.is_none());
+        assert_eq!(
+            editor.status_message.as_deref(),
+            Some("Open failed: looks like a binary file, not opening it")
+        );
+    }
+
+    #[test]
+    fn ctrl_home_and_end_land_on_the_ends_of_the_buffer() {
+        let start = (KeyCode::Home, KeyModifiers::CONTROL);
+        let end = (KeyCode::End, KeyModifiers::CONTROL);
+        for text in ["one\ntwo é", "one\ntwo é\n"] {
+            let mut editor = Editor::new(Config::default());
+            editor.set_content(text);
+            editor.cursor = 2;
+            press(&mut editor, &[end]);
+            assert_eq!(editor.cursor, text.len());
+            press(&mut editor, &[start]);
+            assert_eq!(editor.cursor, 0);
+
+            // Both are jumps, so Alt+Left retraces them
+            press(&mut editor, &[(KeyCode::Left, KeyModifiers::ALT)]);
+            assert_eq!(editor.cursor, text.len());
+            press(&mut editor, &[(KeyCode::Left, KeyModifiers::ALT)]);
+            assert_eq!(editor.cursor, 2);
+        }
+    }
+
+    #[test]
+    fn invalid_actions_ring_the_bell() {
+        let mut config = Config::default();
+        config.audible_bell = true;
+        let mut editor = Editor::new(config);
+        editor.set_content("ab");
+        press(&mut editor, &[(KeyCode::Right, KeyModifiers::NONE)]);
+        assert!(editor.flash_until.is_none() && !editor.ring);
+
+        for key in [KeyCode::Backspace, KeyCode::Up, KeyCode::Char('z')] {
+            editor.cursor = 0;
+            editor.flash_until = None;
+            editor.ring = false;
+            let modifiers = if key == KeyCode::Char('z') { KeyModifiers::CONTROL } else { KeyModifiers::NONE };
+            press(&mut editor, &[(key, modifiers)]);
+            assert!(editor.flash_until.is_some() && editor.ring, "{:?}", key);
+        }
+
+        // The main loop takes the flash down once its time is up
+        editor.flash_until = Some(Instant::now());
+        editor.dirty_display = false;
+        editor.check_timers();
+        assert!(editor.flash_until.is_none() && editor.dirty_display);
+    }
+
+    #[test]
+    fn clicks_place_the_cursor_and_select_words_and_lines() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("fn main() {\n\tlet x_1 = 2;\n}");
+        editor.view_rows = 10;
+        let text = GUTTER_WIDTH;
+
+        editor.click(80, text + 3, 0, 1);
+        assert_eq!((editor.cursor, editor.anchor), (3, None));
+        // Past the end of a line, or below the last one
+        editor.click(80, text + 40, 0, 1);
+        assert_eq!(editor.cursor, 11);
+        editor.click(80, text + 5, 7, 1);
+        assert_eq!(editor.cursor, 27);
+        assert!(editor.offset_at(80, 0, 10).is_none());
+
+        // The tab fills columns 0-3, so column 9 is inside `x_1`
+        editor.click(80, text + 9, 1, 2);
+        assert_eq!(editor.selection(), Some(17..20));
+        editor.click(80, text + 9, 1, 3);
+        assert_eq!(editor.selection(), Some(12..26));
+
+        // A wrapped line takes a row per screen width, pushing the rest down
+        editor.set_content("aaaaaaaaaabbbbbbbbbbcc\nxyz");
+        editor.wrapped_line = Some(0);
+        let width = text + 10;
+        assert_eq!(editor.offset_at(width, text + 3, 1), Some(13));
+        assert_eq!(editor.offset_at(width, text + 9, 2), Some(22));
+        assert_eq!(editor.offset_at(width, text + 1, 3), Some(24));
+        assert_eq!(editor.offset_at(width, text + 1, 5), Some(24));
+    }
+
+    #[test]
+    fn enter_between_brackets_expands_the_block() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("    if x {}");
+        editor.cursor = 10;
+        press(&mut editor, &[(KeyCode::Enter, KeyModifiers::NONE)]);
+        assert_eq!(editor.content(), "    if x {\n        \n    }");
+        assert_eq!(editor.cursor, 19);
+
+        // One undo takes it all back
+        press(&mut editor, &[(KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.content(), "    if x {}");
+
+        // Not between a matching pair: a plain new line
+        editor.set_content("f(]");
+        editor.cursor = 2;
+        press(&mut editor, &[(KeyCode::Enter, KeyModifiers::NONE)]);
+        assert_eq!(editor.content(), "f(\n]");
+
+        editor.config.expand_brackets = false;
+        editor.set_content("[]");
+        editor.cursor = 1;
+        press(&mut editor, &[(KeyCode::Enter, KeyModifiers::NONE)]);
+        assert_eq!(editor.content(), "[\n]");
+    }
+
+    #[test]
+    fn saving_writes_every_chunk_in_the_files_encoding() {
+        let dir = TempDir::new("save");
+        let path = dir.join("latin1.txt");
+        fs::write(&path, b"caf\xe9\n").unwrap();
+        let mut config = Config::default();
+        config.remember_position = false;
+        let mut editor = Editor::new(config);
+        editor.load_file(&path).unwrap();
+        editor.rope = Rope::concat(editor.rope.clone(), Rope::from_string("na\u{ef}ve\n"));
+        editor.save_file().unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xe9\nna\xefve\n");
+
+        assert_eq!(encoding::decode(fs::read(&path).unwrap(), None).0, editor.content());
+
+        // Nothing is written if any of it can't be encoded, and the
+        // temporary file is cleaned up
+        editor.rope = Rope::concat(editor.rope.clone(), Rope::from_string("\u{2603}"));
+        assert!(editor.save_file().is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"caf\xe9\nna\xefve\n");
+        assert_eq!(dir.read_dir().unwrap().count(), 1);
+    }
+
+    #[test]
+    fn save_as_names_the_buffer_only_once_it_is_written() {
+        let dir = TempDir::new("save-as");
+        let mut config = Config::default();
+        config.remember_position = false;
+        let mut editor = Editor::new(config);
+        editor.set_content("text\n");
+        let save_as = |editor: &mut Editor, path: &Path| {
+            editor.execute(EditorCommand::Save);
+            type_str(editor, &path.to_string_lossy());
+            press(editor, &[(KeyCode::Enter, KeyModifiers::NONE)]);
+        };
+
+        save_as(&mut editor, &dir.join("missing").join("a.txt"));
+        assert!(editor.status_message.as_deref().unwrap().starts_with("Save failed"));
+        assert_eq!(editor.filename, None);
+
+        let path = dir.join("a.txt");
+        save_as(&mut editor, &path);
+        assert_eq!(editor.filename.as_deref(), Some(path.to_string_lossy().as_ref()));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "text\n");
+        assert!(!editor.dirty);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn saving_keeps_or_replaces_links_as_configured() {
+        let dir = TempDir::new("links");
+        let (target, link, other) = (dir.join("target.txt"), dir.join("link.txt"), dir.join("other.txt"));
+        fs::write(&target, "old").unwrap();
+        std::os::unix::fs::symlink("target.txt", &link).unwrap();
+        let mut config = Config::default();
+        config.remember_position = false;
+        let mut editor = Editor::new(config);
+
+        // Followed, the target gets the new text and the link stays
+        editor.load_file(&link).unwrap();
+        assert_eq!(editor.link.as_deref(), Some("-> target.txt"));
+        editor.set_content("new");
+        editor.save_file().unwrap();
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+
+        // Not followed, the link becomes a file of its own
+        editor.config.follow_symlinks = false;
+        editor.set_content("own");
+        editor.save_file().unwrap();
+        assert!(!fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(&link).unwrap(), "own");
+        assert_eq!(fs::read_to_string(&target).unwrap(), "new");
+        assert_eq!(editor.link, None);
+
+        // Every name of a hard-linked file sees the save
+        fs::hard_link(&target, &other).unwrap();
+        editor.load_file(&target).unwrap();
+        assert_eq!(editor.link.as_deref(), Some("2 hard links"));
+        editor.set_content("shared");
+        editor.save_file().unwrap();
+        assert_eq!(fs::read_to_string(&other).unwrap(), "shared");
+    }
+
+    #[test]
+    fn settings_view_toggles_options_live() {
+        let mut editor = Editor::new(Config::default());
+        editor.execute(EditorCommand::Settings);
+        type_str(&mut editor, "minim");
+        press(&mut editor, &[(KeyCode::Enter, KeyModifiers::NONE)]);
+        assert!(editor.config.minimap);
+        press(&mut editor, &[(KeyCode::Char(' '), KeyModifiers::NONE)]);
+        assert!(!editor.config.minimap);
+
+        // Still open, showing the current value
+        let overlay = editor.overlay.as_ref().unwrap();
+        let idx = overlay.selected_index().unwrap();
+        assert_eq!(editor.setting_label("minimap"), "minimap            false");
+        assert!(matches!(&overlay.kind, OverlayKind::Settings(keys, changed) if keys[idx] == "minimap" && changed == &["minimap"]));
+
+        press(&mut editor, &[(KeyCode::Esc, KeyModifiers::NONE)]);
+        assert!(editor.overlay.is_none());
+    }
+
+    #[test]
+    fn typing_pasting_and_deleting_replace_the_selection() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("one\ntwo\nthree");
+        editor.cursor = 2;
+        editor.anchor = Some(10); // "e\ntwo\nth"
+        type_str(&mut editor, "X");
+        assert_eq!(editor.content(), "onXree");
+        assert_eq!(editor.cursor, 3);
+        assert!(editor.anchor.is_none());
+        // The whole replacement is one undo step
+        press(&mut editor, &[(KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.content(), "one\ntwo\nthree");
+
+        editor.anchor = Some(4);
+        editor.cursor = 8;
+        editor.paste("2\n");
+        assert_eq!(editor.content(), "one\n2\nthree");
+        editor.anchor = Some(0);
+        press(&mut editor, &[(KeyCode::Backspace, KeyModifiers::NONE)]);
+        assert_eq!(editor.content(), "three");
+        press(&mut editor, &[(KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.content(), "one\n2\nthree");
+    }
+
+    #[test]
+    fn align_on_lines_up_the_first_delimiter() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("a = 1\nlonger = 2\nnone\n\tb == 3\nafter = 4");
+        editor.cursor = 3;
+        editor.anchor = Some(27); // Into the fourth line
+        editor.execute(EditorCommand::AlignOn);
+        type_str(&mut editor, "=");
+        press(&mut editor, &[(KeyCode::Enter, KeyModifiers::NONE)]);
+        let aligned = "a      = 1\nlonger = 2\nnone\n\tb  == 3\nafter = 4"; // The tab is 4 wide
+        assert_eq!(editor.content(), aligned);
+        assert_eq!(editor.selection(), Some(0..aligned.len() - 10));
+
+        press(&mut editor, &[(KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.content(), "a = 1\nlonger = 2\nnone\n\tb == 3\nafter = 4");
+        assert_eq!(align("x\ny", ":", 4), None);
+    }
+
+    #[test]
+    fn block_comments_toggle_around_the_selection() {
+        let mut editor = Editor::new(Config::default());
+        editor.filename = Some("page.html".to_string());
+        editor.set_content("<p>\n<b>x</b>\n</p>");
+        editor.anchor = Some(4);
+        editor.cursor = 12;
+        editor.execute(EditorCommand::ToggleBlockComment);
+        assert_eq!(editor.content(), "<p>\n<!-- <b>x</b> -->\n</p>");
+        assert_eq!(editor.selection(), Some(9..17));
+        editor.execute(EditorCommand::ToggleBlockComment);
+        assert_eq!(editor.content(), "<p>\n<b>x</b>\n</p>");
+        assert_eq!(editor.selection(), Some(4..12));
+        press(&mut editor, &[(KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.content(), "<p>\n<!-- <b>x</b> -->\n</p>");
+
+        editor.filename = Some("notes.txt".to_string());
+        editor.anchor = Some(0);
+        editor.execute(EditorCommand::ToggleBlockComment);
+        assert_eq!(editor.status_message.as_deref(), Some("No block comments known for this file type"));
+    }
+
+    #[test]
+    fn swapping_selection_ends_keeps_the_range() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("abcdef");
+        editor.cursor = 2;
+        let extend = (KeyCode::Right, KeyModifiers::SHIFT);
+        let swap = (KeyCode::Char(';'), KeyModifiers::ALT);
+        press(&mut editor, &[extend, extend, swap]);
+        assert_eq!(editor.selection(), Some(2..4));
+        assert_eq!(editor.cursor, 2);
+
+        // Extending now moves the start
+        press(&mut editor, &[(KeyCode::Left, KeyModifiers::SHIFT)]);
+        assert_eq!(editor.selection(), Some(1..4));
+        press(&mut editor, &[swap]);
+        assert_eq!((editor.cursor, editor.selection()), (4, Some(1..4)));
+
+        press(&mut editor, &[(KeyCode::Esc, KeyModifiers::NONE), swap]);
+        assert_eq!(editor.status_message.as_deref(), Some("Nothing selected"));
+    }
+
+    #[test]
+    fn saving_offers_to_fix_mixed_indentation() {
+        let dir = TempDir::new("mixed");
+        let mut config = Config::parse("detect_indent = false\n[py]\ncheck_mixed_indent = true\n");
+        config.remember_position = false;
+        let mut editor = Editor::new(config);
+        let mixed = "def f():\n\tif x:\n\t    return 1\n    \tpass\n";
+
+        // Other file types aren't checked
+        editor.filename = Some(dir.join("a.txt").to_string_lossy().into_owned());
+        editor.set_content(mixed);
+        editor.save_and_report(editor.filename.clone().unwrap());
+        assert!(editor.prompt.is_none());
+
+        let path = dir.join("a.py");
+        editor.filename = Some(path.to_string_lossy().into_owned());
+        editor.save_and_report(editor.filename.clone().unwrap());
+        assert!(matches!(editor.prompt.as_ref().unwrap().kind, PromptKind::ConfirmNormalizeIndent(_)));
+        press(&mut editor, &[(KeyCode::Char('y'), KeyModifiers::NONE)]);
+        let fixed = "def f():\n    if x:\n        return 1\n        pass\n";
+        assert_eq!(fs::read_to_string(&path).unwrap(), fixed);
+        press(&mut editor, &[(KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.content(), mixed);
+
+        // No keeps the file as it is
+        editor.save_and_report(editor.filename.clone().unwrap());
+        press(&mut editor, &[(KeyCode::Char('n'), KeyModifiers::NONE)]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), mixed);
+        editor.set_content("\tif x:\n\t\t  y\n");
+        assert!(!indent::is_mixed(editor.rope.chars()));
+        assert!(indent::is_mixed("\tx\n  y\n".chars()));
+        assert!(indent::is_mixed("  \tx".chars()));
+        assert!(!indent::is_mixed("x \ty\n  z \t\n".chars()));
+    }
+
+    #[test]
+    fn project_editorconfig_overrides_the_config() {
+        let dir = TempDir::new("project");
+        fs::write(dir.join(".editorconfig"), "root = true\n[*.go]\nindent_style = tab\ntrim_trailing_whitespace = true\n").unwrap();
+        let path = dir.join("main.go");
+        fs::write(&path, "package main  \r\n\r\nfunc f() {} \t\r\n").unwrap();
+        let mut config = Config::parse("detect_indent = false\nindent_style = spaces\n");
+        config.remember_position = false;
+        let mut editor = Editor::new(config);
+
+        editor.load_file(&path).unwrap();
+        assert_eq!(editor.indent.style, IndentStyle::Tabs);
+        editor.cursor = 13; // In the spaces after `package main`
+        editor.save_and_report(editor.filename.clone().unwrap());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "package main\r\n\r\nfunc f() {}\r\n");
+        assert_eq!(editor.cursor, 12);
+        press(&mut editor, &[(KeyCode::Char('z'), KeyModifiers::CONTROL)]);
+        assert_eq!(editor.content(), "package main  \r\n\r\nfunc f() {} \t\r\n");
+
+        // Turned off, only the config counts
+        editor.config.editorconfig = false;
+        editor.load_file(&path).unwrap();
+        assert_eq!(editor.indent.style, IndentStyle::Spaces);
+    }
+
+    #[test]
+    fn paragraph_motions_stop_at_blank_lines() {
+        let mut editor = Editor::new(Config::default());
+        let text = "one\ntwo\n\n  \nthree\n\nfour";
+        editor.set_content(text);
+        let down = (KeyCode::Down, KeyModifiers::CONTROL);
+        let up = (KeyCode::Up, KeyModifiers::CONTROL);
+
+        // Consecutive blank lines are one gap
+        let mut stops = Vec::new();
+        for _ in 0..4 {
+            press(&mut editor, &[down]);
+            stops.push(editor.cursor);
+        }
+        assert_eq!(stops, [8, 18, text.len(), text.len()]);
+        stops.clear();
+        for _ in 0..4 {
+            press(&mut editor, &[up]);
+            stops.push(editor.cursor);
+        }
+        assert_eq!(stops, [18, 9, 0, 0]);
+
+        editor.cursor = 5;
+        press(&mut editor, &[(KeyCode::Char('p'), KeyModifiers::ALT)]);
+        assert_eq!(editor.selection(), Some(0..8));
+        editor.cursor = 9;
+        press(&mut editor, &[(KeyCode::Char('p'), KeyModifiers::ALT)]);
+        assert_eq!(editor.selection(), Some(8..12));
+        editor.cursor = text.len();
+        press(&mut editor, &[(KeyCode::Char('p'), KeyModifiers::ALT)]);
+        assert_eq!(editor.selection(), Some(19..text.len()));
+    }
+
+    #[test]
+    fn cursor_hides_when_idle_and_comes_back_on_input() {
+        let mut config = Config::default();
+        config.hide_cursor_after = Some(Duration::from_secs(5));
+        let mut editor = Editor::new(config);
+        let start = editor.last_key_time;
+        assert_eq!(editor.next_timer(), Some(start + Duration::from_secs(5)));
+        editor.check_timers();
+        assert!(!editor.idle);
+
+        editor.last_key_time = start - Duration::from_secs(6);
+        editor.check_timers();
+        assert!(editor.idle);
+        assert!(editor.cursor_style() == ui::CursorStyle::Hidden);
+        assert_eq!(editor.next_timer(), None);
+
+        editor.handle_event(Event::Key(KeyEvent::new(KeyCode::Char('a'), KeyModifiers::NONE)));
+        assert!(!editor.idle);
+        assert!(editor.cursor_style() != ui::CursorStyle::Hidden);
+    }
 }
@@ -0,0 +1,68 @@
+// Buffer positions that are kept while the text around them is edited
+
+// Where `offset` ends up after `removed` bytes at `start` are replaced by
+// `inserted` bytes. Offsets inside the replaced text move to its start.
+pub fn adjust_offset(offset: usize, start: usize, removed: usize, inserted: usize) -> usize {
+    if offset <= start {
+        offset
+    } else if offset >= start + removed {
+        offset - removed + inserted
+    } else {
+        start
+    }
+}
+
+const MAX_JUMPS: usize = 100;
+
+// Cursor positions from before big jumps, browsable back and forward like
+// browser history
+#[derive(Default)]
+pub struct JumpList {
+    entries: Vec<usize>,
+    index: usize, // entries.len() when not browsing
+}
+
+impl JumpList {
+    pub fn record(&mut self, offset: usize) {
+        // A new jump drops whatever was ahead of the current entry
+        self.entries.truncate(self.index);
+        if self.entries.last() != Some(&offset) {
+            self.entries.push(offset);
+        }
+        if self.entries.len() > MAX_JUMPS {
+            self.entries.remove(0);
+        }
+        self.index = self.entries.len();
+    }
+
+    pub fn back(&mut self, current: usize) -> Option<usize> {
+        if self.index == 0 {
+            return None;
+        }
+        // Remember where we started so forward can get back to it
+        if self.index == self.entries.len() {
+            self.entries.push(current);
+        }
+        self.index -= 1;
+        Some(self.entries[self.index])
+    }
+
+    pub fn forward(&mut self) -> Option<usize> {
+        if self.index + 1 >= self.entries.len() {
+            return None;
+        }
+        self.index += 1;
+        Some(self.entries[self.index])
+    }
+
+    pub fn adjust(&mut self, start: usize, removed: usize, inserted: usize) {
+        for offset in &mut self.entries {
+            *offset = adjust_offset(*offset, start, removed, inserted);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.index = 0;
+    }
+}
@@ -0,0 +1,47 @@
+// Telling single, double and triple clicks apart. Terminals only report
+// presses, so repeated presses on the same cell close together in time
+// count up.
+
+use std::time::{Duration, Instant};
+
+// Longest gap between the presses of a double or triple click
+const MULTI_CLICK_TIME: Duration = Duration::from_millis(400);
+
+#[derive(Default)]
+pub struct Clicks {
+    last: Option<(Instant, u16, u16)>,
+    count: usize,
+}
+
+impl Clicks {
+    // 1, 2 or 3 for a single, double or triple click; a fourth starts over
+    pub fn press(&mut self, column: u16, row: u16, now: Instant) -> usize {
+        let repeated = matches!(
+            self.last,
+            Some((at, c, r)) if (c, r) == (column, row) && now.duration_since(at) <= MULTI_CLICK_TIME
+        );
+        self.count = if repeated { self.count % 3 + 1 } else { 1 };
+        self.last = Some((now, column, row));
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quick_presses_on_one_cell_count_up() {
+        let mut clicks = Clicks::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(clicks.press(3, 1, at(0)), 1);
+        assert_eq!(clicks.press(3, 1, at(200)), 2);
+        assert_eq!(clicks.press(3, 1, at(400)), 3);
+        assert_eq!(clicks.press(3, 1, at(500)), 1);
+
+        // Too slow, or somewhere else, is a new single click
+        assert_eq!(clicks.press(3, 1, at(1000)), 1);
+        assert_eq!(clicks.press(4, 1, at(1100)), 1);
+    }
+}
@@ -0,0 +1,158 @@
+// Pop-up list with a filter prompt, drawn over the top of the text area.
+// The command palette, the file finder, the settings view and the undo
+// tree are built on it.
+
+use crossterm::{
+    queue,
+    style::{Attribute, Color, Print, ResetColor, SetAttribute, SetBackgroundColor},
+};
+use std::io;
+use std::path::PathBuf;
+
+use crate::command::EditorCommand;
+use crate::fuzzy;
+
+const MAX_WIDTH: usize = 60;
+const MAX_RESULTS: usize = 10;
+
+// What the overlay's entries stand for, so Enter knows what to do
+pub enum OverlayKind {
+    Commands(Vec<EditorCommand>),
+    Files(Vec<PathBuf>),
+    // Config keys, and the ones changed since the view opened
+    Settings(Vec<&'static str>, Vec<&'static str>),
+    // Undo history nodes, one per line of the tree
+    History(Vec<usize>),
+}
+
+pub struct Overlay {
+    pub kind: OverlayKind,
+    title: &'static str,
+    query: String,
+    candidates: Vec<String>,
+    ranked: Vec<usize>, // Indices into `candidates`, best match first
+    selected: usize,
+}
+
+impl Overlay {
+    pub fn new(kind: OverlayKind, title: &'static str, candidates: Vec<String>) -> Self {
+        let ranked = (0..candidates.len()).collect();
+        Overlay {
+            kind,
+            title,
+            query: String::new(),
+            candidates,
+            ranked,
+            selected: 0,
+        }
+    }
+
+    pub fn command_palette(commands: Vec<EditorCommand>) -> Self {
+        let names = commands.iter().map(|cmd| cmd.name().to_string()).collect();
+        Overlay::new(OverlayKind::Commands(commands), "Command", names)
+    }
+
+    pub fn file_finder(files: Vec<PathBuf>) -> Self {
+        let names = files.iter().map(|path| path.to_string_lossy().into_owned()).collect();
+        Overlay::new(OverlayKind::Files(files), "Open", names)
+    }
+
+    pub fn settings(keys: Vec<&'static str>, labels: Vec<String>) -> Self {
+        Overlay::new(OverlayKind::Settings(keys, Vec::new()), "Settings", labels)
+    }
+
+    // Opens with the line for the current node highlighted
+    pub fn undo_tree(nodes: Vec<usize>, labels: Vec<String>, current: usize) -> Self {
+        let selected = nodes.iter().position(|&node| node == current).unwrap_or(0);
+        let mut overlay = Overlay::new(OverlayKind::History(nodes), "Undo tree", labels);
+        overlay.selected = selected;
+        overlay
+    }
+
+    // Show a new value for an entry in place, keeping the filter and
+    // selection as they are
+    pub fn relabel(&mut self, idx: usize, label: String) {
+        self.candidates[idx] = label;
+    }
+
+    pub fn push_char(&mut self, c: char) {
+        self.query.push(c);
+        self.refilter();
+    }
+
+    pub fn pop_char(&mut self) {
+        self.query.pop();
+        self.refilter();
+    }
+
+    fn refilter(&mut self) {
+        self.ranked = fuzzy::rank(&self.query, &self.candidates);
+        self.selected = 0;
+    }
+
+    pub fn move_selection(&mut self, delta: isize) {
+        if self.ranked.is_empty() {
+            return;
+        }
+        let len = self.ranked.len() as isize;
+        self.selected = (self.selected as isize + delta).rem_euclid(len) as usize;
+    }
+
+    // Index into the candidate list of the highlighted entry
+    pub fn selected_index(&self) -> Option<usize> {
+        self.ranked.get(self.selected).copied()
+    }
+
+    // Draw over the top rows of the frame, returning where the cursor goes
+    pub fn render(&self, rows: &mut [Vec<u8>], term_width: u16) -> io::Result<(u16, u16)> {
+        let Some((prompt, results)) = rows.split_first_mut() else {
+            return Ok((0, 0));
+        };
+        let width = (term_width as usize).min(MAX_WIDTH);
+        let fit = |s: &str| {
+            let mut line: String = s.chars().take(width).collect();
+            let pad = width - line.chars().count();
+            line.extend(std::iter::repeat_n(' ', pad));
+            line
+        };
+
+        prompt.clear();
+        queue!(
+            prompt,
+            SetBackgroundColor(Color::DarkGrey),
+            Print(fit(&format!("{}> {}", self.title, self.query))),
+            ResetColor
+        )?;
+
+        // Scroll the result list so the selection stays visible
+        let shown = results.len().min(MAX_RESULTS);
+        let first = self.selected.saturating_sub(shown.saturating_sub(1));
+        for (row, &idx) in self.ranked.iter().skip(first).take(shown).enumerate() {
+            let out = &mut results[row];
+            out.clear();
+            queue!(out, SetBackgroundColor(Color::DarkGrey))?;
+            if first + row == self.selected {
+                queue!(out, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(
+                out,
+                Print(fit(&format!("  {}", self.candidates[idx]))),
+                SetAttribute(Attribute::NoReverse),
+                ResetColor
+            )?;
+        }
+        if self.ranked.is_empty() && shown > 0 {
+            let out = &mut results[0];
+            out.clear();
+            queue!(
+                out,
+                SetBackgroundColor(Color::DarkGrey),
+                Print(fit("  (no matches)")),
+                ResetColor
+            )?;
+        }
+
+        let prompt_len = self.title.len() + 2 + self.query.chars().count();
+        Ok((prompt_len.min(width) as u16, 0))
+    }
+}
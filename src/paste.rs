@@ -0,0 +1,32 @@
+// Clean-up for pasted text before it goes into the buffer
+
+use crate::line_ending;
+
+// `newlines` turns `\r\n` and lone `\r` into `\n`; `trim` drops trailing
+// whitespace from every pasted line that ends in a line break (the last
+// line runs on into the existing text, so it's left alone)
+pub fn clean(text: &str, newlines: bool, trim: bool) -> String {
+    let text = if newlines {
+        line_ending::normalize(text, false)
+    } else {
+        text.to_string()
+    };
+    if !trim {
+        return text;
+    }
+
+    let mut out = String::with_capacity(text.len());
+    for line in text.split_inclusive('\n') {
+        let Some(body) = line.strip_suffix('\n') else {
+            out.push_str(line);
+            continue;
+        };
+        let (body, ending) = match body.strip_suffix('\r') {
+            Some(body) => (body, "\r\n"),
+            None => (body, "\n"),
+        };
+        out.push_str(body.trim_end());
+        out.push_str(ending);
+    }
+    out
+}
@@ -0,0 +1,99 @@
+// Last cursor position in each file, kept in `positions` next to the config
+// file so reopening a file picks up where it was left. Each line is
+// `line<TAB>col<TAB>path`, oldest first.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+
+// Enough for every file anyone is likely to come back to
+const MAX_FILES: usize = 500;
+
+type Entry = (PathBuf, (usize, usize));
+
+fn state_file() -> Option<PathBuf> {
+    Some(config::config_dir()?.join("positions"))
+}
+
+// Relative paths and symlinks would otherwise give one file several entries
+fn key(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn parse(text: &str) -> Vec<Entry> {
+    text.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\t');
+            let line = fields.next()?.parse().ok()?;
+            let col = fields.next()?.parse().ok()?;
+            Some((PathBuf::from(fields.next()?), (line, col)))
+        })
+        .collect()
+}
+
+fn format(entries: &[Entry]) -> String {
+    entries
+        .iter()
+        .map(|(path, (line, col))| format!("{}\t{}\t{}\n", line, col, path.display()))
+        .collect()
+}
+
+// Moves `path` to the end as the most recent, dropping the oldest entries
+// past the limit
+fn update(entries: &mut Vec<Entry>, path: PathBuf, position: (usize, usize)) {
+    entries.retain(|(p, _)| *p != path);
+    entries.push((path, position));
+    if entries.len() > MAX_FILES {
+        entries.drain(..entries.len() - MAX_FILES);
+    }
+}
+
+fn read() -> Vec<Entry> {
+    state_file()
+        .and_then(|file| fs::read_to_string(file).ok())
+        .map(|text| parse(&text))
+        .unwrap_or_default()
+}
+
+// Line and column (both from 0) the cursor was at when `path` was last left
+pub fn lookup(path: &Path) -> Option<(usize, usize)> {
+    let path = key(path);
+    read().into_iter().find(|(p, _)| *p == path).map(|(_, position)| position)
+}
+
+// Failing to write is not worth bothering anyone about; the position is
+// just forgotten
+pub fn remember(path: &Path, position: (usize, usize)) {
+    let Some(file) = state_file() else {
+        return;
+    };
+    let mut entries = read();
+    update(&mut entries, key(path), position);
+    if let Some(dir) = file.parent() {
+        let _ = fs::create_dir_all(dir);
+    }
+    let _ = fs::write(file, format(&entries));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_round_trip_and_most_recent_wins() {
+        let mut entries = parse("3\t4\t/a b/c.txt\nbad line\n0\t0\t/d.rs\n");
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0], (PathBuf::from("/a b/c.txt"), (3, 4)));
+
+        update(&mut entries, PathBuf::from("/a b/c.txt"), (7, 1));
+        assert_eq!(format(&entries), "0\t0\t/d.rs\n7\t1\t/a b/c.txt\n");
+        assert_eq!(parse(&format(&entries)), entries);
+
+        for i in 0..MAX_FILES {
+            update(&mut entries, PathBuf::from(format!("/f{}", i)), (i, 0));
+        }
+        assert_eq!(entries.len(), MAX_FILES);
+        assert_eq!(entries[0].0, PathBuf::from("/f0"));
+    }
+}
@@ -0,0 +1,61 @@
+// One-line prompt shown in place of the status line
+
+use std::path::PathBuf;
+
+// What to do once the user has agreed to lose unsaved changes
+pub enum AfterDiscard {
+    NewBuffer,
+    Open(PathBuf),
+    OpenAt(PathBuf, usize), // Line from 0
+    Revert,
+    Create(PathBuf),
+    RestoreSession(PathBuf),
+}
+
+pub enum PromptKind {
+    // Free text, submitted with Enter
+    SaveAs,
+    SetFilename,
+    InsertFile,
+    WriteSelection,
+    ReplayMacro,
+    Search,
+    AlignOn,
+    SaveSession,
+    RestoreSession,
+    // Answered with a single key
+    ConfirmDiscard(AfterDiscard),
+    ConfirmOverwrite(PathBuf),
+    ConfirmNormalizeIndent(String), // The name to save under
+    ConfirmLargeFile(AfterDiscard),
+    ConfirmCreate(PathBuf),
+    RecordMacro,
+}
+
+pub struct Prompt {
+    pub kind: PromptKind,
+    pub label: String,
+    pub input: String,
+}
+
+impl Prompt {
+    pub fn new(kind: PromptKind, label: impl Into<String>) -> Self {
+        Prompt {
+            kind,
+            label: label.into(),
+            input: String::new(),
+        }
+    }
+
+    pub fn is_single_key(&self) -> bool {
+        matches!(
+            self.kind,
+            PromptKind::ConfirmDiscard(_)
+                | PromptKind::ConfirmOverwrite(_)
+                | PromptKind::ConfirmNormalizeIndent(_)
+                | PromptKind::ConfirmLargeFile(_)
+                | PromptKind::ConfirmCreate(_)
+                | PromptKind::RecordMacro
+        )
+    }
+}
@@ -0,0 +1,1086 @@
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+// Rope data structure
+#[derive(Clone)]
+enum RopeNode {
+    Leaf(String),
+    Internal {
+        left: Rc<RopeNode>,
+        right: Rc<RopeNode>,
+        weight: usize,   // Length of left subtree
+        newlines: usize, // `\n`s in the left subtree, for finding lines
+        depth: usize,    // Of the whole subtree, so balance is known without a walk
+        leaves: usize,
+    },
+}
+
+// Largest first leaf that `prepend` will add to rather than start a new one
+const PREPEND_LEAF_SIZE: usize = 4 * 1024;
+
+// Longer text is split over several leaves, so an edit never copies more
+// than this much of the buffer
+const MAX_LEAF_SIZE: usize = 64 * 1024;
+
+#[derive(Clone)]
+pub struct Rope {
+    root: Rc<RopeNode>,
+}
+
+impl Rope {
+    pub fn new() -> Self {
+        Rope {
+            root: Rc::new(RopeNode::Leaf(String::new())),
+        }
+    }
+
+    pub fn from_string(s: &str) -> Self {
+        if s.len() <= MAX_LEAF_SIZE {
+            return Rope {
+                root: Rc::new(RopeNode::Leaf(s.to_string())),
+            };
+        }
+        let mut builder = RopeBuilder::new();
+        leaf_pieces(s).for_each(|piece| builder.push(piece.to_string()));
+        builder.finish()
+    }
+
+    // Weights already hold the length of every left subtree, so only the
+    // right edge of the tree is walked. A loop rather than recursion, like
+    // the other traversals a badly unbalanced tree could make very deep.
+    pub fn len(&self) -> usize {
+        Rope::node_len(&self.root)
+    }
+
+    fn node_len(mut node: &Rc<RopeNode>) -> usize {
+        let mut len = 0;
+        loop {
+            match node.as_ref() {
+                RopeNode::Leaf(s) => return len + s.len(),
+                RopeNode::Internal { right, weight, .. } => {
+                    len += weight;
+                    node = right;
+                }
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Reads only the leaves covering the last `suffix.len()` bytes
+    pub fn ends_with(&self, suffix: &str) -> bool {
+        let mut rest = suffix.as_bytes();
+        Rope::suffix_matches(&self.root, &mut rest) && rest.is_empty()
+    }
+
+    // Match the end of `node` against the end of `rest`, trimming off what
+    // matched
+    fn suffix_matches(node: &Rc<RopeNode>, rest: &mut &[u8]) -> bool {
+        if rest.is_empty() {
+            return true;
+        }
+        match node.as_ref() {
+            RopeNode::Leaf(s) => {
+                let n = rest.len().min(s.len());
+                if s.as_bytes()[s.len() - n..] != rest[rest.len() - n..] {
+                    return false;
+                }
+                *rest = &rest[..rest.len() - n];
+                true
+            }
+            RopeNode::Internal { left, right, .. } => {
+                Rope::suffix_matches(right, rest) && Rope::suffix_matches(left, rest)
+            }
+        }
+    }
+
+    // The very same tree, so the same text, found without reading it
+    pub fn ptr_eq(a: &Rope, b: &Rope) -> bool {
+        Rc::ptr_eq(&a.root, &b.root)
+    }
+
+    pub fn concat(left: Rope, right: Rope) -> Rope {
+        Rope {
+            root: Rope::internal(left.root.clone(), right.root.clone()),
+        }
+    }
+
+    fn internal(left: Rc<RopeNode>, right: Rc<RopeNode>) -> Rc<RopeNode> {
+        Rc::new(RopeNode::Internal {
+            weight: Rope::node_len(&left),
+            newlines: Rope::node_newlines(&left),
+            depth: Rope::node_depth(&left).max(Rope::node_depth(&right)) + 1,
+            leaves: Rope::node_leaves(&left) + Rope::node_leaves(&right),
+            left,
+            right,
+        })
+    }
+
+    fn node_depth(node: &Rc<RopeNode>) -> usize {
+        match node.as_ref() {
+            RopeNode::Leaf(_) => 0,
+            RopeNode::Internal { depth, .. } => *depth,
+        }
+    }
+
+    fn node_leaves(node: &Rc<RopeNode>) -> usize {
+        match node.as_ref() {
+            RopeNode::Leaf(_) => 1,
+            RopeNode::Internal { leaves, .. } => *leaves,
+        }
+    }
+
+    // Like `node_len`, down the right edge, counting only the last leaf
+    fn node_newlines(mut node: &Rc<RopeNode>) -> usize {
+        let mut count = 0;
+        loop {
+            match node.as_ref() {
+                RopeNode::Leaf(s) => return count + newlines_in(s),
+                RopeNode::Internal { right, newlines, .. } => {
+                    count += newlines;
+                    node = right;
+                }
+            }
+        }
+    }
+
+    pub fn split(&self, index: usize) -> (Rope, Rope) {
+        let index = index.min(self.len());
+        let (left, right) = self.split_node(&self.root, index);
+        (Rope { root: left }, Rope { root: right })
+    }
+
+    fn split_node(&self, node: &Rc<RopeNode>, index: usize) -> (Rc<RopeNode>, Rc<RopeNode>) {
+        match node.as_ref() {
+            // At either end the leaf is shared, not copied
+            RopeNode::Leaf(_) if index == 0 => (Rc::new(RopeNode::Leaf(String::new())), node.clone()),
+            RopeNode::Leaf(s) if index >= s.len() => (node.clone(), Rc::new(RopeNode::Leaf(String::new()))),
+            RopeNode::Leaf(s) => {
+                let (left, right) = s.split_at(index);
+                (
+                    Rc::new(RopeNode::Leaf(left.to_string())),
+                    Rc::new(RopeNode::Leaf(right.to_string())),
+                )
+            }
+            RopeNode::Internal { left, right, weight, .. } => {
+                if index <= *weight {
+                    let (ll, lr) = self.split_node(left, index);
+                    (ll, Rope::internal(lr, right.clone()))
+                } else {
+                    let (rl, rr) = self.split_node(right, index - weight);
+                    (Rope::internal(left.clone(), rl), rr)
+                }
+            }
+        }
+    }
+    
+
+    // The text between two byte indexes as a rope of its own, sharing every
+    // leaf it covers whole
+    pub fn slice(&self, start: usize, end: usize) -> Rope {
+        self.split(end).0.split(start).1
+    }
+
+    // Like `insert`, but sharing `other`'s leaves
+    pub fn insert_rope(&self, index: usize, other: &Rope) -> Rope {
+        if other.is_empty() {
+            return self.clone();
+        }
+        let (left, right) = self.split(index);
+        Rope::concat(Rope::concat(left, other.clone()), right).checked()
+    }
+
+    pub fn insert(&self, index: usize, text: &str) -> Rope {
+        if index == 0 {
+            return self.prepend(text);
+        }
+        let (left, right) = self.split(index);
+        let middle = Rope::from_string(text);
+        Rope::concat(Rope::concat(left, middle), right).checked()
+    }
+
+    // Small additions go into the first leaf, so prepending over and over
+    // doesn't grow a long chain of nodes down the left of the tree. When a
+    // new leaf is needed and the tree has got too deep, it's rebuilt
+    // balanced.
+    pub fn prepend(&self, text: &str) -> Rope {
+        if text.is_empty() {
+            return self.clone();
+        }
+        if let Some(root) = Rope::prepend_node(&self.root, text) {
+            return Rope { root }.checked();
+        }
+        let rope = Rope::concat(Rope::from_string(text), self.clone());
+        let (depth, leaves) = rope.depth_and_leaves();
+        // A balanced tree is about log2(leaves) deep
+        if depth > 2 * (usize::BITS - leaves.leading_zeros()) as usize + 2 {
+            rope.rebalanced().checked()
+        } else {
+            rope.checked()
+        }
+    }
+
+    // `node` with `text` added to the front of its first leaf, copying only
+    // the nodes down the left edge. None if that leaf is already big.
+    fn prepend_node(node: &Rc<RopeNode>, text: &str) -> Option<Rc<RopeNode>> {
+        match node.as_ref() {
+            RopeNode::Leaf(s) if s.len() + text.len() <= PREPEND_LEAF_SIZE => {
+                Some(Rc::new(RopeNode::Leaf(format!("{}{}", text, s))))
+            }
+            RopeNode::Leaf(_) => None,
+            RopeNode::Internal { left, right, weight, newlines, depth, leaves } => Some(Rc::new(RopeNode::Internal {
+                left: Rope::prepend_node(left, text)?,
+                right: right.clone(),
+                weight: weight + text.len(),
+                newlines: newlines + newlines_in(text),
+                depth: *depth,
+                leaves: *leaves,
+            })),
+        }
+    }
+
+    // Depth and number of leaves, for the debug HUD
+    pub fn depth_and_leaves(&self) -> (usize, usize) {
+        (Rope::node_depth(&self.root), Rope::node_leaves(&self.root))
+    }
+
+    // The same text in a balanced tree of the same leaves. Each leaf's
+    // length and newline count follow from its parents', so none is scanned.
+    fn rebalanced(&self) -> Rope {
+        let mut builder = RopeBuilder::new();
+        let mut stack = vec![(&self.root, self.len(), Rope::node_newlines(&self.root))];
+        while let Some((node, len, newlines)) = stack.pop() {
+            match node.as_ref() {
+                RopeNode::Leaf(_) if len == 0 => {}
+                RopeNode::Leaf(_) => builder.push_subtree(Subtree {
+                    root: node.clone(),
+                    leaves: 1,
+                    len,
+                    newlines,
+                }),
+                RopeNode::Internal { left, right, weight, newlines: left_newlines, .. } => {
+                    stack.push((right, len - weight, newlines - left_newlines));
+                    stack.push((left, *weight, *left_newlines));
+                }
+            }
+        }
+        builder.finish()
+    }
+
+    #[cfg(test)]
+    pub fn delete(&self, start: usize, len: usize) -> Rope {
+        self.delete_range(start, start.saturating_add(len))
+    }
+
+    // Removes the bytes from `start` up to (not including) `end`. The ends
+    // may come in either order and are clamped to the text.
+    pub fn delete_range(&self, start: usize, end: usize) -> Rope {
+        self.replace_range(start, end, "")
+    }
+
+    // Same result as delete then insert, with one split of the tree each side
+    pub fn replace_range(&self, start: usize, end: usize, text: &str) -> Rope {
+        let (start, end) = (start.min(end), start.max(end));
+        let (left, rest) = self.split(start);
+        let (_, right) = rest.split(end - start.min(self.len()));
+        if text.is_empty() {
+            return Rope::concat(left, right).checked();
+        }
+        Rope::concat(Rope::concat(left, Rope::from_string(text)), right).checked()
+    }
+
+    // Text between two byte indexes, copying only the leaves it covers. An
+    // index inside a character moves forward to the end of that character.
+    pub fn substring(&self, start: usize, end: usize) -> String {
+        let mut result = String::new();
+        // Nodes still to visit, with the range wanted from each, right
+        // halves pushed first so the text comes out in order
+        let mut stack = vec![(self.root.as_ref(), start, end)];
+        while let Some((node, start, end)) = stack.pop() {
+            if start >= end {
+                continue;
+            }
+            match node {
+                RopeNode::Leaf(s) => {
+                    let boundary = |i: usize| (i.min(s.len())..=s.len()).find(|&i| s.is_char_boundary(i)).unwrap();
+                    let (from, to) = (boundary(start), boundary(end));
+                    if from < to {
+                        result.push_str(&s[from..to]);
+                    }
+                }
+                RopeNode::Internal { left, right, weight, .. } => {
+                    if end > *weight {
+                        stack.push((right.as_ref(), start.saturating_sub(*weight), end - weight));
+                    }
+                    if start < *weight {
+                        stack.push((left.as_ref(), start, end.min(*weight)));
+                    }
+                }
+            }
+        }
+        result
+    }
+
+    #[cfg(test)]
+    pub fn char_at(&self, index: usize) -> Option<char> {
+        let (mut node, mut index) = (&self.root, index);
+        loop {
+            match node.as_ref() {
+                RopeNode::Leaf(s) => return s.chars().nth(index),
+                RopeNode::Internal { left, right, weight, .. } => {
+                    if index < *weight {
+                        node = left;
+                    } else {
+                        node = right;
+                        index -= weight;
+                    }
+                }
+            }
+        }
+    }
+
+    // `index`, or the start of the character it's inside, clamped to the
+    // text. Only the leaf holding it is looked at.
+    pub fn floor_char_boundary(&self, index: usize) -> usize {
+        let (mut node, mut index, mut offset) = (&self.root, index.min(self.len()), 0);
+        loop {
+            match node.as_ref() {
+                RopeNode::Leaf(s) => {
+                    let i = (0..=index).rev().find(|&i| s.is_char_boundary(i)).unwrap();
+                    return offset + i;
+                }
+                RopeNode::Internal { left, right, weight, .. } => {
+                    if index < *weight {
+                        node = left;
+                    } else {
+                        index -= weight;
+                        offset += weight;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    // Leaf strings in document order, without copying them, e.g. to write
+    // the text out without building one big String first
+    pub fn chunks(&self) -> impl Iterator<Item = &str> + '_ {
+        Leaves {
+            stack: vec![self.root.as_ref()],
+            first: None,
+            backward: false,
+        }
+    }
+
+    // The leaves from `index` on, the first one cut to start there. Only
+    // the way down to `index` is walked, so reading a little past the
+    // cursor doesn't cost a pass over everything before it.
+    pub fn chunks_from(&self, index: usize) -> impl Iterator<Item = &str> + '_ {
+        let (mut node, mut index, mut stack) = (self.root.as_ref(), index.min(self.len()), Vec::new());
+        loop {
+            match node {
+                RopeNode::Leaf(s) => {
+                    let start = (index..=s.len()).find(|&i| s.is_char_boundary(i)).unwrap();
+                    return Leaves { stack, first: Some(&s[start..]), backward: false };
+                }
+                RopeNode::Internal { left, right, weight, .. } => {
+                    if index < *weight {
+                        stack.push(right.as_ref());
+                        node = left;
+                    } else {
+                        index -= weight;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    // The leaves before `index`, last first, the first one cut to end there
+    pub fn chunks_before(&self, index: usize) -> impl Iterator<Item = &str> + '_ {
+        let (mut node, mut index, mut stack) = (self.root.as_ref(), index.min(self.len()), Vec::new());
+        loop {
+            match node {
+                RopeNode::Leaf(s) => {
+                    let end = (0..=index).rev().find(|&i| s.is_char_boundary(i)).unwrap();
+                    return Leaves { stack, first: Some(&s[..end]), backward: true };
+                }
+                RopeNode::Internal { left, right, weight, .. } => {
+                    if index <= *weight {
+                        node = left;
+                    } else {
+                        stack.push(left.as_ref());
+                        index -= weight;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.chunks().flat_map(str::chars)
+    }
+
+    // Non-overlapping occurrences of `needle`. An empty needle has none.
+    pub fn count_matches(&self, needle: &str) -> usize {
+        let mut count = 0;
+        self.find_matches(needle, |_| count += 1);
+        count
+    }
+
+    // Where the occurrences `count_matches` counts start, in order
+    pub fn match_starts(&self, needle: &str) -> Vec<usize> {
+        let mut starts = Vec::new();
+        self.find_matches(needle, |start| starts.push(start));
+        starts
+    }
+
+    // Found leaf by leaf with the standard library's linear-time search.
+    // Only the tail of each leaf that could start a match is carried over
+    // to the next, so the text is never copied whole.
+    fn find_matches(&self, needle: &str, mut found: impl FnMut(usize)) {
+        if needle.is_empty() {
+            return;
+        }
+        let mut text = String::new();
+        let mut offset = 0; // Of `text` in the rope
+        for chunk in self.chunks() {
+            text.push_str(chunk);
+            let mut end = 0;
+            for (i, _) in text.match_indices(needle) {
+                found(offset + i);
+                end = i + needle.len();
+            }
+            let mut keep = end.max(text.len().saturating_sub(needle.len() - 1));
+            while !text.is_char_boundary(keep) {
+                keep -= 1;
+            }
+            text.drain(..keep);
+            offset += keep;
+        }
+    }
+
+    // Byte index just after the nth `\n` (counting from 1), which is where
+    // line n (counting from 0) starts; 0 for n = 0, and None when the text
+    // has fewer newlines. Goes down the tree by the cached newline counts,
+    // so only one leaf is searched.
+    pub fn nth_newline(&self, n: usize) -> Option<usize> {
+        if n == 0 {
+            return Some(0);
+        }
+        let (mut node, mut n, mut offset) = (&self.root, n, 0);
+        loop {
+            match node.as_ref() {
+                RopeNode::Leaf(s) => {
+                    let (i, _) = s.match_indices('\n').nth(n - 1)?;
+                    return Some(offset + i + 1);
+                }
+                RopeNode::Internal { left, right, weight, newlines, .. } => {
+                    if n <= *newlines {
+                        node = left;
+                    } else {
+                        n -= newlines;
+                        offset += weight;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    // Number of `\n`s before a byte index
+    fn newlines_before(&self, index: usize) -> usize {
+        let (mut node, mut index, mut count) = (&self.root, index, 0);
+        loop {
+            match node.as_ref() {
+                RopeNode::Leaf(s) => return count + newlines_in(&s.as_bytes()[..index.min(s.len())]),
+                RopeNode::Internal { left, right, weight, newlines, .. } => {
+                    if index <= *weight {
+                        node = left;
+                    } else {
+                        count += newlines;
+                        index -= weight;
+                        node = right;
+                    }
+                }
+            }
+        }
+    }
+
+    // Line and column of a byte index, both from 0. Columns count
+    // characters, the way the cursor moves.
+    pub fn index_to_line_col(&self, index: usize) -> (usize, usize) {
+        let index = index.min(self.len());
+        let line = self.newlines_before(index);
+        let start = self.nth_newline(line).unwrap_or(0);
+        (line, self.substring(start, index).chars().count())
+    }
+
+    // The line a byte index is on, from 0, without counting the column
+    pub fn line_at(&self, index: usize) -> usize {
+        self.newlines_before(index.min(self.len()))
+    }
+
+    // Bytes of a line, from 0, not counting its `\n`. A line past the end
+    // is an empty range at the end of the text.
+    pub fn line_range(&self, line: usize) -> Range<usize> {
+        let Some(start) = self.nth_newline(line) else {
+            return self.len()..self.len();
+        };
+        start..self.nth_newline(line + 1).map_or(self.len(), |next| next - 1)
+    }
+
+    // Byte index of a line and column. A column past the end of its line
+    // gives the end of that line; a line past the end gives the end of the
+    // text.
+    pub fn line_col_to_index(&self, line: usize, col: usize) -> usize {
+        let range = self.line_range(line);
+        let text = self.substring(range.start, range.end);
+        range.start + text.char_indices().nth(col).map_or(text.len(), |(i, _)| i)
+    }
+
+    pub fn insert_str_at_line_col(&self, line: usize, col: usize, text: &str) -> Rope {
+        self.insert(self.line_col_to_index(line, col), text)
+    }
+
+    // Panics if the tree is inconsistent: every internal node's weight must
+    // be the length of its left subtree, which indexing relies on, and no
+    // leaf may be over MAX_LEAF_SIZE. Subtrees shared with another rope were
+    // checked when that one was made, so after an edit only the nodes it
+    // built are walked. Counting a shared subtree's newlines would mean
+    // reading its last leaf, so next to one those go unchecked. Left out of
+    // release builds.
+    #[cfg(any(test, debug_assertions))]
+    pub fn validate(&self) {
+        Rope::checked_len(&self.root);
+    }
+
+    // The result of an edit, validated in debug builds
+    fn checked(self) -> Rope {
+        #[cfg(debug_assertions)]
+        self.validate();
+        self
+    }
+
+    // Length and newline count, the count None when it wasn't worked out
+    #[cfg(any(test, debug_assertions))]
+    fn checked_len(node: &Rc<RopeNode>) -> (usize, Option<usize>) {
+        if Rc::strong_count(node) > 1 {
+            return (Rope::node_len(node), None);
+        }
+        match node.as_ref() {
+            RopeNode::Leaf(s) => {
+                assert!(s.len() <= MAX_LEAF_SIZE, "rope leaf is over the size limit");
+                (s.len(), Some(newlines_in(s)))
+            }
+            RopeNode::Internal { left, right, weight, newlines, depth, leaves } => {
+                let shape = (
+                    Rope::node_depth(left).max(Rope::node_depth(right)) + 1,
+                    Rope::node_leaves(left) + Rope::node_leaves(right),
+                );
+                assert_eq!((*depth, *leaves), shape, "rope depth or leaf count doesn't match its subtrees");
+                let (left_len, left_newlines) = Rope::checked_len(left);
+                assert_eq!(*weight, left_len, "rope weight doesn't match its left subtree");
+                if let Some(left_newlines) = left_newlines {
+                    assert_eq!(*newlines, left_newlines, "rope newline count doesn't match its left subtree");
+                }
+                let (right_len, right_newlines) = Rope::checked_len(right);
+                (left_len + right_len, right_newlines.map(|right| newlines + right))
+            }
+        }
+    }
+
+    // Same tree shape and same leaves. Mostly useful when debugging
+    // split/concat; `==` compares content only.
+    #[cfg(test)]
+    pub fn structurally_eq(&self, other: &Rope) -> bool {
+        Rope::nodes_eq(&self.root, &other.root)
+    }
+
+    #[cfg(test)]
+    fn nodes_eq(a: &Rc<RopeNode>, b: &Rc<RopeNode>) -> bool {
+        if Rc::ptr_eq(a, b) {
+            return true;
+        }
+        match (a.as_ref(), b.as_ref()) {
+            (RopeNode::Leaf(x), RopeNode::Leaf(y)) => x == y,
+            (
+                RopeNode::Internal { left: l1, right: r1, weight: w1, .. },
+                RopeNode::Internal { left: l2, right: r2, weight: w2, .. },
+            ) => w1 == w2 && Rope::nodes_eq(l1, l2) && Rope::nodes_eq(r1, r2),
+            _ => false,
+        }
+    }
+}
+
+// Dropping the last reference to a tree would otherwise free it
+// recursively, one stack frame per level
+impl Drop for Rope {
+    fn drop(&mut self) {
+        if Rc::strong_count(&self.root) > 1 || matches!(self.root.as_ref(), RopeNode::Leaf(_)) {
+            return;
+        }
+        let mut stack = vec![std::mem::replace(&mut self.root, Rc::new(RopeNode::Leaf(String::new())))];
+        while let Some(node) = stack.pop() {
+            // Shared nodes just lose a reference; sole ones hand their
+            // children over before going
+            if let Ok(RopeNode::Internal { left, right, .. }) = Rc::try_unwrap(node) {
+                stack.push(left);
+                stack.push(right);
+            }
+        }
+    }
+}
+
+// Ropes are equal when they hold the same text, however the trees are shaped
+impl PartialEq for Rope {
+    fn eq(&self, other: &Rope) -> bool {
+        Rc::ptr_eq(&self.root, &other.root)
+            || (self.len() == other.len() && self.chars().eq(other.chars()))
+    }
+}
+
+impl Eq for Rope {}
+
+// The whole text, written out leaf by leaf
+impl fmt::Display for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.chunks().try_for_each(|chunk| f.write_str(chunk))
+    }
+}
+
+// Builds a balanced rope from leaves pushed in order, without holding the
+// whole text in one string. Works like a binary counter: equal-sized
+// subtrees are merged as soon as there are two of them.
+pub struct RopeBuilder {
+    stack: Vec<Subtree>,
+}
+
+// A finished part of a RopeBuilder's tree, with the totals its parent node
+// will need, so merging doesn't have to walk it again
+struct Subtree {
+    root: Rc<RopeNode>,
+    leaves: usize,
+    len: usize,
+    newlines: usize,
+}
+
+impl Subtree {
+    fn merge(left: Subtree, right: Subtree) -> Subtree {
+        Subtree {
+            root: Rc::new(RopeNode::Internal {
+                depth: Rope::node_depth(&left.root).max(Rope::node_depth(&right.root)) + 1,
+                left: left.root,
+                right: right.root,
+                weight: left.len,
+                newlines: left.newlines,
+                leaves: left.leaves + right.leaves,
+            }),
+            leaves: left.leaves + right.leaves,
+            len: left.len + right.len,
+            newlines: left.newlines + right.newlines,
+        }
+    }
+}
+
+impl RopeBuilder {
+    pub fn new() -> Self {
+        RopeBuilder { stack: Vec::new() }
+    }
+
+    // A leaf over MAX_LEAF_SIZE goes in as several
+    pub fn push(&mut self, leaf: String) {
+        if leaf.len() > MAX_LEAF_SIZE {
+            leaf_pieces(&leaf).for_each(|piece| self.push(piece.to_string()));
+            return;
+        }
+        self.push_subtree(Subtree {
+            leaves: 1,
+            len: leaf.len(),
+            newlines: newlines_in(&leaf),
+            root: Rc::new(RopeNode::Leaf(leaf)),
+        });
+    }
+
+    fn push_subtree(&mut self, mut node: Subtree) {
+        while let Some(top) = self.stack.pop() {
+            if top.leaves != node.leaves {
+                self.stack.push(top);
+                break;
+            }
+            node = Subtree::merge(top, node);
+        }
+        self.stack.push(node);
+    }
+
+    pub fn finish(self) -> Rope {
+        self.stack
+            .into_iter()
+            .reduce(Subtree::merge)
+            .map_or_else(Rope::new, |tree| Rope { root: tree.root })
+            .checked()
+    }
+}
+
+// `text` cut at character boundaries into pieces of at most MAX_LEAF_SIZE
+fn leaf_pieces(mut text: &str) -> impl Iterator<Item = &str> {
+    std::iter::from_fn(move || {
+        if text.is_empty() {
+            return None;
+        }
+        let mut end = text.len().min(MAX_LEAF_SIZE);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (piece, rest) = text.split_at(end);
+        text = rest;
+        Some(piece)
+    })
+}
+
+fn newlines_in(text: impl AsRef<[u8]>) -> usize {
+    text.as_ref().iter().filter(|&&b| b == b'\n').count()
+}
+
+struct Leaves<'a> {
+    stack: Vec<&'a RopeNode>,
+    first: Option<&'a str>, // Part of a leaf to hand out before the stack
+    backward: bool,         // Last leaf first
+}
+
+impl<'a> Iterator for Leaves<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        if let Some(first) = self.first.take() {
+            return Some(first);
+        }
+        while let Some(node) = self.stack.pop() {
+            match node {
+                RopeNode::Leaf(s) => return Some(s),
+                RopeNode::Internal { left, right, .. } if self.backward => {
+                    self.stack.push(left);
+                    self.stack.push(right);
+                }
+                RopeNode::Internal { left, right, .. } => {
+                    self.stack.push(right);
+                    self.stack.push(left);
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn equal_content_with_different_trees() {
+        let flat = Rope::from_string("hello world");
+        let built = Rope::concat(
+            Rope::concat(Rope::from_string("he"), Rope::from_string("llo ")),
+            Rope::from_string("world"),
+        );
+        assert_eq!(built.to_string(), flat.to_string());
+        assert!(built == flat);
+        assert!(!built.structurally_eq(&flat));
+
+        let edited = flat.insert(5, ",").delete(5, 1);
+        edited.validate();
+        assert!(edited == flat);
+        assert!(!edited.structurally_eq(&flat));
+    }
+
+    #[test]
+    fn unequal_content() {
+        assert!(Rope::from_string("abc") != Rope::from_string("abd"));
+        assert!(Rope::from_string("abc") != Rope::from_string("abcd"));
+        assert!(Rope::new() == Rope::from_string(""));
+        assert!(Rope::new() != Rope::from_string(" "));
+    }
+
+    #[test]
+    fn structural_equality() {
+        let a = Rope::from_string("hello").insert(2, "XY");
+        let b = Rope::from_string("hello").insert(2, "XY");
+        assert!(a.structurally_eq(&b));
+        assert!(a.structurally_eq(&a.clone()));
+        assert!(!a.structurally_eq(&Rope::from_string("heXYllo")));
+    }
+
+    #[test]
+    fn chars_streams_every_leaf() {
+        let rope = Rope::from_string("abc").insert(1, "123").insert(0, "<").insert(7, ">");
+        rope.validate();
+        assert_eq!(rope.chars().collect::<String>(), rope.to_string());
+        assert_eq!(rope.to_string(), "<a123bc>");
+    }
+
+    #[test]
+    fn line_col_round_trips() {
+        let rope = Rope::concat(Rope::from_string("ab\n\nné"), Rope::from_string("€\nlast"));
+        let text = rope.to_string();
+        for (index, _) in text.char_indices().chain([(text.len(), ' ')]) {
+            let (line, col) = rope.index_to_line_col(index);
+            assert_eq!(rope.line_col_to_index(line, col), index);
+        }
+
+        assert_eq!(rope.index_to_line_col(3), (1, 0)); // Empty line
+        assert_eq!(rope.index_to_line_col(2), (0, 2)); // End of line
+        assert_eq!(rope.line_col_to_index(2, 3), 10); // After the `€`
+        assert_eq!(rope.line_col_to_index(0, 99), 2);
+        assert_eq!(rope.line_col_to_index(99, 0), text.len());
+        assert_eq!(rope.insert_str_at_line_col(1, 0, "x").to_string(), "ab\nx\nné€\nlast");
+        assert_eq!(rope.insert_str_at_line_col(2, 3, "!").to_string(), "ab\n\nné€!\nlast");
+    }
+
+    #[test]
+    fn nth_newline_finds_line_starts_across_leaves() {
+        let mut builder = RopeBuilder::new();
+        for leaf in ["\nab", "\n\n", "c", "d\n", "\n"] {
+            builder.push(leaf.to_string());
+        }
+        let rope = Rope::concat(Rope::from_string("\n"), builder.finish()).insert(5, "x\ny");
+        rope.validate();
+        let text = rope.to_string();
+        assert_eq!(text, "\n\nab\nx\ny\ncd\n\n");
+        let expected: Vec<usize> = text.match_indices('\n').map(|(i, _)| i + 1).collect();
+        for (n, &start) in expected.iter().enumerate() {
+            assert_eq!(rope.nth_newline(n + 1), Some(start), "newline {}", n + 1);
+        }
+        assert_eq!(rope.nth_newline(0), Some(0));
+        assert_eq!(rope.nth_newline(expected.len() + 1), None);
+        assert_eq!(Rope::new().nth_newline(1), None);
+        assert_eq!(rope.index_to_line_col(text.len()), (expected.len(), 0));
+    }
+
+    #[test]
+    fn is_empty_and_ends_with_across_leaves() {
+        let empty = Rope::concat(Rope::new(), Rope::new());
+        assert!(empty.is_empty());
+        assert!(empty.ends_with(""));
+        assert!(!empty.ends_with("\n"));
+
+        let rope = Rope::concat(
+            Rope::concat(Rope::from_string("hello w"), Rope::new()),
+            Rope::concat(Rope::from_string("or"), Rope::from_string("ld\n")),
+        );
+        assert!(!rope.is_empty());
+        assert!(Rope::ptr_eq(&rope.insert_rope(3, &empty), &rope));
+        assert!(rope.ends_with("\n"));
+        assert!(rope.ends_with("world\n"));
+        assert!(rope.ends_with("hello world\n"));
+        assert!(!rope.ends_with("help world\n"));
+        assert!(!rope.ends_with("say hello world\n"));
+    }
+
+    #[test]
+    fn chunks_are_the_leaves_in_order() {
+        let rope = Rope::concat(
+            Rope::concat(Rope::from_string("hello"), Rope::new()),
+            Rope::concat(Rope::from_string(" wör"), Rope::from_string("ld\n")),
+        );
+        let chunks: Vec<&str> = rope.chunks().filter(|chunk| !chunk.is_empty()).collect();
+        assert_eq!(chunks, ["hello", " wör", "ld\n"]);
+        assert_eq!(rope.chunks().collect::<String>(), rope.to_string());
+
+        // Either way from any point, without splitting the `ö`
+        let text = rope.to_string();
+        for i in (0..=text.len()).filter(|&i| text.is_char_boundary(i)) {
+            assert_eq!(rope.chunks_from(i).collect::<String>(), text[i..], "{}", i);
+            let mut before: Vec<&str> = rope.chunks_before(i).collect();
+            before.reverse();
+            assert_eq!(before.concat(), text[..i], "{}", i);
+        }
+        // Inside it, forward starts after it and backward ends before it
+        assert_eq!(rope.chunks_from(8).collect::<String>(), "rld\n");
+        assert_eq!(rope.chunks_before(8).collect::<Vec<_>>(), [" w", "", "hello"]);
+    }
+
+    #[test]
+    fn count_matches_agrees_with_str_matches() {
+        // Leaves small enough that matches straddle them
+        let mut builder = RopeBuilder::new();
+        let text = "aaa·abab··aXa·\nababa".repeat(20);
+        let chars: Vec<char> = text.chars().collect();
+        for piece in chars.chunks(2) {
+            builder.push(piece.iter().collect());
+        }
+        let rope = Rope::from_string(&text);
+        let split = builder.finish();
+        assert_eq!(split.depth_and_leaves().1, chars.len() / 2);
+        for needle in ["a", "aa", "ab", "aba", "·", "··", "a·\na", "ababa", "x", "aaa·abab··aXa·\nababaaaa"] {
+            let expected = rope.to_string().matches(needle).count();
+            assert_eq!(rope.count_matches(needle), expected, "{:?}", needle);
+            assert_eq!(split.count_matches(needle), expected, "{:?}", needle);
+            let starts: Vec<usize> = text.match_indices(needle).map(|(i, _)| i).collect();
+            assert_eq!(split.match_starts(needle), starts, "{:?}", needle);
+        }
+        assert_eq!(rope.count_matches(""), 0);
+        assert_eq!(Rope::new().count_matches("a"), 0);
+        assert!(rope.match_starts("").is_empty());
+    }
+
+    #[test]
+    fn slices_share_the_leaves_they_cover() {
+        let big = "x".repeat(10_000);
+        let rope = Rope::from_string("abc").insert(3, &big).insert(3 + big.len(), "def");
+        let slice = rope.slice(3, 3 + big.len());
+        assert_eq!(slice.to_string(), big);
+        let leaf = |rope: &Rope| rope.chunks().find(|chunk| chunk.len() == big.len()).map(str::as_ptr);
+        assert_eq!(leaf(&slice), leaf(&rope));
+        assert_eq!(rope.slice(2, 5).to_string(), "cxx");
+
+        let joined = Rope::from_string("ab").insert_rope(1, &slice);
+        assert_eq!(joined.to_string(), format!("a{}b", big));
+        assert_eq!(leaf(&joined), leaf(&rope));
+        joined.validate();
+    }
+
+    #[test]
+    fn repeated_prepends_keep_the_tree_shallow() {
+        let mut rope = Rope::from_string("end\n");
+        let mut expected = String::from("end\n");
+        for i in 0..10_000 {
+            let line = format!("line {}\n", i);
+            rope = rope.prepend(&line);
+            expected.insert_str(0, &line);
+        }
+        assert_eq!(rope.to_string(), expected);
+        rope.validate();
+        let (depth, leaves) = rope.depth_and_leaves();
+        assert!(depth <= 2 * (usize::BITS - leaves.leading_zeros()) as usize + 2, "depth {} for {} leaves", depth, leaves);
+
+        // Text too big to share a leaf still stays logarithmic
+        let block = "x".repeat(PREPEND_LEAF_SIZE);
+        for _ in 0..1000 {
+            rope = rope.prepend(&block);
+        }
+        assert_eq!(rope.len(), expected.len() + 1000 * block.len());
+        assert!(rope.depth_and_leaves().0 < 30);
+        assert_eq!(rope.insert(0, "a").substring(0, 2), "ax");
+    }
+
+    #[test]
+    fn very_deep_trees_dont_overflow_the_stack() {
+        // Chains of 100k single-leaf concats, leaning each way, as a long
+        // run of edits with no rebalancing could leave
+        let mut left_deep = Rope::new();
+        let mut right_deep = Rope::new();
+        for _ in 0..100_000 {
+            left_deep = Rope::concat(left_deep, Rope::from_string("ab"));
+            right_deep = Rope::concat(Rope::from_string("ab"), right_deep);
+        }
+        for rope in [&left_deep, &right_deep] {
+            assert_eq!(rope.len(), 200_000);
+            assert_eq!(rope.to_string(), "ab".repeat(100_000));
+            assert_eq!(rope.substring(99_999, 100_003), "baba");
+            assert_eq!(rope.char_at(150_001), Some('b'));
+            assert!(!rope.is_empty());
+        }
+        // Both are freed without recursing too
+        drop(left_deep);
+        drop(right_deep);
+    }
+
+    #[test]
+    fn substring_spans_leaves_and_snaps_to_characters() {
+        let mut builder = RopeBuilder::new();
+        for leaf in ["ab", "c€", "de"] {
+            builder.push(leaf.to_string());
+        }
+        let rope = builder.finish();
+        assert_eq!(rope.substring(1, 6), "bc€");
+        assert_eq!(rope.substring(0, rope.len()), "abc€de");
+        assert_eq!(rope.substring(4, 8), "de");
+        assert_eq!(rope.substring(3, 4), "€");
+        assert_eq!(rope.substring(5, 5), "");
+        assert_eq!(rope.substring(6, 100), "de");
+        let floors: Vec<usize> = [2, 4, 5, 6, 100].iter().map(|&i| rope.floor_char_boundary(i)).collect();
+        assert_eq!(floors, [2, 3, 3, 6, 8]);
+    }
+
+    #[test]
+    fn delete_range_agrees_with_delete() {
+        let rope = Rope::concat(Rope::from_string("hello "), Rope::from_string("world"));
+        for (start, len) in [(0, 0), (0, 5), (3, 5), (6, 5), (10, 7), (11, 3), (20, 1)] {
+            assert!(rope.delete_range(start, start + len) == rope.delete(start, len));
+        }
+        assert_eq!(rope.delete_range(8, 3).to_string(), "helrld");
+        assert_eq!(rope.delete_range(4, 100).to_string(), "hell");
+        assert_eq!(rope.delete_range(100, 200).to_string(), "hello world");
+        assert_eq!(rope.delete(2, usize::MAX).to_string(), "he");
+    }
+
+    #[test]
+    fn edits_keep_weights_consistent() {
+        let mut rope = Rope::from_string("the quick brown fox");
+        let mut text = rope.to_string();
+        // A fixed mix of inserts, deletes and replacements all over the text
+        for step in 0..200usize {
+            let at = step * 7 % (text.len() + 1);
+            let end = (at + step % 5).min(text.len());
+            rope = match step % 3 {
+                0 => rope.insert(at, "ab"),
+                1 => rope.delete_range(at, end),
+                _ => rope.replace_range(at, end, "xyz"),
+            };
+            text = match step % 3 {
+                0 => format!("{}ab{}", &text[..at], &text[at..]),
+                1 => format!("{}{}", &text[..at], &text[end..]),
+                _ => format!("{}xyz{}", &text[..at], &text[end..]),
+            };
+            rope.validate();
+            assert_eq!(rope.to_string(), text);
+        }
+        let (left, right) = rope.split(text.len() / 2);
+        left.validate();
+        right.validate();
+    }
+
+    #[test]
+    fn long_text_is_split_into_leaves_at_character_boundaries() {
+        let text = "€".repeat(MAX_LEAF_SIZE); // Three times the limit, in bytes
+        let rope = Rope::from_string(&text);
+        rope.validate();
+        assert_eq!(rope.to_string(), text);
+        assert!(rope.chunks().all(|chunk| chunk.len() <= MAX_LEAF_SIZE));
+        assert_eq!(rope.depth_and_leaves().1, 4);
+
+        let mut builder = RopeBuilder::new();
+        builder.push("a".to_string());
+        builder.push(text.clone());
+        assert_eq!(builder.finish().to_string(), format!("a{}", text));
+    }
+
+    #[test]
+    #[should_panic(expected = "rope leaf")]
+    fn validate_catches_an_oversized_leaf() {
+        let rope = Rope {
+            root: Rc::new(RopeNode::Leaf("x".repeat(MAX_LEAF_SIZE + 1))),
+        };
+        rope.validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "rope weight")]
+    fn validate_catches_a_bad_weight() {
+        let rope = Rope {
+            root: Rc::new(RopeNode::Internal {
+                left: Rc::new(RopeNode::Leaf("ab".to_string())),
+                right: Rc::new(RopeNode::Leaf("c".to_string())),
+                weight: 1,
+                newlines: 0,
+                depth: 1,
+                leaves: 2,
+            }),
+        };
+        rope.validate();
+    }
+}
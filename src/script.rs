@@ -0,0 +1,175 @@
+// Headless batch editing, for sed-like edits with the editor's own commands:
+//
+//     rope-editor --script notes.txt --exec "replace foo bar; save"
+//
+// The file is loaded, the statements run in order, and the editor exits
+// without touching the terminal. Statements are separated by `;` or
+// newlines; words by whitespace. Double quotes keep spaces and `;` in a
+// word, with `\n`, `\t`, `\"` and `\\` escapes inside them.
+//
+//     replace FROM TO   Replace every FROM with TO, as one undo step
+//     type TEXT         Insert TEXT at the cursor
+//     insert L C TEXT   Insert TEXT at line L, column C, both from 1
+//     save              Write the file; failing to is an error
+//     COMMAND           Any editor command by its name in kebab case,
+//                       e.g. `undo`, `move-down`, `line-endings-to-crlf`
+//
+// The first failing statement stops the script; the error goes to stderr
+// and the exit code is 1.
+
+use std::path::Path;
+
+use crate::command::{self, EditorCommand};
+use crate::config::Config;
+use crate::Editor;
+
+// Entry point for `--script`/`--exec`; returns the process exit code
+pub fn main(args: &[String]) -> i32 {
+    let mut file = None;
+    let mut script = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--script" => file = args.next(),
+            "--exec" => script = args.next(),
+            other => {
+                eprintln!("unexpected argument: {}", other);
+                return 2;
+            }
+        }
+    }
+    let (Some(file), Some(script)) = (file, script) else {
+        eprintln!("usage: rope-editor --script FILE --exec SCRIPT");
+        return 2;
+    };
+
+    let mut editor = Editor::new(Config::load());
+    if let Err(e) = editor.load_file(Path::new(file)) {
+        eprintln!("{}: {}", file, e);
+        return 1;
+    }
+    match run(&mut editor, script) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+pub fn run(editor: &mut Editor, script: &str) -> Result<(), String> {
+    for (n, statement) in parse(script)?.into_iter().enumerate() {
+        let words: Vec<&str> = statement.iter().map(String::as_str).collect();
+        match words.as_slice() {
+            ["replace", from, to] => replace_all(editor, from, to)?,
+            ["type", text] => editor.insert(text),
+            ["insert", line, col, text] => {
+                let position = |word: &str| word.parse::<usize>().ok().filter(|&n| n > 0);
+                let (Some(line), Some(col)) = (position(line), position(col)) else {
+                    return Err(format!("statement {}: insert needs a line and column from 1", n + 1));
+                };
+                editor.insert_at_line_col(line - 1, col - 1, text);
+            }
+            ["save"] => editor.save_file().map_err(|e| format!("save failed: {}", e))?,
+            [name] => match find_command(name) {
+                Some(EditorCommand::Quit) => return Ok(()),
+                Some(command) => editor.execute(command),
+                None => return Err(format!("statement {}: unknown command `{}`", n + 1, name)),
+            },
+            _ => return Err(format!("statement {}: can't understand `{}`", n + 1, words.join(" "))),
+        }
+    }
+    Ok(())
+}
+
+fn replace_all(editor: &mut Editor, from: &str, to: &str) -> Result<(), String> {
+    if from.is_empty() {
+        return Err("replace: nothing to search for".to_string());
+    }
+    let content = editor.content();
+    if content.contains(from) {
+        editor.replace_range(0, content.len(), &content.replace(from, to));
+    }
+    Ok(())
+}
+
+// `move-left` for EditorCommand::MoveLeft
+fn find_command(name: &str) -> Option<EditorCommand> {
+    let wanted: String = name.chars().filter(|&c| c != '-' && c != '_').collect();
+    command::all_commands()
+        .into_iter()
+        .find(|cmd| format!("{:?}", cmd).eq_ignore_ascii_case(&wanted))
+}
+
+// Statements as lists of words
+fn parse(script: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut statements = Vec::new();
+    let mut words = Vec::new();
+    let mut chars = script.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            ';' | '\n' => {
+                if !words.is_empty() {
+                    statements.push(std::mem::take(&mut words));
+                }
+            }
+            c if c.is_whitespace() => {}
+            '"' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => word.push(match chars.next() {
+                            Some('n') => '\n',
+                            Some('t') => '\t',
+                            Some(c) => c,
+                            None => return Err("unterminated string".to_string()),
+                        }),
+                        Some(c) => word.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                words.push(word);
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ';' || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                words.push(word);
+            }
+        }
+    }
+    if !words.is_empty() {
+        statements.push(words);
+    }
+    Ok(statements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_statements_in_order() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("one two one\n");
+        run(&mut editor, "replace one \"1; 1\"\nmove-down; type end").unwrap();
+        assert_eq!(editor.content(), "1; 1 two 1; 1\nend");
+        run(&mut editor, "undo").unwrap();
+        assert_eq!(editor.content(), "1; 1 two 1; 1\n");
+        run(&mut editor, "insert 1 3 \" x\"; insert 9 1 end").unwrap();
+        assert_eq!(editor.content(), "1; x 1 two 1; 1\nend");
+        run(&mut editor, "undo").unwrap();
+        assert_eq!(editor.content(), "1; x 1 two 1; 1\n");
+
+        assert!(run(&mut editor, "replace one").is_err());
+        assert!(run(&mut editor, "type \"open").is_err());
+        assert!(run(&mut editor, "insert 0 1 x").is_err());
+    }
+}
@@ -0,0 +1,90 @@
+// Sessions: the file being edited, where the cursor and view were in it,
+// and the files closed before it (what "Reopen last closed file" goes
+// through), saved to a file of the user's choosing. Each line is
+// `active<TAB>line<TAB>col<TAB>scroll<TAB>path` or `closed<TAB>path`,
+// closed files oldest first.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct View {
+    pub line: usize, // Cursor line and column, from 0
+    pub col: usize,
+    pub scroll: usize, // First line on screen
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Session {
+    pub active: Option<(PathBuf, View)>,
+    pub closed: Vec<PathBuf>,
+}
+
+impl Session {
+    // Unreadable lines are skipped, as in the positions file
+    pub fn parse(text: &str) -> Session {
+        let mut session = Session::default();
+        for line in text.lines() {
+            let fields: Vec<&str> = line.splitn(5, '\t').collect();
+            match fields[..] {
+                ["active", line, col, scroll, path] => {
+                    if let (Ok(line), Ok(col), Ok(scroll)) = (line.parse(), col.parse(), scroll.parse()) {
+                        session.active = Some((PathBuf::from(path), View { line, col, scroll }));
+                    }
+                }
+                ["closed", path] => session.closed.push(PathBuf::from(path)),
+                _ => {}
+            }
+        }
+        session
+    }
+
+    pub fn format(&self) -> String {
+        let mut text = String::new();
+        if let Some((path, view)) = &self.active {
+            text += &format!("active\t{}\t{}\t{}\t{}\n", view.line, view.col, view.scroll, path.display());
+        }
+        for path in &self.closed {
+            text += &format!("closed\t{}\n", path.display());
+        }
+        text
+    }
+
+    // Leaves out the files that are gone, handing back their paths
+    pub fn drop_missing(&mut self) -> Vec<PathBuf> {
+        let mut missing: Vec<PathBuf> = self.closed.iter().filter(|path| !path.exists()).cloned().collect();
+        self.closed.retain(|path| path.exists());
+        if let Some((path, _)) = self.active.as_ref().filter(|(path, _)| !path.exists()) {
+            missing.insert(0, path.clone());
+            self.active = None;
+        }
+        missing
+    }
+}
+
+// Absolute, so the session can be restored from any directory
+pub fn absolute(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sessions_round_trip_and_lose_missing_files() {
+        let session = Session::parse("active\t3\t4\t1\t/a b/c.txt\nbad line\nclosed\t/d.rs\nclosed\t/e.rs\n");
+        let view = View { line: 3, col: 4, scroll: 1 };
+        assert_eq!(session.active, Some((PathBuf::from("/a b/c.txt"), view)));
+        assert_eq!(session.closed, [PathBuf::from("/d.rs"), PathBuf::from("/e.rs")]);
+        assert_eq!(Session::parse(&session.format()), session);
+
+        let here = absolute(Path::new("src/session.rs"));
+        let mut session = Session {
+            active: Some((PathBuf::from("/no/such/file"), view)),
+            closed: vec![here.clone(), PathBuf::from("/no/such/other")],
+        };
+        assert_eq!(session.drop_missing(), [PathBuf::from("/no/such/file"), PathBuf::from("/no/such/other")]);
+        assert_eq!(session, Session { active: None, closed: vec![here] });
+    }
+}
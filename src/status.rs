@@ -0,0 +1,85 @@
+// The status line, built from independent segments. Each feature adds its
+// own piece with a priority; when the terminal is too narrow, the
+// lowest-priority pieces are dropped first.
+
+const SEPARATOR: &str = " | ";
+
+pub struct StatusLine {
+    segments: Vec<(u8, String)>, // (priority, text), in display order
+}
+
+impl StatusLine {
+    pub fn new() -> Self {
+        StatusLine { segments: Vec::new() }
+    }
+
+    // Empty segments are left out rather than leaving a gap between separators
+    pub fn push(&mut self, priority: u8, text: impl Into<String>) {
+        let text = text.into();
+        if !text.is_empty() {
+            self.segments.push((priority, text));
+        }
+    }
+
+    // Exactly `width` characters: segments that don't fit are dropped, and
+    // if even the most important one is too long it's cut short with `…`
+    pub fn render(&self, width: usize) -> String {
+        let mut shown: Vec<&(u8, String)> = self.segments.iter().collect();
+        while shown.len() > 1 && joined_width(&shown) > width {
+            let lowest = (0..shown.len()).min_by_key(|&i| shown[i].0).unwrap();
+            shown.remove(lowest);
+        }
+        let text = shown.iter().map(|(_, text)| text.as_str()).collect::<Vec<_>>().join(SEPARATOR);
+        fit(&text, width)
+    }
+}
+
+fn joined_width(segments: &[&(u8, String)]) -> usize {
+    let text: usize = segments.iter().map(|(_, text)| text.chars().count()).sum();
+    text + SEPARATOR.len() * segments.len().saturating_sub(1)
+}
+
+// Cut or pad `text` to `width` characters, so it can't wrap onto another
+// row and leaves nothing of a longer previous line behind
+pub fn fit(text: &str, width: usize) -> String {
+    let len = text.chars().count();
+    if len > width {
+        let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+        if width > 0 {
+            cut.push('…');
+        }
+        cut
+    } else {
+        format!("{}{}", text, " ".repeat(width - len))
+    }
+}
+
+pub fn format_size(bytes: usize) -> String {
+    if bytes < 1024 {
+        format!("{} B", bytes)
+    } else if bytes < 1024 * 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else if bytes < 1024 * 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else {
+        format!("{:.1} GB", bytes as f64 / (1024.0 * 1024.0 * 1024.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_fits_narrow_widths() {
+        let mut status = StatusLine::new();
+        status.push(9, "File: notes.txt");
+        status.push(1, "UTF-8");
+
+        assert_eq!(status.render(30), "File: notes.txt | UTF-8       ");
+        assert_eq!(status.render(16), "File: notes.txt ");
+        assert_eq!(status.render(8), "File: n…");
+        assert_eq!(status.render(1), "…");
+        assert_eq!(status.render(0), "");
+    }
+}
@@ -0,0 +1,1208 @@
+// Terminal rendering. A frame is drawn into one byte buffer per screen row,
+// and only rows that differ from the previous frame are written out, so
+// typing doesn't repaint (and flicker) the whole screen.
+
+use crossterm::{
+    cursor::{self, SetCursorStyle}, queue,
+    style::{Attribute, Color, Print, ResetColor, SetAttribute, SetBackgroundColor, SetForegroundColor},
+    terminal::{self, Clear, ClearType},
+};
+use std::fs::File;
+use std::io::{self, Write};
+use std::ops::Range;
+use std::time::Instant;
+
+use crate::column;
+use crate::config::{CursorShape, LineNumbers};
+use crate::diff::{self, LineChange};
+use crate::line_ending::LineEndings;
+use crate::prompt::PromptKind;
+use crate::rope::Rope;
+use crate::status::{self, StatusLine};
+use crate::{Editor, GUTTER_WIDTH};
+
+// How the terminal's own cursor should look
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum CursorStyle {
+    Hidden,
+    UserDefault, // Whatever the terminal was set to before we started
+    Shape(CursorShape, bool), // Blinking when true
+}
+
+// One byte buffer per screen row, and where the cursor goes
+type Frame = (Vec<Vec<u8>>, (u16, u16));
+
+// Every line's change marker, with the text and baseline they compare
+pub type Gutter = (Rope, Rope, Vec<Option<LineChange>>);
+
+impl CursorStyle {
+    fn command(self) -> SetCursorStyle {
+        match self {
+            CursorStyle::Hidden | CursorStyle::UserDefault => SetCursorStyle::DefaultUserShape,
+            CursorStyle::Shape(CursorShape::Block, true) => SetCursorStyle::BlinkingBlock,
+            CursorStyle::Shape(CursorShape::Block, false) => SetCursorStyle::SteadyBlock,
+            CursorStyle::Shape(CursorShape::Bar, true) => SetCursorStyle::BlinkingBar,
+            CursorStyle::Shape(CursorShape::Bar, false) => SetCursorStyle::SteadyBar,
+            CursorStyle::Shape(CursorShape::Underline, true) => SetCursorStyle::BlinkingUnderScore,
+            CursorStyle::Shape(CursorShape::Underline, false) => SetCursorStyle::SteadyUnderScore,
+        }
+    }
+}
+
+// Where the UI is drawn: stdout, unless stdout is carrying the edited text
+// down a pipe, in which case the terminal is opened directly
+pub enum Output {
+    Stdout(io::Stdout),
+    Tty(File),
+}
+
+impl Output {
+    pub fn tty() -> io::Result<Self> {
+        File::options().write(true).open("/dev/tty").map(Output::Tty)
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Output::Stdout(out) => out.write(buf),
+            Output::Tty(out) => out.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Output::Stdout(out) => out.flush(),
+            Output::Tty(out) => out.flush(),
+        }
+    }
+}
+
+// What is currently on the terminal
+pub struct Screen {
+    size: (u16, u16),
+    rows: Vec<Vec<u8>>,
+    cursor_style: Option<CursorStyle>, // None until first set
+}
+
+impl Screen {
+    pub fn new() -> Self {
+        Screen {
+            size: (0, 0),
+            rows: Vec::new(),
+            cursor_style: None,
+        }
+    }
+
+    // Forget the previous frame so the next one is drawn in full
+    pub fn invalidate(&mut self) {
+        self.size = (0, 0);
+        self.rows.clear();
+        self.cursor_style = None;
+    }
+
+    pub fn present<W: Write>(
+        &mut self,
+        out: &mut W,
+        size: (u16, u16),
+        rows: Vec<Vec<u8>>,
+        cursor_pos: (u16, u16),
+        cursor_style: CursorStyle,
+    ) -> io::Result<()> {
+        if size != self.size {
+            // After a resize the old rows mean nothing, so start clean
+            queue!(out, Clear(ClearType::All))?;
+            self.size = size;
+            self.rows.clear();
+        }
+
+        for (i, row) in rows.iter().enumerate() {
+            if self.rows.get(i) == Some(row) {
+                continue;
+            }
+            queue!(out, cursor::MoveTo(0, i as u16))?;
+            out.write_all(row)?;
+            queue!(
+                out,
+                SetAttribute(Attribute::Reset),
+                ResetColor,
+                Clear(ClearType::UntilNewLine)
+            )?;
+        }
+        for i in rows.len()..self.rows.len() {
+            queue!(out, cursor::MoveTo(0, i as u16), Clear(ClearType::CurrentLine))?;
+        }
+
+        queue!(out, cursor::MoveTo(cursor_pos.0, cursor_pos.1))?;
+        if self.cursor_style != Some(cursor_style) {
+            if cursor_style == CursorStyle::Hidden {
+                queue!(out, cursor::Hide)?;
+            } else {
+                queue!(out, cursor_style.command(), cursor::Show)?;
+            }
+            self.cursor_style = Some(cursor_style);
+        }
+        out.flush()?;
+        self.rows = rows;
+        Ok(())
+    }
+}
+
+impl Editor {
+    pub fn render(&mut self) -> io::Result<()> {
+        let started = Instant::now();
+        let size = terminal::size()?;
+        self.view_rows = (size.1 as usize).saturating_sub(1);
+        self.view_cols = self.layout(size.0).text_width;
+        self.scroll_to_cursor();
+        let (mut rows, cursor_pos) = self.draw_frame(size.0, size.1)?;
+        // The HUD's own drawing is left out of the time it shows
+        let drawn = started.elapsed();
+        if self.debug_hud {
+            self.draw_hud(&mut rows, size.0)?;
+        }
+        self.draw_key_log(&mut rows, size.0)?;
+        let presenting = Instant::now();
+        if std::mem::take(&mut self.ring) {
+            self.output.write_all(b"\x07")?;
+        }
+        let cursor_style = self.cursor_style();
+        self.screen.present(&mut self.output, size, rows, cursor_pos, cursor_style)?;
+        self.render_time = drawn + presenting.elapsed();
+        Ok(())
+    }
+
+    // Numbers for chasing slow frames: the last frame's time and the shape
+    // of the rope and undo history
+    fn hud_lines(&self) -> Vec<String> {
+        let (depth, leaves) = self.rope.depth_and_leaves();
+        vec![
+            format!("render {:.2}ms", self.render_time.as_secs_f64() * 1000.0),
+            format!("depth {} leaves {}", depth, leaves),
+            format!("length {}", self.rope.len()),
+            format!("undo {} redo {}", self.history.depth(), self.history.redo_len()),
+            format!("cursor {}", self.cursor),
+        ]
+    }
+
+    // Over the top right of the text, clear of the minimap and, like it, of
+    // the terminal's last column
+    fn draw_hud(&self, rows: &mut [Vec<u8>], term_width: u16) -> io::Result<()> {
+        let lines = self.hud_lines();
+        let width = lines.iter().map(|line| line.len()).max().unwrap_or(0) + 2;
+        let right = (term_width as usize).saturating_sub(if self.config.minimap { MINIMAP_WIDTH } else { 1 });
+        if width > right {
+            return Ok(());
+        }
+        for (row, line) in rows.iter_mut().zip(lines) {
+            queue!(
+                row,
+                cursor::MoveToColumn((right - width) as u16),
+                SetAttribute(Attribute::Reverse),
+                Print(format!(" {:<w$} ", line, w = width - 2)),
+                SetAttribute(Attribute::NoReverse)
+            )?;
+        }
+        Ok(())
+    }
+
+    // Dimly over the bottom right of the text, the newest key lowest
+    fn draw_key_log(&self, rows: &mut [Vec<u8>], term_width: u16) -> io::Result<()> {
+        let Some(log) = &self.key_log else {
+            return Ok(());
+        };
+        let lines = log.lines();
+        let right = (term_width as usize).saturating_sub(if self.config.minimap { MINIMAP_WIDTH } else { 1 });
+        let width = (lines.iter().map(|line| line.chars().count()).max().unwrap_or(0) + 2).min(KEY_LOG_WIDTH);
+        if width > right {
+            return Ok(());
+        }
+        let text_rows = rows.len().saturating_sub(1);
+        for (row, line) in rows[..text_rows].iter_mut().rev().zip(lines.iter().rev()) {
+            queue!(
+                row,
+                cursor::MoveToColumn((right - width) as u16),
+                SetForegroundColor(Color::DarkGrey),
+                Print(format!(" {} ", status::fit(line, width - 2))),
+                ResetColor
+            )?;
+        }
+        Ok(())
+    }
+
+    // Scroll just enough to keep the cursor line, and `scrolloff` lines
+    // around it, on screen, and the cursor's column within the screen width.
+    // Called on every render, and straight after edits that can move the
+    // cursor far away (e.g. undo).
+    pub fn scroll_to_cursor(&mut self) {
+        let (cursor_line, _) = self.cursor_line_col();
+        let total_lines = self.rope.line_at(self.rope.len());
+        self.scroll_offset = scroll_for(
+            self.scroll_offset,
+            cursor_line,
+            total_lines + 1,
+            self.view_rows,
+            self.config.scrolloff,
+        )
+        .min(self.max_scroll());
+
+        let line_start = self.rope.line_col_to_index(cursor_line, 0);
+        let before = self.rope.substring(line_start, self.cursor);
+        let cursor_x = column::display_width(&before, self.indent.tab_width);
+        if self.wrapped_line != Some(cursor_line) {
+            // Leaving a wrapped line puts it back to scrolling sideways
+            self.wrapped_line = None;
+            // The last column is left for the marker of a line that goes on
+            let cols = self.view_cols.saturating_sub(1);
+            self.col_offset = scroll_for(self.col_offset, cursor_x, cursor_x + 1, cols, 0);
+        } else if self.view_cols > 0 {
+            // A wrapped line takes a row per screen width. `col_offset` is
+            // where the first row shown starts, which is only past 0 when
+            // the line needs more rows than the screen has.
+            let width = self.view_cols;
+            let row = cursor_x / width;
+            let mut first = (self.col_offset / width).min(row);
+            let rows_needed = row - first + 1;
+            if rows_needed > self.view_rows {
+                first = row + 1 - self.view_rows;
+                self.scroll_offset = cursor_line;
+            } else if cursor_line - self.scroll_offset + rows_needed > self.view_rows {
+                self.scroll_offset = cursor_line + rows_needed - self.view_rows;
+            }
+            self.col_offset = first * width;
+        }
+    }
+
+    // Buffer offset shown at a terminal cell, for mouse clicks. Clicks left
+    // of the text land at the start of the line, and below the last line on
+    // the last line; the status line isn't text at all.
+    pub fn offset_at(&self, term_width: u16, column: u16, row: u16) -> Option<usize> {
+        if row as usize >= self.view_rows {
+            return None;
+        }
+        let last_line = self.rope.line_at(self.rope.len());
+        let layout = self.layout(term_width);
+        // Walk the rows down from the top as draw_frame lays them out
+        let (mut line, mut row) = (self.scroll_offset.min(last_line), row as usize);
+        let (text, left) = loop {
+            let start = self.rope.line_col_to_index(line, 0);
+            let end = self.rope.line_col_to_index(line + 1, 0);
+            let mut text = self.rope.substring(start, end);
+            if text.ends_with('\n') {
+                text.pop();
+            }
+            let segments = self.segments(line, &text, layout.text_width);
+            if row < segments.len() || line == last_line {
+                let left = segments[row.min(segments.len() - 1)];
+                break (text, left);
+            }
+            row -= segments.len();
+            line += 1;
+        };
+        let mut x = (column as usize).saturating_sub(layout.screen_col(0) as usize);
+        if self.wrapped_line == Some(line) {
+            // Past the right edge is still this row, not the next one
+            x = x.min(layout.text_width.saturating_sub(1));
+        }
+        let col = column::char_col_at(&text, left + x, self.indent.tab_width);
+        Some(self.rope.line_col_to_index(line, col))
+    }
+
+    // Where each screen row of a line starts, as a column into it: normally
+    // one row, from the part scrolled into view, or all of it (from
+    // `col_offset`) a row per screen width for a line wrapped on demand
+    fn segments(&self, line: usize, text: &str, text_width: usize) -> Vec<usize> {
+        if self.wrapped_line == Some(line) && text_width > 0 {
+            let last = column::display_width(text, self.indent.tab_width) / text_width;
+            (self.col_offset / text_width..=last).map(|n| n * text_width).collect()
+        } else {
+            vec![self.col_offset]
+        }
+    }
+
+    // Where the text column sits across the terminal. Everything that turns
+    // a text column into a screen column goes through this.
+    fn layout(&self, term_width: u16) -> Layout {
+        let minimap = if self.config.minimap { MINIMAP_WIDTH } else { 0 };
+        // Wide enough for the last line's number, which no relative
+        // distance can beat, so the text doesn't shift as the cursor moves
+        let numbers = if self.config.line_numbers == LineNumbers::Off {
+            0
+        } else {
+            let last_line = self.rope.line_at(self.rope.len());
+            (last_line + 1).to_string().len() + 1
+        };
+        let available = (term_width as usize).saturating_sub(GUTTER_WIDTH as usize + numbers + minimap);
+        let text_width = if self.config.centered { available.min(self.config.center_width) } else { available };
+        Layout {
+            padding: (available - text_width) / 2,
+            numbers,
+            text_width,
+        }
+    }
+
+    // The place for a mode (e.g. bar while inserting) to pick its own shape
+    pub fn cursor_style(&self) -> CursorStyle {
+        let editing = self.prompt.is_none() && self.overlay.is_none();
+        match self.config.cursor_shape {
+            _ if self.idle && editing => CursorStyle::Hidden,
+            Some(shape) => CursorStyle::Shape(shape, self.config.cursor_blink),
+            // The text cursor is drawn, but prompts and overlays still need
+            // the real one
+            None if self.prompt.is_some() || self.overlay.is_some() => CursorStyle::UserDefault,
+            None => CursorStyle::Hidden,
+        }
+    }
+
+    // Segments from highest to lowest priority: what's being edited and
+    // where, then messages, then details about the file
+    fn status_line(&self, cursor_line: usize, cursor_col: usize, line_len: usize) -> StatusLine {
+        let mut status = StatusLine::new();
+        status.push(9, format!("File: {}", self.filename.as_deref().unwrap_or("Untitled")));
+        status.push(9, if self.dirty { "[Modified]" } else { "" });
+        if let Some(register) = self.macros.recording() {
+            status.push(8, format!("Recording @{}", register));
+        }
+        status.push(7, format!("Ln {}, Col {}", cursor_line + 1, cursor_col + 1));
+        if let Some((chars, lines)) = self.selection_size() {
+            let lines = if lines == 1 { String::new() } else { format!(", {} lines", lines) };
+            status.push(7, format!("Sel {}{}", chars, lines));
+        }
+        status.push(3, format!("Len {}", line_len));
+        status.push(5, if self.overwrite { "OVR" } else { "INS" });
+        status.push(6, self.status_message.clone().unwrap_or_default());
+        if let Some((Some(nth), total)) = self.search_position() {
+            status.push(6, format!("Match {} of {}", nth, total));
+        }
+        status.push(4, self.encoding.name());
+        status.push(3, self.line_endings().label());
+        status.push(2, status::format_size(self.rope.len()));
+        status.push(2, self.link.clone().unwrap_or_default());
+        status.push(1, self.indent_label());
+        status
+    }
+
+    // Change markers for the lines in `visible`. The diff against the
+    // baseline covers the whole file, so it's kept until either side changes
+    // rather than run again every frame.
+    fn gutter_markers(&self, visible: Range<usize>) -> Vec<Option<LineChange>> {
+        let mut cache = self.gutter.borrow_mut();
+        let fresh = matches!(&*cache, Some((rope, baseline, _))
+            if Rope::ptr_eq(rope, &self.rope) && Rope::ptr_eq(baseline, &self.baseline));
+        if !fresh {
+            let markers = if self.rope == self.baseline {
+                Vec::new()
+            } else {
+                let (content, base_content) = (self.rope.to_string(), self.baseline.to_string());
+                let lines: Vec<&str> = content.split('\n').collect();
+                let base_lines: Vec<&str> = base_content.split('\n').collect();
+                diff::visible_changes(&base_lines, &lines, 0..lines.len())
+            };
+            *cache = Some((self.rope.clone(), self.baseline.clone(), markers));
+        }
+        let markers = cache.as_ref().map_or(&[][..], |(_, _, markers)| markers);
+        visible.map(|line| markers.get(line).copied().flatten()).collect()
+    }
+
+    // The buffer's line endings for the status line, counted again only
+    // after an edit
+    fn line_endings(&self) -> LineEndings {
+        let mut cache = self.endings.borrow_mut();
+        match &*cache {
+            Some((rope, endings)) if Rope::ptr_eq(rope, &self.rope) => *endings,
+            _ => {
+                let endings = LineEndings::count(self.rope.chars());
+                *cache = Some((self.rope.clone(), endings));
+                endings
+            }
+        }
+    }
+
+    // Lay out the whole screen: text rows, then the status line on the last row
+    fn draw_frame(&self, term_width: u16, term_height: u16) -> io::Result<Frame> {
+        let text_rows = (term_height as usize).saturating_sub(1);
+        let mut rows = vec![Vec::new(); text_rows];
+
+        // Only the lines on screen are copied out of the rope, so a frame
+        // costs the same however big the file is
+        let line_count = self.rope.line_at(self.rope.len()) + 1;
+        let top = self.scroll_offset.min(line_count - 1);
+        let visible = top..(top + text_rows).min(line_count);
+        let line_text = |line: usize| {
+            let range = self.rope.line_range(line);
+            self.rope.substring(range.start, range.end)
+        };
+        let lines: Vec<String> = visible.clone().map(line_text).collect();
+        let markers = self.gutter_markers(visible.clone());
+
+        let (cursor_line, cursor_col) = self.cursor_line_col();
+        let tab_width = self.indent.tab_width;
+        let layout = self.layout(term_width);
+        let text_width = layout.text_width;
+
+        let selection = self.selection();
+        // Found afresh every frame, so matches follow edits
+        let query = self.search.as_deref().filter(|q| self.highlight_matches && !q.is_empty());
+        let mut line_start = self.rope.line_range(top).start;
+        let mut row = 0;
+        for (i, line) in lines.iter().enumerate() {
+            if row == text_rows {
+                break;
+            }
+            let wrapped = self.wrapped_line == Some(top + i) && text_width > 0;
+            let segments = self.segments(top + i, line, text_width);
+            let matches: Vec<Range<usize>> = query
+                .map(|q| line.match_indices(q).map(|(i, m)| i..i + m.len()).collect())
+                .unwrap_or_default();
+            // Guides are drawn in the leading whitespace, up to this column
+            let guides_to = if self.config.indent_guides {
+                let indent = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+                column::display_width(indent, tab_width)
+            } else {
+                0
+            };
+            for (n, &left) in segments.iter().enumerate() {
+                if row == text_rows {
+                    break;
+                }
+                let out = &mut rows[row];
+                row += 1;
+                if n > 0 {
+                    queue!(out, Print(" ".repeat(layout.padding + GUTTER_WIDTH as usize + layout.numbers)))?;
+                } else {
+                    let (marker, color) = match markers[i] {
+                        Some(LineChange::Added) => ("+", Color::Green),
+                        Some(LineChange::Modified) => ("~", Color::Yellow),
+                        Some(LineChange::Removed) => ("_", Color::Red),
+                        None => (" ", Color::Reset),
+                    };
+                    queue!(
+                        out,
+                        Print(" ".repeat(layout.padding)),
+                        SetForegroundColor(color),
+                        Print(format!("{:<width$}", marker, width = GUTTER_WIDTH as usize)),
+                        ResetColor
+                    )?;
+                    if let Some(number) = self.config.line_numbers.label(top + i, cursor_line) {
+                        let color = if top + i == cursor_line { Color::Yellow } else { Color::DarkGrey };
+                        queue!(
+                            out,
+                            SetForegroundColor(color),
+                            Print(format!("{:>width$} ", number, width = layout.numbers - 1)),
+                            ResetColor
+                        )?;
+                    }
+                }
+                let right = left + text_width;
+
+                let drawn_cursor = top + i == cursor_line && self.config.cursor_shape.is_none() && !self.idle;
+                let mut selected = false;
+                let mut col = 0;
+                let mut x = 0;
+                let mut clipped = false;
+                let mut background = Color::Reset;
+                // Only the on-screen stretch of the line is drawn: characters
+                // left of it are just measured, and the walk stops at the
+                // right edge
+                let mut chars = line.char_indices().peekable();
+                while let Some((byte, ch)) = chars.next() {
+                    let next_x = column::advance(x, ch, tab_width);
+                    if next_x <= left {
+                        x = next_x;
+                        col += 1;
+                        continue;
+                    }
+                    // The last column is kept for the marker if there's more,
+                    // unless the rest goes on the next row
+                    if next_x > right || (next_x == right && chars.peek().is_some() && !wrapped) {
+                        clipped = true;
+                        break;
+                    }
+                    let in_selection = matches!(&selection, Some(s) if s.contains(&(line_start + byte)));
+                    if in_selection != selected {
+                        let attr = if in_selection { Attribute::Reverse } else { Attribute::NoReverse };
+                        queue!(out, SetAttribute(attr))?;
+                        selected = in_selection;
+                    }
+                    // Backgrounds, so selection (reverse video) still shows on
+                    // top. Search matches win over text past the length limit.
+                    let color = if matches.iter().any(|m| m.contains(&byte)) {
+                        Color::DarkYellow
+                    } else if matches!(self.config.line_length_limit, Some(limit) if next_x > limit) {
+                        self.config.line_length_color
+                    } else {
+                        Color::Reset
+                    };
+                    if color != background {
+                        queue!(out, SetBackgroundColor(color))?;
+                        background = color;
+                    }
+                    if ch == '\r' {
+                        // Printed as is, it would send the terminal back to column 0
+                        if self.config.show_line_endings {
+                            queue!(out, SetForegroundColor(Color::DarkGrey), Print('␍'), SetForegroundColor(Color::Reset))?;
+                        } else {
+                            queue!(out, Print(' '))?;
+                        }
+                    } else {
+                        // A guide at every tab stop in the indentation, even
+                        // partway through a tab
+                        let guided = x < guides_to;
+                        let text = if guided {
+                            (x.max(left)..next_x).map(|c| if c % tab_width == 0 { '│' } else { ' ' }).collect()
+                        } else if ch == '\t' || x < left {
+                            // Tabs, and the half of a wide character still
+                            // on screen, are drawn as blanks
+                            " ".repeat(next_x - x.max(left))
+                        } else {
+                            ch.to_string()
+                        };
+                        if guided {
+                            queue!(out, SetForegroundColor(Color::DarkGrey))?;
+                        }
+                        if drawn_cursor && col == cursor_col {
+                            queue!(
+                                out,
+                                SetAttribute(Attribute::Underlined),
+                                Print(text),
+                                SetAttribute(Attribute::NoUnderline)
+                            )?;
+                        } else {
+                            queue!(out, Print(text))?;
+                        }
+                        if guided {
+                            queue!(out, SetForegroundColor(Color::Reset))?;
+                        }
+                    }
+                    x = next_x;
+                    col += 1;
+                }
+                if selected {
+                    queue!(out, SetAttribute(Attribute::NoReverse))?;
+                }
+                if background != Color::Reset {
+                    queue!(out, SetBackgroundColor(Color::Reset))?;
+                }
+                if clipped && text_width > 0 && !wrapped {
+                    let gap = (right - 1).saturating_sub(x.max(left));
+                    queue!(
+                        out,
+                        Print(" ".repeat(gap)),
+                        SetForegroundColor(Color::DarkGrey),
+                        Print('>'),
+                        ResetColor
+                    )?;
+                }
+
+                // A selected line break shows as a selected blank after the
+                // line, so it's clear the selection carries on from there
+                let newline_selected = n + 1 == segments.len()
+                    && !clipped
+                    && x.max(left) < right
+                    && matches!(&selection, Some(s) if s.contains(&(line_start + line.len())));
+                if newline_selected {
+                    queue!(out, SetAttribute(Attribute::Reverse))?;
+                }
+                // Underline a space if cursor is at end of line
+                if drawn_cursor && cursor_col >= col && !clipped {
+                    queue!(
+                        out,
+                        SetAttribute(Attribute::Underlined),
+                        SetForegroundColor(Color::Cyan),
+                        Print(" "),
+                        SetAttribute(Attribute::NoUnderline)
+                    )?;
+                } else if newline_selected {
+                    queue!(out, Print(" "))?;
+                }
+                if newline_selected {
+                    queue!(out, SetAttribute(Attribute::NoReverse))?;
+                }
+            }
+            line_start += line.len() + 1;
+        }
+
+        // Mark rows past the end of the buffer so it's clear where it stops
+        if self.config.show_eof_markers {
+            for row in rows.iter_mut().skip(row) {
+                queue!(
+                    row,
+                    Print(" ".repeat(layout.padding)),
+                    SetForegroundColor(Color::DarkGrey),
+                    Print("~"),
+                    ResetColor
+                )?;
+            }
+        }
+
+        if self.config.minimap && text_width > 0 {
+            let map = minimap(line_count, |line| self.rope.line_range(line).len(), text_rows, visible.clone());
+            for (row, (glyph, in_view)) in rows.iter_mut().zip(map) {
+                let background = if in_view { Color::DarkGrey } else { Color::Reset };
+                queue!(
+                    row,
+                    cursor::MoveToColumn(term_width - MINIMAP_WIDTH as u16),
+                    SetBackgroundColor(background),
+                    Print(glyph),
+                    SetBackgroundColor(Color::Reset)
+                )?;
+            }
+        }
+
+        let cursor_text = match visible.contains(&cursor_line) {
+            true => lines[cursor_line - top].clone(),
+            false => line_text(cursor_line),
+        };
+        let cursor_x = column::display_col(&cursor_text, cursor_col, tab_width).saturating_sub(self.col_offset);
+        let mut cursor_pos = if self.wrapped_line == Some(cursor_line) && text_width > 0 {
+            let row = cursor_line.saturating_sub(top) + cursor_x / text_width;
+            (layout.screen_col(cursor_x % text_width), row as u16)
+        } else {
+            (layout.screen_col(cursor_x), cursor_line.saturating_sub(top) as u16)
+        };
+
+        let mut status_row = Vec::new();
+        if let Some(prompt) = &self.prompt {
+            let mut text = format!("{} {}", prompt.label, prompt.input);
+            let col = text.chars().count().min((term_width as usize).saturating_sub(1));
+            cursor_pos = (col as u16, term_height.saturating_sub(1));
+            // The search's match count at the right, if there's room
+            if let (PromptKind::Search, Some(total)) = (&prompt.kind, self.typed_match_count()) {
+                let count = if total == 1 { "1 match".to_string() } else { format!("{} matches", total) };
+                let gap = (term_width as usize).saturating_sub(text.chars().count() + count.len());
+                if gap > 0 {
+                    text = format!("{}{}{}", text, " ".repeat(gap), count);
+                }
+            }
+            queue!(status_row, Print(status::fit(&text, term_width as usize)))?;
+        } else {
+            let line_len = cursor_text.chars().count();
+            let status = self.status_line(cursor_line, cursor_col, line_len);
+            if self.flash_until.is_some() {
+                queue!(status_row, SetAttribute(Attribute::Reverse))?;
+            }
+            queue!(
+                status_row,
+                SetForegroundColor(Color::Cyan),
+                Print(status.render(term_width as usize)),
+                ResetColor
+            )?;
+        }
+
+        if let Some(overlay) = &self.overlay {
+            cursor_pos = overlay.render(&mut rows, term_width)?;
+        }
+
+        rows.push(status_row);
+        Ok((rows, cursor_pos))
+    }
+}
+
+// Horizontal placement of the text: `padding` blank columns, the gutter,
+// `numbers` columns of line numbers, then `text_width` columns of text
+struct Layout {
+    padding: usize,
+    numbers: usize,
+    text_width: usize,
+}
+
+impl Layout {
+    // Terminal column of text column `x`, counted from the left of the
+    // visible text
+    fn screen_col(&self, x: usize) -> u16 {
+        (self.padding + GUTTER_WIDTH as usize + self.numbers + x) as u16
+    }
+}
+
+// The minimap is one column, kept off the terminal's last column: the
+// clear at the end of each row would erase anything printed there
+const MINIMAP_WIDTH: usize = 2;
+
+// Lines sampled per minimap row, so a huge file costs no more than a small one
+const MINIMAP_SAMPLES: usize = 4;
+
+// Widest the keystroke display gets, padding included; long command names
+// are cut short
+const KEY_LOG_WIDTH: usize = 40;
+
+// One glyph per screen row, shaded by how long the lines it stands for
+// are, and whether they're in `view`. Each row covers an equal share of
+// the file.
+fn minimap(line_count: usize, line_len: impl Fn(usize) -> usize, rows: usize, view: Range<usize>) -> Vec<(char, bool)> {
+    let per_row = line_count.div_ceil(rows.max(1)).max(1);
+    (0..rows)
+        .map(|row| {
+            let covered = (row * per_row).min(line_count)..((row + 1) * per_row).min(line_count);
+            if covered.is_empty() {
+                return (' ', false);
+            }
+            let step = covered.len().div_ceil(MINIMAP_SAMPLES);
+            let sampled: Vec<usize> = covered.clone().step_by(step).map(&line_len).collect();
+            let glyph = match sampled.iter().sum::<usize>() / sampled.len() {
+                0 => ' ',
+                1..=19 => '░',
+                20..=39 => '▒',
+                40..=79 => '▓',
+                _ => '█',
+            };
+            (glyph, covered.start < view.end && view.start < covered.end)
+        })
+        .collect()
+}
+
+// New top line for a view of `rows` lines, moving `top` as little as possible
+// so the cursor has `scrolloff` lines of context above and below it. Near
+// the ends of the file the margin shrinks to what's there.
+fn scroll_for(top: usize, cursor_line: usize, total_lines: usize, rows: usize, scrolloff: usize) -> usize {
+    if rows == 0 {
+        return cursor_line;
+    }
+    // A margin of half the screen or more would leave nowhere to move
+    let margin = scrolloff.min((rows - 1) / 2);
+    let first = cursor_line.saturating_sub(margin);
+    let last = (cursor_line + margin).min(total_lines.saturating_sub(1).max(cursor_line));
+    if first < top {
+        first
+    } else if last >= top + rows {
+        last + 1 - rows
+    } else {
+        top
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{minimap, scroll_for};
+    use crossterm::event::{KeyCode, KeyModifiers};
+    use crate::command::EditorCommand;
+    use crate::config::{Config, LineNumbers};
+    use crate::rope::Rope;
+    use crate::{Editor, GUTTER_WIDTH};
+
+    // Row text with the escape sequences taken out
+    fn visible(row: &[u8]) -> String {
+        let text = String::from_utf8(row.to_vec()).unwrap();
+        let mut out = String::new();
+        let mut in_escape = false;
+        for c in text.chars() {
+            if c == '\x1b' {
+                in_escape = true;
+            } else if in_escape {
+                in_escape = !c.is_ascii_alphabetic();
+            } else {
+                out.push(c);
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn status_line_fits_tiny_terminal() {
+        let mut editor = Editor::new(Config::default());
+        editor.rope = Rope::from_string("hello\nworld");
+        for width in [0, 1, 5, 12, 200] {
+            let (rows, _) = editor.draw_frame(width, 3).unwrap();
+            let status = visible(rows.last().unwrap());
+            assert_eq!(status.chars().count(), width as usize);
+        }
+        let (rows, _) = editor.draw_frame(8, 3).unwrap();
+        assert_eq!(visible(rows.last().unwrap()), "File: U…");
+    }
+
+    #[test]
+    fn status_line_shows_the_selection_size() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("héllo\nworld");
+        editor.cursor = 3; // After the é
+        editor.anchor = Some(0);
+        let status = |editor: &Editor| visible(editor.draw_frame(120, 3).unwrap().0.last().unwrap());
+        assert!(status(&editor).contains("Ln 1, Col 3 | Sel 2 |"), "{}", status(&editor));
+        // Made backwards, over a line break
+        editor.cursor = 4;
+        editor.anchor = Some(10);
+        assert!(status(&editor).contains("| Sel 6, 2 lines |"), "{}", status(&editor));
+
+        editor.execute(EditorCommand::SelectionLength);
+        assert_eq!(editor.status_message.as_deref(), Some("Selection: 6 characters, 2 lines, 6 bytes"));
+        assert_eq!(editor.anchor, Some(10));
+        editor.cursor = 0;
+        editor.execute(EditorCommand::SelectionLength);
+        assert_eq!(editor.status_message.as_deref(), Some("Selection: 9 characters, 2 lines, 10 bytes"));
+    }
+
+    #[test]
+    fn search_shows_the_match_count_while_typing_and_on_a_match() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("one two one\ndone");
+        let last_row = |editor: &Editor| visible(editor.draw_frame(120, 3).unwrap().0.last().unwrap());
+        editor.execute(EditorCommand::Search);
+        assert_eq!(last_row(&editor).trim_end(), "Search:");
+        for (c, count) in [('o', "4 matches"), ('n', "3 matches"), ('e', "3 matches"), (' ', "1 match")] {
+            editor.handle_key(KeyCode::Char(c), KeyModifiers::NONE);
+            assert!(last_row(&editor).ends_with(count), "{:?}", last_row(&editor));
+        }
+        editor.handle_key(KeyCode::Backspace, KeyModifiers::NONE);
+
+        // Submitting jumps to a match, and the status line says which
+        editor.handle_key(KeyCode::Enter, KeyModifiers::NONE);
+        assert_eq!(editor.cursor, 8);
+        assert!(last_row(&editor).contains("| Match 2 of 3 |"), "{}", last_row(&editor));
+        editor.execute(EditorCommand::SearchNext);
+        assert!(last_row(&editor).contains("| Match 3 of 3 |"), "{}", last_row(&editor));
+        editor.execute(EditorCommand::MoveLeft);
+        assert!(!last_row(&editor).contains("Match"));
+
+        // An edit is counted in, not left out by the counts kept from before
+        editor.cursor = 0;
+        for c in "one ".chars() {
+            editor.handle_key(KeyCode::Char(c), KeyModifiers::NONE);
+        }
+        assert!(last_row(&editor).contains("| Match 2 of 4 |"), "{}", last_row(&editor));
+    }
+
+    #[test]
+    fn selected_line_breaks_show_past_the_line_end() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("one\ntwo\nthree");
+        editor.baseline = editor.rope.clone();
+        // Selected text as drawn, with the reverse-video stretches in []
+        let selected = |row: &[u8]| {
+            let marked = String::from_utf8_lossy(row).replace("\x1b[7m", "[").replace("\x1b[27m", "]");
+            visible(marked.as_bytes()).replace("][", "")
+        };
+        for (anchor, cursor) in [(1, 10), (10, 1)] {
+            editor.anchor = Some(anchor);
+            editor.cursor = cursor;
+            let (rows, _) = editor.draw_frame(40, 4).unwrap();
+            let rows: Vec<String> = rows[..3].iter().map(|row| selected(row)).collect();
+            assert_eq!(rows, ["  o[ne ]", "  [two ]", "  [th]ree"]);
+        }
+
+        // Ending just past a line break still shows it
+        editor.anchor = Some(0);
+        editor.cursor = 4;
+        let (rows, _) = editor.draw_frame(40, 4).unwrap();
+        assert_eq!(selected(&rows[0]), "  [one ]");
+        assert_eq!(selected(&rows[1]), "  two");
+    }
+
+    #[test]
+    fn enter_at_the_end_of_the_file_shows_a_new_empty_line() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("one\ntwo");
+        editor.baseline = editor.rope.clone();
+        editor.view_rows = 2;
+        editor.view_cols = 30;
+        editor.cursor = editor.rope.len();
+        editor.execute(EditorCommand::InsertNewline);
+        assert_eq!((editor.content().as_str(), editor.cursor_line_col()), ("one\ntwo\n", (2, 0)));
+        editor.scroll_to_cursor();
+        assert_eq!(editor.scroll_offset, 1);
+
+        // The last row is the new line, with the cursor on it, and typing
+        // goes there
+        let (rows, cursor) = editor.draw_frame(40, 3).unwrap();
+        assert_eq!(visible(&rows[0]).trim_end(), "  two");
+        assert_eq!(visible(&rows[1]).trim_end(), "+");
+        assert_eq!(cursor, (GUTTER_WIDTH, 1));
+        editor.handle_key(KeyCode::Char('x'), KeyModifiers::NONE);
+        editor.scroll_to_cursor();
+        let (rows, _) = editor.draw_frame(40, 3).unwrap();
+        assert_eq!(visible(&rows[1]).trim_end(), "+ x");
+        assert_eq!(editor.content(), "one\ntwo\nx");
+    }
+
+    #[test]
+    fn gutter_markers_follow_edits_and_saves() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("a\nb\nc");
+        editor.baseline = editor.rope.clone();
+        let gutter = |editor: &Editor| {
+            let (rows, _) = editor.draw_frame(40, 4).unwrap();
+            rows[..3].iter().map(|row| visible(row).chars().next().unwrap()).collect::<String>()
+        };
+        assert_eq!(gutter(&editor), "   ");
+        editor.cursor = 2;
+        editor.handle_key(KeyCode::Char('x'), KeyModifiers::NONE);
+        assert_eq!(gutter(&editor), " ~ ");
+        editor.handle_key(KeyCode::Backspace, KeyModifiers::NONE);
+        assert_eq!(gutter(&editor), "   ");
+        editor.handle_key(KeyCode::Char('y'), KeyModifiers::NONE);
+        editor.baseline = editor.rope.clone();
+        assert_eq!(gutter(&editor), "   ");
+    }
+
+    #[test]
+    fn scrolloff_keeps_context_near_both_ends() {
+        // 100 lines on a 10-row view
+        assert_eq!(scroll_for(0, 0, 100, 10, 3), 0);
+        assert_eq!(scroll_for(0, 6, 100, 10, 3), 0);
+        assert_eq!(scroll_for(0, 7, 100, 10, 3), 1);
+        assert_eq!(scroll_for(50, 52, 100, 10, 3), 49);
+        assert_eq!(scroll_for(0, 99, 100, 10, 3), 90);
+        assert_eq!(scroll_for(90, 96, 100, 10, 3), 90);
+        assert_eq!(scroll_for(90, 2, 100, 10, 3), 0);
+        // The default of 0 only scrolls once the cursor leaves the screen
+        assert_eq!(scroll_for(0, 9, 100, 10, 0), 0);
+        assert_eq!(scroll_for(0, 10, 100, 10, 0), 1);
+    }
+
+    #[test]
+    fn cursor_lands_after_expanded_tabs() {
+        let mut editor = Editor::new(Config::default());
+        editor.rope = Rope::from_string("x\na\tbc\td");
+        let cursor_x = |editor: &Editor| editor.draw_frame(80, 5).unwrap().1 .0 - GUTTER_WIDTH;
+
+        editor.cursor = 3; // On the first tab
+        assert_eq!(cursor_x(&editor), 1);
+        editor.cursor = 4; // On `b`, at the tab stop
+        assert_eq!(cursor_x(&editor), 4);
+        editor.cursor = 7; // On `d`, after the second tab
+        assert_eq!(cursor_x(&editor), 8);
+
+        editor.indent.tab_width = 8;
+        assert_eq!(cursor_x(&editor), 16);
+    }
+
+    #[test]
+    fn long_lines_scroll_sideways_and_show_a_marker() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content(&format!("{}\nshort", "x".repeat(1000)));
+        editor.view_rows = 5;
+        editor.view_cols = 20;
+        editor.cursor = 500;
+        editor.scroll_to_cursor();
+        assert_eq!(editor.col_offset, 482);
+
+        let (rows, cursor_pos) = editor.draw_frame(22, 6).unwrap();
+        // Past the diff gutter
+        assert_eq!(visible(&rows[0])[2..], format!("{}>", "x".repeat(19)));
+        assert!(visible(&rows[1])[2..].is_empty());
+        assert_eq!(cursor_pos, (20, 0));
+
+        let (rows, _) = editor.draw_frame(200, 6).unwrap();
+        assert!(visible(rows.last().unwrap()).contains("Len 1000"));
+    }
+
+    #[test]
+    fn search_matches_stay_highlighted_until_cleared() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("cat concat\ncatalog");
+        editor.search = Some("cat".to_string());
+        let highlights = |editor: &Editor| {
+            let (rows, _) = editor.draw_frame(40, 4).unwrap();
+            rows.iter().map(|row| String::from_utf8_lossy(row).matches("\x1b[48;5;3m").count()).sum::<usize>()
+        };
+        assert_eq!(highlights(&editor), 0);
+        editor.highlight_matches = true;
+        assert_eq!(highlights(&editor), 3);
+        editor.set_content("cat dog");
+        assert_eq!(highlights(&editor), 1);
+    }
+
+    #[test]
+    fn minimap_shades_by_line_length_and_marks_the_view() {
+        let long = "x".repeat(100);
+        let lines = ["", "short", &long, "", "", ""];
+        let glyphs = |map: Vec<(char, bool)>| map.iter().map(|&(c, _)| c).collect::<String>();
+
+        // Fewer lines than rows: one line per row
+        let len = |line: usize| lines[line].len();
+        let map = minimap(lines.len(), len, 8, 1..3);
+        assert_eq!(glyphs(map.clone()), " ░█     ");
+        assert_eq!(map.iter().filter(|&&(_, in_view)| in_view).count(), 2);
+
+        // More lines than rows: each row stands for two, sampled
+        let map = minimap(lines.len(), len, 3, 4..6);
+        assert_eq!(glyphs(map.clone()), "░▓ ");
+        assert_eq!(map.iter().map(|&(_, in_view)| in_view).collect::<Vec<_>>(), [false, false, true]);
+    }
+
+    #[test]
+    fn text_past_the_length_limit_is_flagged() {
+        let mut config = Config::default();
+        config.line_length_limit = Some(6);
+        let mut editor = Editor::new(config);
+        editor.set_content("short\n\tlonger line\nexactly");
+        let (rows, _) = editor.draw_frame(40, 5).unwrap();
+        let flagged = |row: &[u8]| {
+            let row = String::from_utf8_lossy(row);
+            let start = row.find("\x1b[48;5;1m")?;
+            Some(visible(row[start..].as_bytes()))
+        };
+        assert_eq!(flagged(&rows[0]), None);
+        // The tab takes four columns, so the limit falls inside "longer"
+        assert_eq!(flagged(&rows[1]).as_deref(), Some("nger line"));
+        assert_eq!(flagged(&rows[2]).as_deref(), Some("y"));
+    }
+
+    #[test]
+    fn indent_guides_mark_each_level_of_indentation() {
+        let mut config = Config::default();
+        config.indent_guides = true;
+        let mut editor = Editor::new(config);
+        editor.set_content("fn f() {\n        x\n\t  \ty\n  z");
+        editor.baseline = editor.rope.clone(); // No change markers
+        let (rows, _) = editor.draw_frame(40, 6).unwrap();
+        assert_eq!(visible(&rows[0]), "  fn f() {");
+        assert_eq!(visible(&rows[1]), "  │   │   x");
+        // A tab stop inside the second tab still gets its guide
+        assert_eq!(visible(&rows[2]), "  │   │   y");
+        assert_eq!(visible(&rows[3]), "  │ z");
+
+        // Scrolled sideways, only the guides still on screen are drawn
+        editor.col_offset = 2;
+        let (rows, _) = editor.draw_frame(40, 6).unwrap();
+        assert_eq!(visible(&rows[1]), "    │   x");
+    }
+
+    #[test]
+    fn keystroke_display_shows_recent_keys_in_the_corner() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("one\ntwo\nthree\n");
+        editor.execute(EditorCommand::ToggleKeyLog);
+        editor.handle_key(KeyCode::Down, KeyModifiers::NONE);
+        editor.handle_key(KeyCode::Down, KeyModifiers::NONE);
+        editor.handle_key(KeyCode::Char('x'), KeyModifiers::NONE);
+        // A key a prompt takes isn't reported as the command it's bound to
+        editor.execute(EditorCommand::Search);
+        editor.handle_key(KeyCode::Char('s'), KeyModifiers::CONTROL);
+        editor.handle_key(KeyCode::Esc, KeyModifiers::NONE);
+
+        let (mut rows, _) = editor.draw_frame(60, 6).unwrap();
+        editor.draw_key_log(&mut rows, 60).unwrap();
+        let rows: Vec<String> = rows.iter().map(|row| visible(row).trim_end().to_string()).collect();
+        assert!(rows[1].ends_with(" Down ×2  Move cursor down"), "{:?}", rows);
+        assert!(rows[2].ends_with(" x"), "{:?}", rows);
+        assert!(rows[3].ends_with(" Ctrl+S"), "{:?}", rows);
+        assert!(rows[4].ends_with(" Esc  Cancel"), "{:?}", rows);
+        assert!(!rows[5].contains("Esc"));
+
+        assert!(editor.next_timer().is_some());
+        editor.execute(EditorCommand::ToggleKeyLog);
+        assert!(editor.key_log.is_none());
+        assert_eq!(editor.next_timer(), None);
+    }
+
+    #[test]
+    fn debug_hud_shows_rope_and_history_stats() {
+        let mut editor = Editor::new(Config::default());
+        editor.set_content("hello\nworld");
+        editor.cursor = 3;
+        editor.render_time = std::time::Duration::from_micros(1500);
+        let mut rows = vec![b"text".to_vec(); 6];
+        editor.draw_hud(&mut rows, 40).unwrap();
+        let row = |n: usize| visible(&rows[n]);
+        assert_eq!(row(0), "text render 1.50ms    ");
+        assert_eq!(row(1), "text depth 0 leaves 1 ");
+        assert_eq!(row(2), "text length 11        ");
+        assert_eq!(row(4), "text cursor 3         ");
+        assert_eq!(row(5), "text");
+
+        // Too narrow a terminal just goes without
+        let mut rows = vec![Vec::new(); 6];
+        editor.draw_hud(&mut rows, 10).unwrap();
+        editor.config.minimap = true;
+        editor.draw_hud(&mut rows, 1).unwrap();
+        assert!(rows.iter().all(|row| row.is_empty()));
+    }
+
+    #[test]
+    fn wide_characters_and_combining_marks_keep_columns_in_step() {
+        let mut config = Config::default();
+        config.cursor_shape = None;
+        let mut editor = Editor::new(config);
+        editor.set_content("한글 e\u{301}x");
+        editor.baseline = editor.rope.clone();
+        editor.cursor = editor.content().len() - 1; // On the x
+        let (rows, cursor_pos) = editor.draw_frame(40, 3).unwrap();
+        assert_eq!(visible(&rows[0]), "  한글 e\u{301}x");
+        assert_eq!(cursor_pos.0, GUTTER_WIDTH + 6);
+
+        // Left steps over the accent to the e, not onto the accent
+        editor.execute(EditorCommand::MoveLeft);
+        assert_eq!(editor.cursor, 7);
+        editor.execute(EditorCommand::MoveRight);
+        assert_eq!(editor.cursor, 10);
+
+        // Scrolled one column in, the half of 한 still showing is blank
+        editor.col_offset = 1;
+        let (rows, _) = editor.draw_frame(40, 3).unwrap();
+        assert_eq!(visible(&rows[0]), "   글 e\u{301}x");
+    }
+
+    #[test]
+    fn centered_layout_pads_the_text_column() {
+        let mut config = Config::default();
+        config.centered = true;
+        config.center_width = 20;
+        let mut editor = Editor::new(config);
+        editor.set_content("hello");
+        editor.cursor = 5;
+
+        // 60 columns for text, 20 used: 20 columns of padding before the gutter
+        let (rows, cursor_pos) = editor.draw_frame(62, 4).unwrap();
+        assert_eq!(visible(&rows[0]), format!("{}~ hello", " ".repeat(20)));
+        assert_eq!(cursor_pos.0, 20 + GUTTER_WIDTH + 5);
+
+        // Narrower than the content width, nothing is padded
+        let (rows, _) = editor.draw_frame(12, 4).unwrap();
+        assert!(visible(&rows[0]).starts_with("~ hello"));
+    }
+
+    #[test]
+    fn line_numbers_count_from_the_cursor_in_relative_modes() {
+        let mut config = Config::default();
+        config.line_numbers = LineNumbers::Relative;
+        let mut editor = Editor::new(config);
+        editor.set_content(&"x\n".repeat(10));
+        editor.cursor = editor.rope.line_col_to_index(2, 0);
+
+        // 11 lines: two digits and a space, whichever mode and wherever the cursor is
+        let (rows, cursor_pos) = editor.draw_frame(40, 5).unwrap();
+        let numbers: Vec<String> = rows[..4].iter().map(|row| visible(row)[2..5].to_string()).collect();
+        assert_eq!(numbers, [" 2 ", " 1 ", " 0 ", " 1 "]);
+        assert_eq!(cursor_pos.0, GUTTER_WIDTH + 3);
+
+        editor.config.line_numbers = LineNumbers::Hybrid;
+        let (rows, _) = editor.draw_frame(40, 5).unwrap();
+        assert_eq!(&visible(&rows[2])[2..], " 3 x");
+        assert_eq!(&visible(&rows[3])[2..], " 1 x");
+
+        editor.config.line_numbers = LineNumbers::Absolute;
+        let (rows, _) = editor.draw_frame(40, 5).unwrap();
+        assert_eq!(&visible(&rows[0])[2..], " 1 x");
+    }
+
+    #[test]
+    fn one_line_can_wrap_over_several_rows() {
+        let mut editor = Editor::new(Config::default());
+        let long: String = ('a'..='z').collect();
+        editor.set_content(&format!("{}\nnext", long));
+        editor.view_rows = 5;
+        editor.view_cols = 10;
+        editor.cursor = 23;
+        editor.execute(EditorCommand::WrapLine);
+        editor.scroll_to_cursor();
+        assert_eq!(editor.col_offset, 0);
+
+        let (rows, cursor_pos) = editor.draw_frame(12, 6).unwrap();
+        let text: Vec<String> = rows[..4].iter().map(|row| visible(row)[2..].to_string()).collect();
+        assert_eq!(text, ["abcdefghij", "klmnopqrst", "uvwxyz", "next"]);
+        assert!(rows[4].is_empty());
+        assert_eq!(cursor_pos, (GUTTER_WIDTH + 3, 2));
+
+        // Too tall for the screen: the rows before the cursor's go first
+        editor.view_rows = 2;
+        editor.scroll_to_cursor();
+        assert_eq!(editor.col_offset, 10);
+        let (rows, cursor_pos) = editor.draw_frame(12, 3).unwrap();
+        assert_eq!(visible(&rows[0])[2..], *"klmnopqrst");
+        assert_eq!(cursor_pos, (GUTTER_WIDTH + 3, 1));
+
+        // Moving off the line puts it back to scrolling sideways
+        editor.cursor = long.len() + 2;
+        editor.scroll_to_cursor();
+        assert_eq!(editor.wrapped_line, None);
+    }
+}